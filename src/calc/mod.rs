@@ -0,0 +1,416 @@
+//! A calculator: `/calc 2^100`, `/calc sqrt(-4)`, `/calc 9.81 m/s^2 * 2 kg` or `/calc 3 ft to m`.
+//!
+//! Expressions are parsed by [crate::expr] and worked out exactly, in complex rationals, until
+//! something irrational comes in. Quantities carry their units, in SI base units, so they can be
+//! multiplied together and converted into other units. The answer is rendered as an equation, like
+//! the rest of the bot's math.
+
+mod number;
+mod units;
+
+use std::cmp::Ordering;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use num::traits::ToPrimitive;
+use poise::serenity_prelude::AttachmentType;
+
+use crate::expr::{self, BinaryOp, Expr};
+use crate::math_markup::{render_settings, typst_render};
+use crate::utils::{Context, Error};
+use number::Number;
+use units::Dimension;
+
+/// How long working out an answer can take. It's checked before each step, and the size limit on
+/// exact numbers keeps each step quick.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Names that are numbers rather than units.
+const CONSTANTS: [&str; 9] = ["pi", "π", "tau", "τ", "e", "phi", "φ", "i", "j"];
+
+/// A number with units, in SI base units.
+#[derive(Debug, Clone, PartialEq)]
+struct Quantity {
+    number: Number,
+    dimension: Dimension,
+}
+
+impl Quantity {
+    /// A number without units.
+    fn plain(number: Number) -> Self {
+        Self {
+            number,
+            dimension: Dimension::default(),
+        }
+    }
+
+    /// The number, checking it doesn't have units, which `what` wouldn't know what to do with.
+    fn unitless(&self, what: &str) -> Result<&Number> {
+        if !self.dimension.is_none() {
+            bail!(
+                "{} needs a plain number, not {}.",
+                what,
+                self.dimension.describe()
+            );
+        }
+        Ok(&self.number)
+    }
+
+    /// Checks that two quantities have the same units, so they can be added or compared.
+    fn same_units(&self, other: &Self, doing: &str) -> Result<()> {
+        if self.dimension != other.dimension {
+            bail!(
+                "You can't {} {} and {}.",
+                doing,
+                self.dimension.describe(),
+                other.dimension.describe()
+            );
+        }
+        Ok(())
+    }
+
+    fn pow(&self, exponent: &Self) -> Result<Self> {
+        let exponent = exponent.unitless("An exponent")?;
+        if self.dimension.is_none() {
+            return Ok(Self::plain(self.number.pow(exponent)?));
+        }
+        let dimension = exponent
+            .exact_real()
+            .and_then(|r| Some((r.numer().to_i32()?, r.denom().to_i32()?)))
+            .and_then(|(numer, denom)| self.dimension.pow(numer, denom));
+        let Some(dimension) = dimension else {
+            bail!(
+                "{} can't be raised to that power: units can only end up with whole powers.",
+                self.dimension.describe()
+            );
+        };
+        let dimension = dimension.limited()?;
+        Ok(Self {
+            number: self.number.pow(exponent)?,
+            dimension,
+        })
+    }
+}
+
+/// What a name means: a constant, like `pi`, or a unit, like `km`.
+fn name_value(name: &str) -> Result<Quantity> {
+    let number = match name {
+        "pi" | "π" => Number::approx(std::f64::consts::PI),
+        "tau" | "τ" => Number::approx(std::f64::consts::TAU),
+        "e" => Number::approx(std::f64::consts::E),
+        "phi" | "φ" => Number::approx((1.0 + 5f64.sqrt()) / 2.0),
+        "i" | "j" => Number::i(),
+        _ => {
+            let (number, dimension) =
+                units::lookup(name).ok_or_else(|| expr::unknown_name(name))?;
+            return Ok(Quantity { number, dimension });
+        }
+    };
+    Ok(Quantity::plain(number))
+}
+
+/// Applies a function to its arguments.
+fn call(name: &str, args: &[Quantity]) -> Result<Quantity> {
+    if matches!(name, "min" | "max") {
+        let Some((first, rest)) = args.split_first() else {
+            bail!("{} needs something to pick from.", name);
+        };
+        let wanted = if name == "min" {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        };
+        let mut best = first;
+        for arg in args {
+            best.same_units(arg, "compare")?;
+            if arg.number.to_real().is_none() {
+                bail!("{} only works on real numbers.", name);
+            }
+        }
+        for arg in rest {
+            if arg.number.compare(&best.number) == Some(wanted) {
+                best = arg;
+            }
+        }
+        return Ok(best.clone());
+    }
+
+    let [x] = args else {
+        bail!("{} takes one argument.", name);
+    };
+    let keeping_units = |number| Quantity {
+        number,
+        dimension: x.dimension,
+    };
+    let root = |n: i32| {
+        x.dimension.pow(1, n).ok_or_else(|| {
+            anyhow!(
+                "{} doesn't have that root: units can only end up with whole powers.",
+                x.dimension.describe()
+            )
+        })
+    };
+    Ok(match name {
+        // sqrt(-4) is exactly 2i, and cbrt(-8) is -2 rather than a complex root
+        "sqrt" => Quantity {
+            number: x.number.sqrt()?,
+            dimension: root(2)?,
+        },
+        "cbrt" => Quantity {
+            number: x.number.cbrt()?,
+            dimension: root(3)?,
+        },
+        "abs" => keeping_units(x.number.abs()?),
+        "re" => keeping_units(x.number.re()),
+        "im" => keeping_units(x.number.im()),
+        "conj" => keeping_units(x.number.conj()),
+        "sign" => Quantity::plain(x.number.sign()?),
+        "arg" => Quantity::plain(x.number.function(name)?),
+        "floor" | "ceil" | "round" => Quantity::plain(x.unitless(name)?.round(name)?),
+        _ => Quantity::plain(x.unitless(name)?.function(name)?),
+    })
+}
+
+/// Works out an expression, giving up if it's still going at the deadline.
+fn eval(expr: &Expr, deadline: Instant) -> Result<Quantity> {
+    if Instant::now() > deadline {
+        bail!("That took too long to work out.");
+    }
+    Ok(match expr {
+        Expr::Number(n) => {
+            Quantity::plain(Number::parse(n).ok_or_else(|| anyhow!("{} isn't a number.", n))?)
+        }
+        Expr::Name(name) => name_value(name)?,
+        Expr::Neg(e) => {
+            let q = eval(e, deadline)?;
+            Quantity {
+                number: q.number.neg(),
+                ..q
+            }
+        }
+        Expr::Factorial(e) => {
+            Quantity::plain(eval(e, deadline)?.unitless("A factorial")?.factorial()?)
+        }
+        Expr::Binary(op, a, b) => {
+            let (a, b) = (eval(a, deadline)?, eval(b, deadline)?);
+            match op {
+                BinaryOp::Add => {
+                    a.same_units(&b, "add")?;
+                    Quantity {
+                        number: a.number.add(&b.number),
+                        ..a
+                    }
+                }
+                BinaryOp::Sub => {
+                    a.same_units(&b, "subtract")?;
+                    Quantity {
+                        number: a.number.sub(&b.number),
+                        ..a
+                    }
+                }
+                BinaryOp::Mul => Quantity {
+                    number: a.number.mul(&b.number),
+                    dimension: a.dimension.mul(&b.dimension)?,
+                },
+                BinaryOp::Div => Quantity {
+                    number: a.number.div(&b.number)?,
+                    dimension: a.dimension.div(&b.dimension)?,
+                },
+                BinaryOp::Pow => a.pow(&b)?,
+            }
+        }
+        Expr::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, deadline))
+                .collect::<Result<Vec<_>>>()?;
+            call(name, &args)?
+        }
+        Expr::Convert(..) => {
+            bail!("Only the whole expression can be converted, like 1 ft + 2 in to cm.")
+        }
+    })
+}
+
+/// Converts a temperature from one scale to another, like `20 °C to °F`, which isn't only scaling
+/// like other conversions are. `None` if it isn't a conversion like that.
+fn convert_temperature(from: &Expr, to: &Expr, deadline: Instant) -> Result<Option<Number>> {
+    let Expr::Name(to) = to else {
+        return Ok(None);
+    };
+    let (negative, from) = match from {
+        Expr::Neg(from) => (true, &**from),
+        _ => (false, from),
+    };
+    let Expr::Binary(BinaryOp::Mul, value, unit) = from else {
+        return Ok(None);
+    };
+    let Expr::Name(from) = &**unit else {
+        return Ok(None);
+    };
+    let value = eval(value, deadline)?;
+    if !value.dimension.is_none() {
+        return Ok(None);
+    }
+    let value = if negative {
+        value.number.neg()
+    } else {
+        value.number
+    };
+    Ok(units::convert_temperature(&value, from, to))
+}
+
+/// Works out an expression, and writes it and its answer as a Typst equation, without the `$`s.
+/// Gives up if it takes longer than [TIMEOUT].
+fn calculate(input: &str) -> Result<String> {
+    let deadline = Instant::now() + TIMEOUT;
+    let expr = expr::parse(input)?;
+    let upright = |name: &str| !CONSTANTS.contains(&name) && units::is_unit(name);
+    let (shown, value, unit) = match &expr {
+        Expr::Convert(from, to) => {
+            let unit = to.to_typst_with(&upright);
+            let value = match convert_temperature(from, to, deadline)? {
+                Some(value) => value,
+                None => {
+                    let (from, to) = (eval(from, deadline)?, eval(to, deadline)?);
+                    if from.dimension != to.dimension {
+                        bail!(
+                            "You can't convert {} to {}.",
+                            from.dimension.describe(),
+                            to.dimension.describe()
+                        );
+                    }
+                    from.number.div(&to.number)?
+                }
+            };
+            (&**from, value, unit)
+        }
+        _ => {
+            let q = eval(&expr, deadline)?;
+            (&expr, q.number, q.dimension.to_typst())
+        }
+    };
+    if !value.is_finite() {
+        bail!("The answer is too big, or undefined.");
+    }
+    Ok(format!(
+        "{} {}",
+        shown.to_typst_with(&upright),
+        value.typst_value(&unit)
+    ))
+}
+
+/// Works out math, with complex numbers and units, like `2^100`, `sqrt(-4)`, `9.81 m/s^2 * 2 kg`
+/// or `3 ft to m`.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    track_edits,
+    invoke_on_edit,
+    reuse_response,
+    track_deletion
+)]
+pub(crate) async fn calc(
+    ctx: Context<'_>,
+    #[description = "What to work out, like 1/3 + 1/6, or a conversion like 3 ft to m."]
+    #[rest]
+    expression: String,
+) -> Result<(), Error> {
+    let input = expression.clone();
+    let equation = tokio::task::spawn_blocking(move || calculate(&input)).await??;
+    let settings =
+        render_settings(ctx.author().id, ctx.guild_id(), ctx.data().storage.as_ref()).await?;
+    let im = typst_render(&format!("$ {} $", equation), &settings).await?;
+    ctx.send(|m| {
+        m.content(format!("`{}`", &expression))
+            .attachment(AttachmentType::Bytes {
+                data: im.into(),
+                filename: "Answer.png".into(),
+            })
+    })
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calculate() {
+        let cases = [
+            ("1/3 + 1/6", "(1)/(3) + (1)/(6) = 0.5"),
+            ("2^100", "2^(100) = 1267650600228229401496703205376"),
+            ("sqrt(-4)", "sqrt(-4) = 2 i"),
+            ("(1 + 2i)(3 - i)", "(1 + 2 i) (3 - i) = 5 + 5 i"),
+            ("sqrt(2)", "sqrt(2) approx 1.414213562"),
+            ("3 ft to m", "3 thin \"ft\" = 0.9144 thin \"m\""),
+            ("90 min to h", "90 thin \"min\" = 1.5 thin \"h\""),
+            (
+                "9.81 m/s^2 * 2 kg",
+                "(9.81 thin \"m\")/(\"s\"^(2)) dot 2 thin \"kg\" = 19.62 thin \"N\"",
+            ),
+            (
+                "60 mph to km/h",
+                "60 thin \"mph\" = 96.56064 thin (\"km\")/(\"h\")",
+            ),
+            ("sqrt(4 m^2)", "sqrt(4 thin \"m\"^(2)) = 2 thin \"m\""),
+            (
+                "2 m * 3 s",
+                "2 thin \"m\" dot 3 thin \"s\" = 6 thin \"m\" thin \"s\"",
+            ),
+            (
+                "1 atm to psi",
+                "1 thin \"atm\" approx 14.69594878 thin \"psi\"",
+            ),
+            ("100 °C to °F", "100 thin \"°C\" = 212 thin \"°F\""),
+            ("-40 degF to degC", "-40 thin \"degF\" = -40 thin \"degC\""),
+            ("0 °C to K", "0 thin \"°C\" = 273.15 thin \"K\""),
+            ("sin(30 deg)", "sin(30 thin \"deg\") approx 0.5"),
+            ("1 kWh to MJ", "1 thin \"kWh\" = 3.6 thin \"MJ\""),
+            ("5!", "5! = 120"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(calculate(input).unwrap(), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_calculate_errors() {
+        let cases = [
+            ("1 m + 1 s", "You can't add m and s."),
+            ("3 ft to kg", "You can't convert m to kg."),
+            ("sin(2 m)", "sin needs a plain number, not m."),
+            (
+                "sqrt(2 m)",
+                "m doesn't have that root: units can only end up with whole powers.",
+            ),
+            ("2^(1 m)", "An exponent needs a plain number, not m."),
+            ("1/0", "You can't divide by zero."),
+            ("2 frobs", "I don't know what frobs is."),
+            ("sin 2", "sin needs parentheses, like sin(x)."),
+            ("ln(0)", "The logarithm of zero is undefined."),
+            (
+                "1/(m^(-2147483648))",
+                "Units can't have powers beyond ±100.",
+            ),
+            ("m^2147483647 * m", "Units can't have powers beyond ±100."),
+            ("m^100 * m", "Units can't have powers beyond ±100."),
+            ("1/m^100/m", "Units can't have powers beyond ±100."),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                calculate(input).unwrap_err().to_string(),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+
+        // exact answers that would get huge are worked out approximately
+        assert_eq!(
+            calculate("2^50000 * 2^50000").unwrap_err().to_string(),
+            "The answer is too big, or undefined."
+        );
+    }
+}
@@ -0,0 +1,655 @@
+//! The numbers calculations are done in. They're kept exact, as complex numbers with rational parts,
+//! for as long as they can be, and become floating point once something irrational, like `sqrt(2)`
+//! or `pi`, comes in.
+
+use std::cmp::Ordering;
+
+use anyhow::{bail, Result};
+use num::complex::{Complex, Complex64};
+use num::rational::BigRational;
+use num::traits::{One, Signed, ToPrimitive, Zero};
+use num::BigInt;
+
+/// How big exact numbers can get, in bits, before they're worked out approximately instead.
+const MAX_BITS: u64 = 100_000;
+
+/// The largest factorial worked out.
+const MAX_FACTORIAL: u32 = 1000;
+
+/// How many significant digits approximate answers get.
+const SIGNIFICANT: usize = 10;
+
+/// The most digits an exact answer is written out with.
+const MAX_DIGITS: usize = 40;
+
+/// The most digits the top or bottom of a fraction is written out with.
+const MAX_FRACTION_DIGITS: usize = 8;
+
+/// A function, for real numbers and for complex ones.
+type Function = (fn(f64) -> f64, fn(Complex64) -> Complex64);
+
+/// A number, exact or approximate.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Number {
+    Exact(Complex<BigRational>),
+    Approx(Complex64),
+}
+
+/// The rational number `n`.
+fn rational(n: i64) -> BigRational {
+    BigRational::from_integer(n.into())
+}
+
+/// `10^exponent`, exactly.
+fn power_of_ten(exponent: i64) -> BigRational {
+    let power = BigRational::from_integer(BigInt::from(10).pow(exponent.unsigned_abs() as u32));
+    if exponent < 0 {
+        power.recip()
+    } else {
+        power
+    }
+}
+
+/// A rational number as a float, which is infinite if it's too big for one.
+fn to_f64(r: &BigRational) -> f64 {
+    r.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Roughly how many bits a complex rational takes up.
+fn bits(z: &Complex<BigRational>) -> u64 {
+    [z.re.numer(), z.re.denom(), z.im.numer(), z.im.denom()]
+        .iter()
+        .map(|n| n.bits())
+        .max()
+        .unwrap_or(0)
+}
+
+/// The `n`th root of a rational number that isn't negative, if it's rational too.
+fn exact_root(r: &BigRational, n: u32) -> Option<BigRational> {
+    let root = |i: &BigInt| {
+        let root = i.nth_root(n);
+        (root.pow(n) == *i).then_some(root)
+    };
+    Some(BigRational::new(root(r.numer())?, root(r.denom())?))
+}
+
+/// Rounds away tiny parts of a complex number left over from rounding errors, like the imaginary
+/// part of `(-1)^(1/2) * (-1)^(1/2)`.
+fn tidy(z: Complex64) -> Complex64 {
+    let threshold = z.norm() * 1e-14;
+    let clean = |x: f64| if x.abs() < threshold { 0.0 } else { x };
+    Complex64::new(clean(z.re), clean(z.im))
+}
+
+impl Number {
+    pub(crate) fn real(r: BigRational) -> Self {
+        Self::Exact(Complex::new(r, BigRational::zero()))
+    }
+
+    pub(crate) fn integer(n: i64) -> Self {
+        Self::real(rational(n))
+    }
+
+    pub(crate) fn approx(x: f64) -> Self {
+        Self::Approx(Complex64::new(x, 0.0))
+    }
+
+    /// The imaginary unit.
+    pub(crate) fn i() -> Self {
+        Self::Exact(Complex::i())
+    }
+
+    /// Reads a decimal, like `0.25` or `6.02e23`, exactly.
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()),
+            None => (text, Some(0)),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits: BigInt = format!("{}{}", whole, fraction).parse().ok()?;
+        let exponent = exponent
+            .and_then(|e| e.checked_sub(fraction.len() as i64))
+            .filter(|e| e.unsigned_abs() <= MAX_BITS / 4);
+        match exponent {
+            Some(exponent) => Some(Self::real(
+                BigRational::from_integer(digits) * power_of_ten(exponent),
+            )),
+            // far too big or small to be worth keeping exact
+            None => text.parse().ok().map(Self::approx),
+        }
+    }
+
+    /// A fraction, written as two decimals like `4.4482216152605/0.00064516`.
+    pub(crate) fn parse_fraction(text: &str) -> Option<Self> {
+        match text.split_once('/') {
+            Some((top, bottom)) => Self::parse(top)?.div(&Self::parse(bottom)?).ok(),
+            None => Self::parse(text),
+        }
+    }
+
+    pub(crate) fn to_complex64(&self) -> Complex64 {
+        match self {
+            Self::Exact(z) => Complex64::new(to_f64(&z.re), to_f64(&z.im)),
+            Self::Approx(z) => *z,
+        }
+    }
+
+    /// The number, if it's exact and real.
+    pub(crate) fn exact_real(&self) -> Option<&BigRational> {
+        match self {
+            Self::Exact(z) if z.im.is_zero() => Some(&z.re),
+            _ => None,
+        }
+    }
+
+    /// The number as a float, if it's real.
+    pub(crate) fn to_real(&self) -> Option<f64> {
+        let z = self.to_complex64();
+        (z.im == 0.0).then_some(z.re)
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        match self {
+            Self::Exact(z) => z.is_zero(),
+            Self::Approx(z) => z.is_zero(),
+        }
+    }
+
+    /// Whether the number is neither infinite nor undefined.
+    pub(crate) fn is_finite(&self) -> bool {
+        match self {
+            Self::Exact(_) => true,
+            Self::Approx(z) => z.is_finite(),
+        }
+    }
+
+    /// Combines two numbers exactly if they're both exact, or approximately if they aren't or the
+    /// answer is too big to keep exact.
+    fn combine(
+        &self,
+        other: &Self,
+        exact: impl Fn(&Complex<BigRational>, &Complex<BigRational>) -> Complex<BigRational>,
+        approx: impl Fn(Complex64, Complex64) -> Complex64,
+    ) -> Self {
+        match (self, other) {
+            (Self::Exact(a), Self::Exact(b)) => {
+                let z = Self::Exact(exact(a, b));
+                match &z {
+                    Self::Exact(exact) if bits(exact) > MAX_BITS => Self::Approx(z.to_complex64()),
+                    _ => z,
+                }
+            }
+            _ => Self::Approx(approx(self.to_complex64(), other.to_complex64())),
+        }
+    }
+
+    pub(crate) fn add(&self, other: &Self) -> Self {
+        self.combine(other, |a, b| a + b, |a, b| a + b)
+    }
+
+    pub(crate) fn sub(&self, other: &Self) -> Self {
+        self.combine(other, |a, b| a - b, |a, b| a - b)
+    }
+
+    pub(crate) fn mul(&self, other: &Self) -> Self {
+        self.combine(other, |a, b| a * b, |a, b| a * b)
+    }
+
+    pub(crate) fn div(&self, other: &Self) -> Result<Self> {
+        if other.is_zero() {
+            bail!("You can't divide by zero.");
+        }
+        Ok(self.combine(
+            other,
+            |a, b| a / b,
+            |a, b| {
+                if a.im == 0.0 && b.im == 0.0 {
+                    Complex64::new(a.re / b.re, 0.0)
+                } else {
+                    a.fdiv(b)
+                }
+            },
+        ))
+    }
+
+    pub(crate) fn neg(&self) -> Self {
+        match self {
+            Self::Exact(z) => Self::Exact(-z),
+            Self::Approx(z) => Self::Approx(-z),
+        }
+    }
+
+    /// Raises the number to a power. Whole powers and roots that come out rational, like `4^(1/2)`,
+    /// stay exact.
+    pub(crate) fn pow(&self, exponent: &Self) -> Result<Self> {
+        if let (Self::Exact(base), Some(e)) = (self, exponent.exact_real()) {
+            if e.is_integer() {
+                if base.is_zero() && e.is_negative() {
+                    bail!("You can't divide by zero.");
+                }
+                if base.is_zero() || base.is_one() || e.is_zero() {
+                    return Ok(if e.is_zero() {
+                        Self::integer(1)
+                    } else {
+                        self.clone()
+                    });
+                }
+                let n = e.to_integer().to_i64().unwrap_or(i64::MAX);
+                if bits(base).saturating_mul(n.unsigned_abs()) <= MAX_BITS {
+                    return Ok(Self::Exact(base.powi(n as i32)));
+                }
+            } else if base.im.is_zero() && !base.re.is_negative() {
+                let root = e.denom().to_u32().and_then(|n| exact_root(&base.re, n));
+                if let Some(root) = root {
+                    return Self::real(root)
+                        .pow(&Self::real(BigRational::from_integer(e.numer().clone())));
+                }
+            }
+        }
+        let (base, exponent) = (self.to_complex64(), exponent.to_complex64());
+        if base.is_zero() {
+            return match exponent.re.partial_cmp(&0.0) {
+                Some(Ordering::Greater) => Ok(Self::approx(0.0)),
+                _ => bail!("You can't divide by zero."),
+            };
+        }
+        let real = base.im == 0.0 && exponent.im == 0.0;
+        if real && (base.re > 0.0 || exponent.re.fract() == 0.0) {
+            return Ok(Self::approx(base.re.powf(exponent.re)));
+        }
+        Ok(Self::Approx(tidy(base.powc(exponent))))
+    }
+
+    /// The square root, exact if it's rational.
+    pub(crate) fn sqrt(&self) -> Result<Self> {
+        match self.exact_real() {
+            Some(r) if r.is_negative() => Ok(Self::i().mul(&Self::real(-r).sqrt()?)),
+            _ => self.pow(&Self::real(BigRational::new(1.into(), 2.into()))),
+        }
+    }
+
+    /// The real cube root of a real number, like `cbrt(-8) = -2`, or else the principal one.
+    pub(crate) fn cbrt(&self) -> Result<Self> {
+        let third = Self::real(BigRational::new(1.into(), 3.into()));
+        match self.to_real() {
+            Some(x) if x < 0.0 => Ok(self.neg().pow(&third)?.neg()),
+            _ => self.pow(&third),
+        }
+    }
+
+    pub(crate) fn factorial(&self) -> Result<Self> {
+        let n = self
+            .exact_real()
+            .filter(|r| r.is_integer())
+            .and_then(|r| r.to_integer().to_u32())
+            .filter(|n| *n <= MAX_FACTORIAL);
+        let Some(n) = n else {
+            bail!(
+                "Factorials only work on whole numbers from 0 to {}.",
+                MAX_FACTORIAL
+            );
+        };
+        let product = (1..=n).fold(BigInt::one(), |product, k| product * k);
+        Ok(Self::real(BigRational::from_integer(product)))
+    }
+
+    pub(crate) fn abs(&self) -> Result<Self> {
+        match self {
+            Self::Exact(z) if z.im.is_zero() => Ok(Self::real(z.re.abs())),
+            Self::Exact(z) if z.re.is_zero() => Ok(Self::real(z.im.abs())),
+            Self::Exact(z) => Self::real(z.norm_sqr()).sqrt(),
+            Self::Approx(z) => Ok(Self::approx(z.norm())),
+        }
+    }
+
+    pub(crate) fn re(&self) -> Self {
+        match self {
+            Self::Exact(z) => Self::real(z.re.clone()),
+            Self::Approx(z) => Self::approx(z.re),
+        }
+    }
+
+    pub(crate) fn im(&self) -> Self {
+        match self {
+            Self::Exact(z) => Self::real(z.im.clone()),
+            Self::Approx(z) => Self::approx(z.im),
+        }
+    }
+
+    pub(crate) fn conj(&self) -> Self {
+        match self {
+            Self::Exact(z) => Self::Exact(z.conj()),
+            Self::Approx(z) => Self::Approx(z.conj()),
+        }
+    }
+
+    /// Rounds a real number down, up or to the nearest whole number.
+    pub(crate) fn round(&self, how: &str) -> Result<Self> {
+        if let Some(r) = self.exact_real() {
+            let rounded = match how {
+                "floor" => r.floor(),
+                "ceil" => r.ceil(),
+                _ => r.round(),
+            };
+            return Ok(Self::real(rounded));
+        }
+        let Some(x) = self.to_real() else {
+            bail!("{} only works on real numbers.", how);
+        };
+        Ok(Self::approx(match how {
+            "floor" => x.floor(),
+            "ceil" => x.ceil(),
+            _ => x.round(),
+        }))
+    }
+
+    /// The sign of a real number, or the direction of a complex one.
+    pub(crate) fn sign(&self) -> Result<Self> {
+        match self.exact_real() {
+            Some(r) => Ok(Self::real(r.signum())),
+            None if self.is_zero() => Ok(Self::integer(0)),
+            None => self.div(&self.abs()?),
+        }
+    }
+
+    /// Compares two real numbers.
+    pub(crate) fn compare(&self, other: &Self) -> Option<Ordering> {
+        match (self.exact_real(), other.exact_real()) {
+            (Some(a), Some(b)) => Some(a.cmp(b)),
+            _ => self.to_real()?.partial_cmp(&other.to_real()?),
+        }
+    }
+
+    /// Applies one of the functions that give irrational answers, like `sin` or `ln`, to the
+    /// number. They're worked out with real numbers when they can be, so that `ln(2)` doesn't come
+    /// out with an imaginary part of `0`, and with complex ones when they can't, like for `ln(-1)`.
+    pub(crate) fn function(&self, name: &str) -> Result<Self> {
+        let (real, complex): Function = match name {
+            "exp" => (f64::exp, Complex64::exp),
+            "ln" => (f64::ln, Complex64::ln),
+            "log" | "log10" => (f64::log10, Complex64::log10),
+            "log2" => (f64::log2, Complex64::log2),
+            "sin" => (f64::sin, Complex64::sin),
+            "cos" => (f64::cos, Complex64::cos),
+            "tan" => (f64::tan, Complex64::tan),
+            "asin" => (f64::asin, Complex64::asin),
+            "acos" => (f64::acos, Complex64::acos),
+            "atan" => (f64::atan, Complex64::atan),
+            "sinh" => (f64::sinh, Complex64::sinh),
+            "cosh" => (f64::cosh, Complex64::cosh),
+            "tanh" => (f64::tanh, Complex64::tanh),
+            "arg" => (
+                |x| if x < 0.0 { std::f64::consts::PI } else { 0.0 },
+                |z| Complex64::new(z.arg(), 0.0),
+            ),
+            _ => bail!("I don't know the function {}.", name),
+        };
+        if matches!(name, "ln" | "log" | "log10" | "log2") && self.is_zero() {
+            bail!("The logarithm of zero is undefined.");
+        }
+        let z = self.to_complex64();
+        let mut result = match self.to_real().map(real) {
+            Some(x) if !x.is_nan() => Complex64::new(x, 0.0),
+            _ => tidy(complex(z)),
+        };
+        if matches!(name, "sin" | "cos" | "tan") && result.norm() < 1e-15 * z.norm().max(1.0) {
+            // like sin(pi), which rounding leaves a hair away from 0
+            result = Complex64::new(0.0, 0.0);
+        }
+        Ok(Self::Approx(result))
+    }
+
+    /// Writes the number in Typst math, after what it's equal to: `= 0.25`, `= frac(1, 3) approx
+    /// 0.3333333333`, or `approx 1.414213562` if it isn't exact. A unit, if there is one, is
+    /// written after each value.
+    pub(crate) fn typst_value(&self, unit: &str) -> String {
+        let complex = match self {
+            Self::Exact(z) => !z.re.is_zero() && !z.im.is_zero(),
+            Self::Approx(z) => z.re != 0.0 && z.im != 0.0,
+        };
+        let with_unit = |value: String| match (unit.is_empty(), complex) {
+            (true, _) => value,
+            (false, true) => format!("({}) thin {}", value, unit),
+            (false, false) => format!("{} thin {}", value, unit),
+        };
+        let approx = |z: Complex<BigRational>| {
+            let part = |r: &BigRational| (!r.is_zero()).then(|| approx_typst(r));
+            format!(
+                "approx {}",
+                with_unit(complex_typst(part(&z.re), part(&z.im)))
+            )
+        };
+        match self {
+            Self::Exact(z) => {
+                let re = exact_typst(&z.re);
+                let im = exact_typst(&z.im);
+                match (re, im) {
+                    (Some((re, re_decimal)), Some((im, im_decimal))) => {
+                        let exact = with_unit(complex_typst(re, im));
+                        if re_decimal && im_decimal {
+                            format!("= {}", exact)
+                        } else {
+                            format!("= {} {}", exact, approx(z.clone()))
+                        }
+                    }
+                    _ => approx(z.clone()),
+                }
+            }
+            Self::Approx(z) => {
+                let part = |x: f64| BigRational::from_float(x).unwrap_or_default();
+                approx(Complex::new(part(z.re), part(z.im)))
+            }
+        }
+    }
+}
+
+/// Writes a complex number in Typst math from its parts, which are left out if they're zero.
+fn complex_typst(re: Option<String>, im: Option<String>) -> String {
+    let im = im.map(|im| match im.as_str() {
+        "1" => "i".to_string(),
+        "-1" => "-i".to_string(),
+        _ => format!("{} i", im),
+    });
+    match (re, im) {
+        (None, None) => "0".to_string(),
+        (Some(re), None) => re,
+        (None, Some(im)) => im,
+        (Some(re), Some(im)) => match im.strip_prefix('-') {
+            Some(im) => format!("{} - {}", re, im),
+            None => format!("{} + {}", re, im),
+        },
+    }
+}
+
+/// Writes a rational number exactly in Typst math, as a whole number, a decimal or a fraction,
+/// with whether it's been written as a decimal. It's left out if it's zero, and `None` if it's
+/// too long to write out.
+fn exact_typst(r: &BigRational) -> Option<(Option<String>, bool)> {
+    if r.is_zero() {
+        return Some((None, true));
+    }
+    // anything written out is at most 10^MAX_DIGITS over at most 10^MAX_DIGITS, so there's no
+    // need to write out longer numbers to find out they're too long
+    let max_bits = 4 * MAX_DIGITS as u64;
+    if r.numer().bits() > max_bits || r.denom().bits() > max_bits {
+        return None;
+    }
+    let sign = if r.is_negative() { "-" } else { "" };
+    let r = r.abs();
+    if r.is_integer() {
+        let digits = r.to_integer().to_string();
+        return (digits.len() <= MAX_DIGITS).then(|| (Some(format!("{}{}", sign, digits)), true));
+    }
+    // a fraction is a decimal that ends if it's over a power of ten
+    let mut denom = r.denom().clone();
+    let mut places = 0;
+    for factor in [2u32, 5] {
+        let mut count = 0;
+        while (&denom % factor).is_zero() {
+            denom /= factor;
+            count += 1;
+        }
+        places = places.max(count);
+    }
+    if denom.is_one() {
+        let digits = (&r * power_of_ten(places)).to_integer().to_string();
+        if digits.len() <= MAX_DIGITS {
+            let exponent = digits.len() as i64 - 1 - places;
+            return Some((
+                Some(format!("{}{}", sign, decimal(&digits, exponent))),
+                true,
+            ));
+        }
+    }
+    let (numer, denom) = (r.numer().to_string(), r.denom().to_string());
+    (numer.len() <= MAX_FRACTION_DIGITS && denom.len() <= MAX_FRACTION_DIGITS)
+        .then(|| (Some(format!("{}frac({}, {})", sign, numer, denom)), false))
+}
+
+/// Writes a rational number in Typst math, rounded to a few significant digits.
+fn approx_typst(r: &BigRational) -> String {
+    let sign = if r.is_negative() { "-" } else { "" };
+    let r = r.abs();
+    // r is at least 10^(estimate - 1) and less than 10^(estimate + 1)
+    let estimate = r.numer().to_string().len() as i64 - r.denom().to_string().len() as i64;
+    let mut shift = SIGNIFICANT as i64 - estimate;
+    let digits_at = |shift| (&r * power_of_ten(shift)).round().to_integer().to_string();
+    let mut digits = digits_at(shift);
+    while digits.len() > SIGNIFICANT {
+        shift -= 1;
+        digits = digits_at(shift);
+    }
+    format!(
+        "{}{}",
+        sign,
+        decimal(&digits, digits.len() as i64 - 1 - shift)
+    )
+}
+
+/// Writes digits as a decimal in Typst math, where `exponent` is the power of ten of the first
+/// one. Very big and very small numbers are written like `6.02 times 10^(23)`.
+fn decimal(digits: &str, exponent: i64) -> String {
+    let digits = digits.trim_end_matches('0');
+    if digits.is_empty() {
+        return "0".to_string();
+    }
+    if !(-6..12).contains(&exponent) {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        return format!("{}{}{} times 10^({})", first, point, rest, exponent);
+    }
+    if exponent < 0 {
+        return format!("0.{}{}", "0".repeat((-exponent - 1) as usize), digits);
+    }
+    let whole = exponent as usize + 1;
+    if digits.len() <= whole {
+        format!("{}{}", digits, "0".repeat(whole - digits.len()))
+    } else {
+        format!("{}.{}", &digits[..whole], &digits[whole..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(text: &str) -> Number {
+        Number::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_exact() {
+        let third = Number::integer(1).div(&Number::integer(3)).unwrap();
+        let sixth = Number::integer(1).div(&Number::integer(6)).unwrap();
+        assert_eq!(third.add(&sixth), number("0.5"));
+        assert_eq!(number("1e3"), Number::integer(1000));
+        // exponents too big to keep exact, or to even read, are worked out approximately
+        assert_eq!(number("1.5e-9223372036854775808"), Number::approx(0.0));
+        assert_eq!(
+            number("1e99999999999999999999"),
+            Number::approx(f64::INFINITY)
+        );
+        assert_eq!(Number::i().mul(&Number::i()), Number::integer(-1));
+        assert_eq!(Number::integer(4).sqrt().unwrap(), Number::integer(2));
+        assert_eq!(
+            Number::integer(-4).sqrt().unwrap(),
+            Number::i().mul(&Number::integer(2))
+        );
+        assert_eq!(
+            number("0.2962962962962962962962962963")
+                .pow(&number("0"))
+                .unwrap(),
+            Number::integer(1)
+        );
+        let eight_27 = Number::integer(8).div(&Number::integer(27)).unwrap();
+        let third_power = Number::integer(1).div(&Number::integer(3)).unwrap();
+        assert_eq!(
+            eight_27.pow(&third_power).unwrap(),
+            Number::integer(2).div(&Number::integer(3)).unwrap()
+        );
+        assert_eq!(Number::integer(-8).cbrt().unwrap(), Number::integer(-2));
+        assert!(matches!(
+            Number::integer(2).sqrt().unwrap(),
+            Number::Approx(_)
+        ));
+        assert_eq!(
+            Number::integer(20).factorial().unwrap(),
+            number("2432902008176640000")
+        );
+        assert!(Number::integer(1).div(&Number::integer(0)).is_err());
+        assert!(Number::integer(0).pow(&Number::integer(-1)).is_err());
+        assert!(number("2.5").factorial().is_err());
+
+        // answers that get too big are worked out approximately instead
+        let big = Number::integer(2).pow(&Number::integer(40_000)).unwrap();
+        let square = big.mul(&big);
+        assert!(matches!(square, Number::Exact(_)));
+        assert!(matches!(square.mul(&big), Number::Approx(_)));
+        let third = square.div(&Number::integer(3)).unwrap();
+        let tiny = Number::integer(1).div(&big).unwrap();
+        assert!(matches!(third.add(&tiny), Number::Approx(_)));
+    }
+
+    #[test]
+    fn test_typst_value() {
+        let cases = [
+            (number("0.25"), "= 0.25"),
+            (
+                Number::integer(1).div(&Number::integer(3)).unwrap(),
+                "= frac(1, 3) approx 0.3333333333",
+            ),
+            (
+                Number::integer(-2).div(&Number::integer(3)).unwrap(),
+                "= -frac(2, 3) approx -0.6666666667",
+            ),
+            (Number::integer(2).sqrt().unwrap(), "approx 1.414213562"),
+            (number("6.02e23"), "= 602000000000000000000000"),
+            (number("6.02e-23"), "= 6.02 times 10^(-23)"),
+            (
+                Number::integer(2).pow(&Number::integer(200)).unwrap(),
+                "approx 1.606938044 times 10^(60)",
+            ),
+            (Number::integer(3).add(&Number::i().neg()), "= 3 - i"),
+            (Number::integer(-1).sqrt().unwrap(), "= i"),
+            (
+                Number::integer(-1).function("ln").unwrap(),
+                "approx 3.141592654 i",
+            ),
+            (
+                Number::approx(std::f64::consts::PI)
+                    .function("sin")
+                    .unwrap(),
+                "approx 0",
+            ),
+            (number("0.9999999999999"), "= 0.9999999999999"),
+            (Number::approx(0.99999999999999), "approx 1"),
+        ];
+        for (number, expected) in cases {
+            assert_eq!(number.typst_value(""), expected, "{:?}", number);
+        }
+        assert_eq!(
+            Number::i().add(&Number::integer(1)).typst_value("\"m\""),
+            "= (1 + i) thin \"m\""
+        );
+    }
+}
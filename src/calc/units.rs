@@ -0,0 +1,334 @@
+//! Physical units, as multiples of the SI base units.
+//!
+//! A unit's size is written as an exact decimal, or a fraction of two, so conversions between units
+//! defined exactly in terms of each other, like feet and metres, come out exact.
+
+use anyhow::{bail, Result};
+
+use super::number::Number;
+
+/// Powers of the SI base units: the metre, kilogram, second, ampere, kelvin, mole and candela.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Dimension(pub [i32; 7]);
+
+/// The SI base units, in the order [Dimension] has them.
+const BASE_UNITS: [&str; 7] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+/// The order base units are written in, with kilograms first, like `kg m s^(-2)`.
+const BASE_ORDER: [usize; 7] = [1, 0, 2, 3, 4, 5, 6];
+
+const NONE: [i32; 7] = [0, 0, 0, 0, 0, 0, 0];
+
+/// The biggest power a unit can have, either way.
+const MAX_POWER: i32 = 100;
+const LENGTH: [i32; 7] = [1, 0, 0, 0, 0, 0, 0];
+const MASS: [i32; 7] = [0, 1, 0, 0, 0, 0, 0];
+const TIME: [i32; 7] = [0, 0, 1, 0, 0, 0, 0];
+const CURRENT: [i32; 7] = [0, 0, 0, 1, 0, 0, 0];
+const TEMPERATURE: [i32; 7] = [0, 0, 0, 0, 1, 0, 0];
+const AMOUNT: [i32; 7] = [0, 0, 0, 0, 0, 1, 0];
+const LUMINOSITY: [i32; 7] = [0, 0, 0, 0, 0, 0, 1];
+const AREA: [i32; 7] = [2, 0, 0, 0, 0, 0, 0];
+const VOLUME: [i32; 7] = [3, 0, 0, 0, 0, 0, 0];
+const SPEED: [i32; 7] = [1, 0, -1, 0, 0, 0, 0];
+const FREQUENCY: [i32; 7] = [0, 0, -1, 0, 0, 0, 0];
+const FORCE: [i32; 7] = [1, 1, -2, 0, 0, 0, 0];
+const PRESSURE: [i32; 7] = [-1, 1, -2, 0, 0, 0, 0];
+const ENERGY: [i32; 7] = [2, 1, -2, 0, 0, 0, 0];
+const POWER: [i32; 7] = [2, 1, -3, 0, 0, 0, 0];
+const CHARGE: [i32; 7] = [0, 0, 1, 1, 0, 0, 0];
+const VOLTAGE: [i32; 7] = [2, 1, -3, -1, 0, 0, 0];
+const RESISTANCE: [i32; 7] = [2, 1, -3, -2, 0, 0, 0];
+const MAGNETIC_FIELD: [i32; 7] = [0, 1, -2, -1, 0, 0, 0];
+
+/// A unit someone can write.
+struct Unit {
+    /// The unit's names. Only the first can have a prefix, like `km`.
+    names: &'static [&'static str],
+    /// How big the unit is in SI base units, as a decimal or a fraction of two decimals.
+    size: &'static str,
+    dimension: [i32; 7],
+    prefixes: bool,
+}
+
+/// Shorthand for the table of units.
+const fn unit(
+    names: &'static [&'static str],
+    size: &'static str,
+    dimension: [i32; 7],
+    prefixes: bool,
+) -> Unit {
+    Unit {
+        names,
+        size,
+        dimension,
+        prefixes,
+    }
+}
+
+const UNITS: [Unit; 58] = [
+    // length
+    unit(
+        &["m", "metre", "meter", "metres", "meters"],
+        "1",
+        LENGTH,
+        true,
+    ),
+    unit(&["in", "inch", "inches"], "0.0254", LENGTH, false),
+    unit(&["ft", "foot", "feet"], "0.3048", LENGTH, false),
+    unit(&["yd", "yard", "yards"], "0.9144", LENGTH, false),
+    unit(&["mi", "mile", "miles"], "1609.344", LENGTH, false),
+    unit(&["nmi"], "1852", LENGTH, false),
+    unit(&["au", "AU"], "149597870700", LENGTH, false),
+    unit(&["ly"], "9460730472580800", LENGTH, false),
+    unit(&["pc", "parsec"], "3.0856775814913673e16", LENGTH, true),
+    unit(&["Å", "angstrom"], "1e-10", LENGTH, false),
+    // area and volume
+    unit(&["ha", "hectare"], "10000", AREA, false),
+    unit(&["acre", "acres"], "4046.8564224", AREA, false),
+    unit(
+        &["L", "litre", "liter", "litres", "liters"],
+        "0.001",
+        VOLUME,
+        true,
+    ),
+    unit(&["l"], "0.001", VOLUME, true),
+    unit(
+        &["gal", "gallon", "gallons"],
+        "0.003785411784",
+        VOLUME,
+        false,
+    ),
+    // mass
+    unit(&["g", "gram", "grams"], "0.001", MASS, true),
+    unit(&["t", "tonne", "tonnes"], "1000", MASS, false),
+    unit(&["lb", "lbs", "pound", "pounds"], "0.45359237", MASS, false),
+    unit(&["oz", "ounce", "ounces"], "0.028349523125", MASS, false),
+    unit(&["Da", "u"], "1.66053906660e-27", MASS, true),
+    // time
+    unit(&["s", "sec", "second", "seconds"], "1", TIME, true),
+    unit(&["min", "minute", "minutes"], "60", TIME, false),
+    unit(&["h", "hr", "hour", "hours"], "3600", TIME, false),
+    unit(&["day", "days"], "86400", TIME, false),
+    unit(&["week", "weeks"], "604800", TIME, false),
+    unit(&["yr", "year", "years"], "31557600", TIME, false),
+    // speed and frequency
+    unit(&["mph"], "0.44704", SPEED, false),
+    unit(&["kn", "knot", "knots"], "1852/3600", SPEED, false),
+    unit(&["c"], "299792458", SPEED, false),
+    unit(&["Hz", "hertz"], "1", FREQUENCY, true),
+    unit(&["rpm"], "1/60", FREQUENCY, false),
+    // mechanics
+    unit(&["N", "newton", "newtons"], "1", FORCE, true),
+    unit(&["lbf"], "4.4482216152605", FORCE, false),
+    unit(&["Pa", "pascal", "pascals"], "1", PRESSURE, true),
+    unit(&["bar"], "100000", PRESSURE, true),
+    unit(&["atm"], "101325", PRESSURE, false),
+    unit(&["psi"], "4.4482216152605/0.00064516", PRESSURE, false),
+    unit(&["mmHg"], "133.322387415", PRESSURE, false),
+    unit(&["J", "joule", "joules"], "1", ENERGY, true),
+    unit(&["eV"], "1.602176634e-19", ENERGY, true),
+    unit(&["cal"], "4.184", ENERGY, true),
+    unit(&["Wh"], "3600", ENERGY, true),
+    unit(&["W", "watt", "watts"], "1", POWER, true),
+    unit(&["hp"], "745.69987158227022", POWER, false),
+    // electromagnetism
+    unit(
+        &["A", "amp", "amps", "ampere", "amperes"],
+        "1",
+        CURRENT,
+        true,
+    ),
+    unit(&["C", "coulomb", "coulombs"], "1", CHARGE, true),
+    unit(&["V", "volt", "volts"], "1", VOLTAGE, true),
+    unit(&["Ω", "ohm", "ohms"], "1", RESISTANCE, true),
+    unit(&["T", "tesla"], "1", MAGNETIC_FIELD, true),
+    // temperature, as differences: conversions between scales are worked out separately
+    unit(&["K", "kelvin"], "1", TEMPERATURE, true),
+    unit(&["°C", "degC"], "1", TEMPERATURE, false),
+    unit(&["°F", "degF"], "5/9", TEMPERATURE, false),
+    // the rest of the base units
+    unit(&["mol", "mole", "moles"], "1", AMOUNT, true),
+    unit(&["cd", "candela"], "1", LUMINOSITY, true),
+    // angles and ratios
+    unit(&["rad", "radian", "radians"], "1", NONE, false),
+    unit(&["percent"], "0.01", NONE, false),
+    unit(&["ppm"], "1e-6", NONE, false),
+    unit(&["dozen"], "12", NONE, false),
+];
+
+/// Angles in degrees, whose size isn't a fraction.
+const DEGREES: [&str; 3] = ["deg", "°", "degrees"];
+
+/// SI prefixes and their powers of ten.
+const PREFIXES: [(&str, i32); 20] = [
+    ("Y", 24),
+    ("Z", 21),
+    ("E", 18),
+    ("P", 15),
+    ("T", 12),
+    ("G", 9),
+    ("M", 6),
+    ("k", 3),
+    ("h", 2),
+    ("da", 1),
+    ("d", -1),
+    ("c", -2),
+    ("m", -3),
+    ("µ", -6),
+    ("μ", -6),
+    ("u", -6),
+    ("n", -9),
+    ("p", -12),
+    ("f", -15),
+    ("a", -18),
+];
+
+/// The units answers are given in when they aren't converted into something, if their dimension
+/// has one, instead of base units.
+const DERIVED_UNITS: [(&str, [i32; 7]); 7] = [
+    ("N", FORCE),
+    ("Pa", PRESSURE),
+    ("J", ENERGY),
+    ("W", POWER),
+    ("C", CHARGE),
+    ("V", VOLTAGE),
+    ("Ω", RESISTANCE),
+];
+
+/// Temperature scales: the temperature of absolute zero in each, negated, and the size of a
+/// degree in kelvin. A temperature in kelvin is `(t + start) * size`.
+const TEMPERATURE_SCALES: [(&str, &str, &str); 5] = [
+    ("K", "0", "1"),
+    ("°C", "273.15", "1"),
+    ("degC", "273.15", "1"),
+    ("°F", "459.67", "5/9"),
+    ("degF", "459.67", "5/9"),
+];
+
+impl Dimension {
+    pub(crate) fn is_none(&self) -> bool {
+        self.0 == NONE
+    }
+
+    /// Checks that no unit has a power beyond [MAX_POWER].
+    pub(crate) fn limited(self) -> Result<Self> {
+        if self.0.iter().any(|p| !(-MAX_POWER..=MAX_POWER).contains(p)) {
+            bail!("Units can't have powers beyond ±{}.", MAX_POWER);
+        }
+        Ok(self)
+    }
+
+    /// Combines the powers of each unit in two dimensions.
+    fn combine(&self, other: &Self, op: fn(i32, i32) -> Option<i32>) -> Result<Self> {
+        let mut powers = self.0;
+        for (power, &other) in powers.iter_mut().zip(other.0.iter()) {
+            *power = op(*power, other).unwrap_or(i32::MAX);
+        }
+        Self(powers).limited()
+    }
+
+    pub(crate) fn mul(&self, other: &Self) -> Result<Self> {
+        self.combine(other, i32::checked_add)
+    }
+
+    pub(crate) fn div(&self, other: &Self) -> Result<Self> {
+        self.combine(other, i32::checked_sub)
+    }
+
+    /// Raises the dimension to the power `numer / denom`, if that leaves whole powers of each unit:
+    /// `m^2` has a square root, but `m` doesn't.
+    pub(crate) fn pow(&self, numer: i32, denom: i32) -> Option<Self> {
+        let mut powers = self.0;
+        for power in powers.iter_mut() {
+            let scaled = power.checked_mul(numer)?;
+            if scaled % denom != 0 {
+                return None;
+            }
+            *power = scaled / denom;
+        }
+        Some(Self(powers))
+    }
+
+    /// The units with this dimension, like `(kg, 1), (m, 1), (s, -2)`, or a derived unit like
+    /// `(N, 1)` if there's one for it.
+    fn units(&self) -> Vec<(&'static str, i32)> {
+        if let Some((name, _)) = DERIVED_UNITS.iter().find(|(_, d)| *d == self.0) {
+            return vec![(name, 1)];
+        }
+        BASE_ORDER
+            .iter()
+            .map(|&i| (BASE_UNITS[i], self.0[i]))
+            .filter(|(_, power)| *power != 0)
+            .collect()
+    }
+
+    /// Writes the units with this dimension in Typst math, like `"kg" thin "m" thin "s"^(-2)`.
+    pub(crate) fn to_typst(self) -> String {
+        let units: Vec<String> = self
+            .units()
+            .iter()
+            .map(|(name, power)| match power {
+                1 => format!("\"{}\"", name),
+                _ => format!("\"{}\"^({})", name, power),
+            })
+            .collect();
+        units.join(" thin ")
+    }
+
+    /// Writes the units with this dimension in words, like `kg m s^-2`.
+    pub(crate) fn describe(&self) -> String {
+        if self.is_none() {
+            return "a plain number".to_string();
+        }
+        let units: Vec<String> = self
+            .units()
+            .iter()
+            .map(|(name, power)| match power {
+                1 => name.to_string(),
+                _ => format!("{}^{}", name, power),
+            })
+            .collect();
+        units.join(" ")
+    }
+}
+
+/// Looks up a unit, with any prefix it has, like `km`: its size in SI base units and its
+/// dimension.
+pub(crate) fn lookup(name: &str) -> Option<(Number, Dimension)> {
+    if DEGREES.contains(&name) {
+        let size = Number::approx(std::f64::consts::PI / 180.0);
+        return Some((size, Dimension(NONE)));
+    }
+    let size = |unit: &Unit| Number::parse_fraction(unit.size).expect("Unit sizes are numbers");
+    if let Some(unit) = UNITS.iter().find(|u| u.names.contains(&name)) {
+        return Some((size(unit), Dimension(unit.dimension)));
+    }
+    PREFIXES.iter().find_map(|(prefix, power)| {
+        let rest = name.strip_prefix(prefix)?;
+        let unit = UNITS.iter().find(|u| u.prefixes && u.names[0] == rest)?;
+        let prefix = Number::parse(&format!("1e{}", power)).expect("Prefixes are numbers");
+        Some((size(unit).mul(&prefix), Dimension(unit.dimension)))
+    })
+}
+
+/// Whether a name is a unit.
+pub(crate) fn is_unit(name: &str) -> bool {
+    lookup(name).is_some()
+}
+
+/// Converts a temperature between scales, like from °F to °C, if both names are temperature
+/// scales. Other conversions only scale, which would turn 0 °C into 0 K.
+pub(crate) fn convert_temperature(value: &Number, from: &str, to: &str) -> Option<Number> {
+    let scale = |name: &str| {
+        let (_, start, size) = TEMPERATURE_SCALES.iter().find(|(n, ..)| *n == name)?;
+        Some((
+            Number::parse(start).expect("Temperature scales are numbers"),
+            Number::parse_fraction(size).expect("Temperature scales are numbers"),
+        ))
+    };
+    let (from_start, from_size) = scale(from)?;
+    let (to_start, to_size) = scale(to)?;
+    let kelvin = value.add(&from_start).mul(&from_size);
+    Some(kelvin.div(&to_size).ok()?.sub(&to_start))
+}
@@ -1,10 +1,558 @@
 //! Module for global bot configuration.
+//!
+//! The configuration is read once at startup from a TOML file (`nano.toml` unless `--config <path>` or
+//! `NANO_CONFIG` says otherwise), and then overridden by environment variables, so secrets don't have
+//! to be written to disk. Anything left out of both gets the default that used to be hardcoded. See
+//! `nano.example.toml` for every setting.
 
-/// The number of seconds required between thanking people.
-pub(crate) const THANK_COOLDOWN: usize = 5;
-/// The URL of the Redis server.
-pub(crate) const REDIS_URL: &str = "redis://127.0.0.1:1234/";
-///// The opening delimiter that indicate Typst math code to render as such.
-// pub(crate) const TYPST_OPEN_DELIM: &str = r"<.";
-///// The closing delimiter that indicate Typst math code to render as such.
-// pub(crate) const TYPST_CLOSE_DELIM: &str = r".>";
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Where the configuration is read from if no path is given.
+const DEFAULT_CONFIG_PATH: &str = "nano.toml";
+
+/// What secrets are replaced with when the configuration is printed.
+const REDACTED: &str = "<redacted>";
+
+/// The configuration in use, set once at startup.
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The whole bot configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub discord: DiscordConfig,
+    pub storage: StorageConfig,
+    pub reputation: ReputationConfig,
+    pub translation: TranslationConfig,
+    pub reactions: ReactionConfig,
+    pub latex: LatexConfig,
+    pub typst: TypstConfig,
+}
+
+/// Connecting to Discord.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct DiscordConfig {
+    /// The bot token. Overridden by `DISCORD_TOKEN`.
+    pub token: String,
+    /// The prefix for prefix commands, if they're enabled at all. Overridden by `PREFIX`.
+    pub prefix: Option<String>,
+    /// What the bot is shown as playing, if anything. Overridden by `NANO_ACTIVITY`.
+    pub activity: String,
+    /// Guilds to register commands in directly, which is instant, unlike global registration. Used
+    /// for testing. Overridden by `NANO_TEST_GUILDS`, a comma-separated list.
+    pub test_guilds: Vec<u64>,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            prefix: None,
+            activity: "with Sakamoto".to_string(),
+            test_guilds: vec![846580828942237736, 807797906132303901, 1079226248263368814],
+        }
+    }
+}
+
+/// Where state is kept.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct StorageConfig {
+    /// A Redis URL, or `sqlite:` followed by the path of a database file. Overridden by `STORAGE_URL`.
+    pub url: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1:1234/".to_string(),
+        }
+    }
+}
+
+/// The reputation system.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ReputationConfig {
+    /// The number of seconds required between thanking people. Overridden by `NANO_THANK_COOLDOWN`.
+    pub thank_cooldown: usize,
+    /// The guild the old global, username-keyed leaderboard belonged to, the only one that can
+    /// migrate it. Overridden by `NANO_LEGACY_GUILD`.
+    pub legacy_guild: Option<u64>,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            thank_cooldown: 5,
+            legacy_guild: None,
+        }
+    }
+}
+
+/// Translation through DeepL.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TranslationConfig {
+    /// The DeepL API endpoint. Overridden by `NANO_DEEPL_URL`.
+    pub deepl_url: String,
+    /// The DeepL API key. Translation fails without one. Overridden by `DEEPL_KEY`.
+    pub deepl_key: String,
+}
+
+impl Default for TranslationConfig {
+    fn default() -> Self {
+        Self {
+            deepl_url: "https://api-free.deepl.com/v2".to_string(),
+            deepl_key: String::new(),
+        }
+    }
+}
+
+/// Replies to people talking to Nano.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ReactionConfig {
+    /// The GIF to reply to praise with. Overridden by `NANO_GOOD_NANO_GIF`.
+    pub good_nano_gif: String,
+    /// The GIF to reply to scolding with. Overridden by `NANO_BAD_NANO_GIF`.
+    pub bad_nano_gif: String,
+}
+
+impl Default for ReactionConfig {
+    fn default() -> Self {
+        Self {
+            good_nano_gif: "https://i.imgur.com/bgiANhm.gif".to_string(),
+            bad_nano_gif: "https://c.tenor.com/8QjR5hC91b0AAAAC/nichijou-nano.gif".to_string(),
+        }
+    }
+}
+
+/// Rendering LaTeX with a local TeX installation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LatexConfig {
+    /// The TeX engine that turns documents into PDFs. If it isn't installed, LaTeX is converted to
+    /// Typst instead. Overridden by `NANO_LATEX_ENGINE`.
+    pub engine: String,
+    /// The number of seconds a document gets to compile and convert before it's given up on.
+    /// Overridden by `NANO_LATEX_TIMEOUT`.
+    pub timeout: u64,
+    /// The resolution of rendered images, in dots per inch. Overridden by `NANO_LATEX_DPI`.
+    pub dpi: u32,
+}
+
+impl Default for LatexConfig {
+    fn default() -> Self {
+        Self {
+            engine: "pdflatex".to_string(),
+            timeout: 10,
+            dpi: 300,
+        }
+    }
+}
+
+/// Rendering Typst.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TypstConfig {
+    /// The number of processes rendering at once. Overridden by `NANO_TYPST_WORKERS`.
+    pub workers: usize,
+    /// The number of seconds a render can take, including waiting for a free thread. Overridden by
+    /// `NANO_TYPST_TIMEOUT`.
+    pub timeout: u64,
+    /// The most pages a document can have. Overridden by `NANO_TYPST_MAX_PAGES`.
+    pub max_pages: usize,
+    /// The most MiB of memory a rendering process can use, or 0 for no limit. Overridden by
+    /// `NANO_TYPST_MEMORY_LIMIT`.
+    pub memory_limit: usize,
+    /// The most MiB of renders to keep in memory. Overridden by `NANO_TYPST_CACHE_SIZE`.
+    pub cache_size: usize,
+    /// Where to keep every render so they last between runs, if anywhere. Overridden by
+    /// `NANO_TYPST_CACHE_DIR`.
+    pub cache_dir: String,
+    /// Where packages are, and where package archives are unpacked. Overridden by
+    /// `NANO_TYPST_PACKAGE_DIR`.
+    pub package_dir: String,
+    /// The only packages that can be imported, as names or `name:version`, or empty to allow all of
+    /// them. Overridden by `NANO_TYPST_ALLOWED_PACKAGES`, a comma-separated list.
+    pub allowed_packages: Vec<String>,
+}
+
+impl Default for TypstConfig {
+    fn default() -> Self {
+        Self {
+            workers: 2,
+            timeout: 10,
+            max_pages: 10,
+            memory_limit: 2048,
+            cache_size: 64,
+            cache_dir: String::new(),
+            package_dir: "packages".to_string(),
+            allowed_packages: vec![],
+        }
+    }
+}
+
+/// Parses a value from an environment variable, noting an error if it's invalid.
+fn parse_env<T: std::str::FromStr>(name: &str, value: &str, errors: &mut Vec<String>) -> Option<T> {
+    match value.trim().parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            errors.push(format!("{} is not valid: {:?}", name, value));
+            None
+        }
+    }
+}
+
+/// Checks that a URL is an HTTP(S) URL.
+fn check_http_url(name: &str, url: &str, errors: &mut Vec<String>) {
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        errors.push(format!("{} must be an http(s) URL, not {:?}", name, url));
+    }
+}
+
+impl Config {
+    /// Parses a configuration file.
+    pub(crate) fn parse(toml_src: &str) -> Result<Self> {
+        toml::from_str(toml_src).map_err(|e| anyhow!("Invalid configuration file: {}", e))
+    }
+
+    /// Overrides settings with environment variables, which are looked up with `var`. Returns a
+    /// description of every variable that couldn't be parsed.
+    pub(crate) fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+        let mut errors = vec![];
+        if let Some(token) = var("DISCORD_TOKEN") {
+            self.discord.token = token;
+        }
+        if let Some(prefix) = var("PREFIX") {
+            self.discord.prefix = Some(prefix);
+        }
+        if let Some(activity) = var("NANO_ACTIVITY") {
+            self.discord.activity = activity;
+        }
+        if let Some(guilds) = var("NANO_TEST_GUILDS") {
+            self.discord.test_guilds = guilds
+                .split(',')
+                .filter(|g| !g.trim().is_empty())
+                .filter_map(|g| parse_env("NANO_TEST_GUILDS", g, &mut errors))
+                .collect();
+        }
+        if let Some(url) = var("STORAGE_URL") {
+            self.storage.url = url;
+        }
+        if let Some(cooldown) = var("NANO_THANK_COOLDOWN") {
+            if let Some(cooldown) = parse_env("NANO_THANK_COOLDOWN", &cooldown, &mut errors) {
+                self.reputation.thank_cooldown = cooldown;
+            }
+        }
+        if let Some(guild) = var("NANO_LEGACY_GUILD") {
+            self.reputation.legacy_guild = parse_env("NANO_LEGACY_GUILD", &guild, &mut errors);
+        }
+        if let Some(url) = var("NANO_DEEPL_URL") {
+            self.translation.deepl_url = url;
+        }
+        if let Some(key) = var("DEEPL_KEY") {
+            self.translation.deepl_key = key;
+        }
+        if let Some(gif) = var("NANO_GOOD_NANO_GIF") {
+            self.reactions.good_nano_gif = gif;
+        }
+        if let Some(gif) = var("NANO_BAD_NANO_GIF") {
+            self.reactions.bad_nano_gif = gif;
+        }
+        if let Some(engine) = var("NANO_LATEX_ENGINE") {
+            self.latex.engine = engine;
+        }
+        if let Some(timeout) = var("NANO_LATEX_TIMEOUT") {
+            if let Some(timeout) = parse_env("NANO_LATEX_TIMEOUT", &timeout, &mut errors) {
+                self.latex.timeout = timeout;
+            }
+        }
+        if let Some(dpi) = var("NANO_LATEX_DPI") {
+            if let Some(dpi) = parse_env("NANO_LATEX_DPI", &dpi, &mut errors) {
+                self.latex.dpi = dpi;
+            }
+        }
+        if let Some(workers) = var("NANO_TYPST_WORKERS") {
+            if let Some(workers) = parse_env("NANO_TYPST_WORKERS", &workers, &mut errors) {
+                self.typst.workers = workers;
+            }
+        }
+        if let Some(timeout) = var("NANO_TYPST_TIMEOUT") {
+            if let Some(timeout) = parse_env("NANO_TYPST_TIMEOUT", &timeout, &mut errors) {
+                self.typst.timeout = timeout;
+            }
+        }
+        if let Some(pages) = var("NANO_TYPST_MAX_PAGES") {
+            if let Some(pages) = parse_env("NANO_TYPST_MAX_PAGES", &pages, &mut errors) {
+                self.typst.max_pages = pages;
+            }
+        }
+        if let Some(limit) = var("NANO_TYPST_MEMORY_LIMIT") {
+            if let Some(limit) = parse_env("NANO_TYPST_MEMORY_LIMIT", &limit, &mut errors) {
+                self.typst.memory_limit = limit;
+            }
+        }
+        if let Some(size) = var("NANO_TYPST_CACHE_SIZE") {
+            if let Some(size) = parse_env("NANO_TYPST_CACHE_SIZE", &size, &mut errors) {
+                self.typst.cache_size = size;
+            }
+        }
+        if let Some(dir) = var("NANO_TYPST_CACHE_DIR") {
+            self.typst.cache_dir = dir;
+        }
+        if let Some(dir) = var("NANO_TYPST_PACKAGE_DIR") {
+            self.typst.package_dir = dir;
+        }
+        if let Some(packages) = var("NANO_TYPST_ALLOWED_PACKAGES") {
+            self.typst.allowed_packages = packages
+                .split(',')
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect();
+        }
+        errors
+    }
+
+    /// Checks that the configuration makes sense, returning a description of every problem.
+    pub(crate) fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.discord.token.trim().is_empty() {
+            errors.push("discord.token must be set (or DISCORD_TOKEN given)".to_string());
+        }
+        if self
+            .discord
+            .prefix
+            .as_deref()
+            .is_some_and(|p| p.trim().is_empty())
+        {
+            errors.push(
+                "discord.prefix can't be blank: leave it out to disable prefix commands"
+                    .to_string(),
+            );
+        }
+        if self.discord.test_guilds.contains(&0) {
+            errors.push("discord.test_guilds can't contain 0".to_string());
+        }
+        if self.reputation.legacy_guild == Some(0) {
+            errors.push("reputation.legacy_guild can't be 0".to_string());
+        }
+
+        let url = &self.storage.url;
+        let known_scheme = [
+            "redis://",
+            "rediss://",
+            "redis+unix://",
+            "unix://",
+            "sqlite:",
+        ]
+        .iter()
+        .any(|scheme| url.starts_with(scheme));
+        if !known_scheme {
+            errors.push(format!(
+                "storage.url must be a Redis URL or sqlite:<path>, not {:?}",
+                url
+            ));
+        } else if url == "sqlite:" {
+            errors.push("storage.url needs a database path after sqlite:".to_string());
+        }
+
+        if self.reputation.thank_cooldown == 0 {
+            errors.push("reputation.thank_cooldown must be at least 1 second".to_string());
+        }
+
+        check_http_url(
+            "translation.deepl_url",
+            &self.translation.deepl_url,
+            &mut errors,
+        );
+        check_http_url(
+            "reactions.good_nano_gif",
+            &self.reactions.good_nano_gif,
+            &mut errors,
+        );
+        check_http_url(
+            "reactions.bad_nano_gif",
+            &self.reactions.bad_nano_gif,
+            &mut errors,
+        );
+
+        if self.latex.engine.trim().is_empty() {
+            errors.push("latex.engine can't be blank".to_string());
+        }
+        if self.latex.timeout == 0 {
+            errors.push("latex.timeout must be at least 1 second".to_string());
+        }
+        if !(50..=1200).contains(&self.latex.dpi) {
+            errors.push("latex.dpi must be between 50 and 1200".to_string());
+        }
+
+        if self.typst.workers == 0 {
+            errors.push("typst.workers must be at least 1".to_string());
+        }
+        if self.typst.timeout == 0 {
+            errors.push("typst.timeout must be at least 1 second".to_string());
+        }
+        if self.typst.max_pages == 0 {
+            errors.push("typst.max_pages must be at least 1".to_string());
+        }
+        if self.typst.package_dir.trim().is_empty() {
+            errors.push("typst.package_dir can't be blank".to_string());
+        }
+        for package in &self.typst.allowed_packages {
+            let name = package.split(':').next().unwrap_or_default().trim();
+            if name.is_empty() || package.matches(':').count() > 1 {
+                errors.push(format!(
+                    "typst.allowed_packages has {:?}, which isn't a name or name:version",
+                    package
+                ));
+            }
+        }
+        errors
+    }
+
+    /// Loads the configuration: the file at `path` (or the default path, if it exists) and then the
+    /// environment, checking that the result is valid.
+    pub(crate) fn load(path: Option<&str>) -> Result<Self> {
+        let mut config = match path {
+            Some(path) => Self::parse(
+                &std::fs::read_to_string(path)
+                    .map_err(|e| anyhow!("Couldn't read configuration file {}: {}", path, e))?,
+            )?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::parse(&std::fs::read_to_string(DEFAULT_CONFIG_PATH)?)?
+            }
+            None => Self::default(),
+        };
+
+        let mut errors = config.apply_env(|name| std::env::var(name).ok());
+        errors.extend(config.validate());
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            let mut msg = "Invalid configuration:".to_string();
+            for error in errors {
+                write!(msg, "\n- {}", error).expect("Writing to a string can't fail");
+            }
+            Err(anyhow!(msg))
+        }
+    }
+
+    /// The configuration as TOML, with secrets hidden.
+    pub(crate) fn to_redacted_toml(&self) -> String {
+        let mut redacted = self.clone();
+        for secret in [
+            &mut redacted.discord.token,
+            &mut redacted.translation.deepl_key,
+        ] {
+            if !secret.is_empty() {
+                *secret = REDACTED.to_string();
+            }
+        }
+        toml::to_string_pretty(&redacted).expect("Configuration is always serializable")
+    }
+}
+
+/// Sets the configuration for the rest of the bot's run. Can only be done once.
+pub(crate) fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("Configuration was already set");
+    }
+}
+
+/// Gets the configuration. If none was set (e.g., in tests), the defaults are used.
+pub(crate) fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn valid() -> Config {
+        let mut config = Config::default();
+        config.discord.token = "secret-token".to_string();
+        config
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"
+            [discord]
+            token = "abc"
+            test_guilds = []
+
+            [reputation]
+            thank_cooldown = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.discord.token, "abc");
+        assert!(config.discord.test_guilds.is_empty());
+        assert_eq!(config.reputation.thank_cooldown, 10);
+        // everything else keeps its default
+        assert_eq!(config.storage, StorageConfig::default());
+        assert_eq!(config.discord.activity, "with Sakamoto");
+
+        assert!(Config::parse("[discord]\ntokn = \"abc\"").is_err());
+        assert!(Config::parse("[reputation]\nthank_cooldown = \"soon\"").is_err());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let env = HashMap::from([
+            ("DISCORD_TOKEN", "from-env"),
+            ("NANO_TEST_GUILDS", "1, 2,3"),
+            ("STORAGE_URL", "sqlite:nano.db"),
+            ("NANO_THANK_COOLDOWN", "soon"),
+            ("NANO_LEGACY_GUILD", "42"),
+            ("NANO_TYPST_ALLOWED_PACKAGES", "cetz, fletcher:0.4.0,"),
+        ]);
+        let mut config = Config::default();
+        let errors = config.apply_env(|name| env.get(name).map(|v| v.to_string()));
+
+        assert_eq!(config.discord.token, "from-env");
+        assert_eq!(config.discord.test_guilds, vec![1, 2, 3]);
+        assert_eq!(config.storage.url, "sqlite:nano.db");
+        assert_eq!(config.reputation.thank_cooldown, 5);
+        assert_eq!(config.reputation.legacy_guild, Some(42));
+        assert_eq!(
+            config.typst.allowed_packages,
+            vec!["cetz".to_string(), "fletcher:0.4.0".to_string()]
+        );
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(valid().validate().is_empty());
+
+        let mut config = valid();
+        config.storage.url = "sqlite:".to_string();
+        config.reputation.thank_cooldown = 0;
+        config.reactions.bad_nano_gif = "nano.gif".to_string();
+        assert_eq!(config.validate().len(), 3);
+
+        assert_eq!(Config::default().validate().len(), 1);
+    }
+
+    #[test]
+    fn test_redaction() {
+        let printed = valid().to_redacted_toml();
+        assert!(!printed.contains("secret-token"));
+        assert!(printed.contains(REDACTED));
+        assert_eq!(
+            Config::parse(&printed).unwrap().discord.token,
+            REDACTED.to_string()
+        );
+    }
+}
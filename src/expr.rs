@@ -0,0 +1,605 @@
+//! Arithmetic expressions, like `x^2 - 3x` or `9.81 m/s^2 * 2 kg`, for the commands that work with
+//! them. Expressions are only parsed here: each command evaluates them its own way.
+//!
+//! Multiplication can be left out, as in `2x` or `3 ft`, and binds like `*`, so `1/2x` is `x/2`.
+//! Functions need parentheses around their arguments, and without them their names are plain names,
+//! so `min` can also be minutes. Unicode minus signs, `×`, `÷` and superscript powers like `x²` are
+//! read as you'd expect.
+
+use anyhow::{anyhow, bail, Result};
+
+/// The functions expressions can call. `log` is base 10, and `ln` is the natural logarithm.
+pub(crate) const FUNCTIONS: [&str; 27] = [
+    "sqrt", "cbrt", "abs", "exp", "ln", "log", "log10", "log2", "sin", "cos", "tan", "asin",
+    "acos", "atan", "sinh", "cosh", "tanh", "floor", "ceil", "round", "sign", "min", "max", "re",
+    "im", "conj", "arg",
+];
+
+/// How deeply expressions can nest, so a long run of parentheses can't overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// The superscript characters, and what they stand for.
+const SUPERSCRIPTS: [(char, char); 12] = [
+    ('⁰', '0'),
+    ('¹', '1'),
+    ('²', '2'),
+    ('³', '3'),
+    ('⁴', '4'),
+    ('⁵', '5'),
+    ('⁶', '6'),
+    ('⁷', '7'),
+    ('⁸', '8'),
+    ('⁹', '9'),
+    ('⁻', '-'),
+    ('⁺', '+'),
+];
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Expr {
+    /// A number as written, like `3`, `0.5` or `6.02e23`.
+    Number(String),
+    /// A variable, constant or unit.
+    Name(String),
+    Neg(Box<Expr>),
+    Factorial(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    /// A conversion, like `3 ft to m`.
+    Convert(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(String),
+    Name(String),
+    /// One of `+-*/^!`.
+    Op(char),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(s) | Self::Name(s) => write!(f, "{}", s),
+            Self::Op(c) => write!(f, "{}", c),
+            Self::LParen => write!(f, "("),
+            Self::RParen => write!(f, ")"),
+            Self::Comma => write!(f, ","),
+        }
+    }
+}
+
+/// Rewrites the ways of writing operators that aren't ASCII, like `−`, `×` and `x²`.
+fn normalize(input: &str) -> String {
+    let superscript = |c: char| SUPERSCRIPTS.iter().find(|(s, _)| *s == c).map(|(_, n)| *n);
+    let mut out = String::new();
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if let Some(first) = superscript(c) {
+            out.push_str("^(");
+            out.push(first);
+            while let Some(next) = chars.peek().and_then(|&c| superscript(c)) {
+                out.push(next);
+                chars.next();
+            }
+            out.push(')');
+            continue;
+        }
+        match c {
+            '−' | '–' => out.push('-'),
+            '×' | '·' | '⋅' => out.push('*'),
+            '÷' => out.push('/'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = normalize(input).chars().collect();
+    let digit_at = |i: usize| chars.get(i).is_some_and(|c| c.is_ascii_digit());
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_digit() || (c == '.' && digit_at(i + 1)) {
+            while digit_at(i) || chars.get(i) == Some(&'.') {
+                i += 1;
+            }
+            // an exponent, but not the constant e, as in 2e
+            if matches!(chars.get(i), Some('e' | 'E')) {
+                let sign = matches!(chars.get(i + 1), Some('+' | '-')) as usize;
+                if digit_at(i + 1 + sign) {
+                    i += 1 + sign;
+                    while digit_at(i) {
+                        i += 1;
+                    }
+                }
+            }
+            let number: String = chars[start..i].iter().collect();
+            if number.matches('.').count() > 1 {
+                bail!("{} isn't a number.", number);
+            }
+            tokens.push(Token::Number(number));
+            continue;
+        } else if c.is_alphabetic() || c == '_' || c == '°' {
+            i += 1;
+            while chars
+                .get(i)
+                .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+            continue;
+        }
+        tokens.push(match c {
+            '(' | '[' => Token::LParen,
+            ')' | ']' => Token::RParen,
+            ',' => Token::Comma,
+            // ** is a power, as in Python
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                i += 1;
+                Token::Op('^')
+            }
+            '+' | '-' | '*' | '/' | '^' | '!' => Token::Op(c),
+            c => bail!("I don't know what `{}` means.", c),
+        });
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_op(&self, ops: &str) -> Option<char> {
+        match self.peek() {
+            Some(Token::Op(c)) if ops.contains(*c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn unexpected(&self) -> anyhow::Error {
+        match self.peek() {
+            Some(token) => anyhow!("I didn't expect `{}` there.", token),
+            None => anyhow!("The expression ends too early."),
+        }
+    }
+
+    /// Goes a level deeper into the expression, unless that's too deep.
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            bail!("That expression nests too deeply.");
+        }
+        Ok(())
+    }
+
+    fn conversion(&mut self) -> Result<Expr> {
+        let expr = self.sum()?;
+        if matches!(self.peek(), Some(Token::Name(n)) if n == "to") {
+            self.pos += 1;
+            let target = self.sum()?;
+            return Ok(Expr::Convert(Box::new(expr), Box::new(target)));
+        }
+        Ok(expr)
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.peek_op("+-") {
+            self.pos += 1;
+            let op = if op == '+' {
+                BinaryOp::Add
+            } else {
+                BinaryOp::Sub
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        loop {
+            let (op, rhs) = if let Some(op) = self.peek_op("*/") {
+                self.pos += 1;
+                let op = if op == '*' {
+                    BinaryOp::Mul
+                } else {
+                    BinaryOp::Div
+                };
+                (op, self.unary()?)
+            } else if self.starts_operand() {
+                // left-out multiplication, like 2x
+                (BinaryOp::Mul, self.power()?)
+            } else {
+                break;
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    /// Whether the next token starts something that can be multiplied by what's before it.
+    fn starts_operand(&self) -> bool {
+        match self.peek() {
+            Some(Token::Number(_) | Token::LParen) => true,
+            Some(Token::Name(n)) => n != "to",
+            _ => false,
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        self.enter()?;
+        let expr = match self.peek_op("+-") {
+            Some('-') => {
+                self.pos += 1;
+                Expr::Neg(Box::new(self.unary()?))
+            }
+            Some(_) => {
+                self.pos += 1;
+                self.unary()?
+            }
+            None => self.power()?,
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn power(&mut self) -> Result<Expr> {
+        let mut base = self.primary()?;
+        while self.peek_op("!").is_some() {
+            self.pos += 1;
+            base = Expr::Factorial(Box::new(base));
+        }
+        if self.peek_op("^").is_some() {
+            self.pos += 1;
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(
+                BinaryOp::Pow,
+                Box::new(base),
+                Box::new(exponent),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        self.enter()?;
+        let expr = match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Expr::Number(n)
+            }
+            Some(Token::Name(name)) if name != "to" => {
+                self.pos += 1;
+                if FUNCTIONS.contains(&name.as_str()) && self.peek() == Some(&Token::LParen) {
+                    self.pos += 1;
+                    let mut args = vec![self.sum()?];
+                    while self.peek() == Some(&Token::Comma) {
+                        self.pos += 1;
+                        args.push(self.sum()?);
+                    }
+                    self.close()?;
+                    Expr::Call(name, args)
+                } else {
+                    Expr::Name(name)
+                }
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let expr = self.sum()?;
+                self.close()?;
+                expr
+            }
+            _ => return Err(self.unexpected()),
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        match self.peek() {
+            Some(Token::RParen) => {
+                self.pos += 1;
+                Ok(())
+            }
+            None => bail!("There's a `)` missing."),
+            Some(_) => Err(self.unexpected()),
+        }
+    }
+}
+
+/// The error for a name that doesn't mean anything where it's used. Function names are only calls
+/// when followed by parentheses, so those get a reminder.
+pub(crate) fn unknown_name(name: &str) -> anyhow::Error {
+    if FUNCTIONS.contains(&name) {
+        anyhow!("{} needs parentheses, like {}(x).", name, name)
+    } else {
+        anyhow!("I don't know what {} is.", name)
+    }
+}
+
+/// Parses an expression.
+pub(crate) fn parse(input: &str) -> Result<Expr> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        bail!("There's no expression.");
+    }
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        depth: 0,
+    };
+    let expr = parser.conversion()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(_) => Err(parser.unexpected()),
+    }
+}
+
+/// Writes a number in Typst math, with any exponent as a power of ten.
+fn number_typst(number: &str) -> String {
+    match number.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => format!(
+            "{} times 10^({})",
+            mantissa,
+            exponent.trim_start_matches('+')
+        ),
+        None => number.to_string(),
+    }
+}
+
+/// Writes a name in Typst math: letters and Greek letters in italics, and longer names, like
+/// units, upright.
+fn name_typst(name: &str) -> String {
+    match name {
+        "pi" | "π" => "pi".to_string(),
+        "tau" | "τ" => "tau".to_string(),
+        "theta" | "θ" => "theta".to_string(),
+        "phi" | "φ" => "phi".to_string(),
+        _ if name.chars().count() == 1 => name.to_string(),
+        _ => format!("\"{}\"", name),
+    }
+}
+
+/// Writes a function's name in Typst math.
+fn function_typst(name: &str) -> &str {
+    match name {
+        "asin" => "arcsin",
+        "acos" => "arccos",
+        "atan" => "arctan",
+        "log10" => "log_10",
+        "log2" => "log_2",
+        "round" => "op(\"round\")",
+        "sign" => "op(\"sgn\")",
+        "re" => "op(\"Re\")",
+        "im" => "op(\"Im\")",
+        name => name,
+    }
+}
+
+impl Expr {
+    /// Whether the expression is written as one piece, so it never needs parentheses.
+    fn is_atom(&self) -> bool {
+        match self {
+            Self::Number(n) => !n.contains(['e', 'E']),
+            Self::Name(_) | Self::Call(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Writes the expression in Typst math, in parentheses if it's made of pieces.
+    fn typst_grouped(&self, upright: &dyn Fn(&str) -> bool) -> String {
+        if self.is_atom() {
+            self.to_typst_with(upright)
+        } else {
+            format!("({})", self.to_typst_with(upright))
+        }
+    }
+
+    /// Writes the expression in Typst math, with parentheses around sums and conversions.
+    fn typst_factor(&self, upright: &dyn Fn(&str) -> bool) -> String {
+        match self {
+            Self::Binary(BinaryOp::Add | BinaryOp::Sub, ..) | Self::Convert(..) => {
+                format!("({})", self.to_typst_with(upright))
+            }
+            _ => self.to_typst_with(upright),
+        }
+    }
+
+    /// Writes the expression in Typst math, without the `$`s.
+    pub(crate) fn to_typst(&self) -> String {
+        self.to_typst_with(&|_| false)
+    }
+
+    /// Writes the expression in Typst math, with the names `upright` picks out, like units, written
+    /// upright even if they're one letter, and set a little apart from what they multiply.
+    pub(crate) fn to_typst_with(&self, upright: &dyn Fn(&str) -> bool) -> String {
+        match self {
+            Self::Number(n) => number_typst(n),
+            Self::Name(n) if upright(n) => format!("\"{}\"", n),
+            Self::Name(n) => name_typst(n),
+            Self::Neg(e) => format!("-{}", e.typst_factor(upright)),
+            Self::Factorial(e) => format!("{}!", e.typst_grouped(upright)),
+            Self::Binary(BinaryOp::Add, a, b) => format!(
+                "{} + {}",
+                a.to_typst_with(upright),
+                b.to_typst_with(upright)
+            ),
+            Self::Binary(BinaryOp::Sub, a, b) => {
+                format!("{} - {}", a.to_typst_with(upright), b.typst_factor(upright))
+            }
+            Self::Binary(BinaryOp::Mul, a, b) => {
+                let right = match &**b {
+                    Self::Neg(_) => b.typst_grouped(upright),
+                    _ => b.typst_factor(upright),
+                };
+                // numbers next to each other need a dot between them
+                let dot = right.starts_with(|c: char| c.is_ascii_digit() || c == '.');
+                let unit = matches!(&**b, Self::Name(n) if upright(n))
+                    || matches!(&**b, Self::Binary(BinaryOp::Pow, base, _)
+                        if matches!(&**base, Self::Name(n) if upright(n)));
+                let separator = match (dot, unit) {
+                    (true, _) => " dot ",
+                    (false, true) => " thin ",
+                    (false, false) => " ",
+                };
+                format!("{}{}{}", a.typst_factor(upright), separator, right)
+            }
+            Self::Binary(BinaryOp::Div, a, b) => format!(
+                "({})/({})",
+                a.to_typst_with(upright),
+                b.to_typst_with(upright)
+            ),
+            Self::Binary(BinaryOp::Pow, a, b) => format!(
+                "{}^({})",
+                a.typst_grouped(upright),
+                b.to_typst_with(upright)
+            ),
+            Self::Call(f, args) => {
+                let args: Vec<String> = args.iter().map(|a| a.to_typst_with(upright)).collect();
+                match f.as_str() {
+                    "cbrt" => format!("root(3, {})", args.join(", ")),
+                    "conj" => format!("overline({})", args.join(", ")),
+                    _ => format!("{}({})", function_typst(f), args.join(", ")),
+                }
+            }
+            Self::Convert(a, b) => format!(
+                "{} -> {}",
+                a.to_typst_with(upright),
+                b.to_typst_with(upright)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes an expression back out with every operation in parentheses.
+    fn show(expr: &Expr) -> String {
+        match expr {
+            Expr::Number(n) | Expr::Name(n) => n.clone(),
+            Expr::Neg(e) => format!("(-{})", show(e)),
+            Expr::Factorial(e) => format!("({}!)", show(e)),
+            Expr::Binary(op, a, b) => {
+                let op = match op {
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::Mul => "*",
+                    BinaryOp::Div => "/",
+                    BinaryOp::Pow => "^",
+                };
+                format!("({} {} {})", show(a), op, show(b))
+            }
+            Expr::Call(f, args) => {
+                let args: Vec<String> = args.iter().map(show).collect();
+                format!("{}({})", f, args.join(", "))
+            }
+            Expr::Convert(a, b) => format!("({} to {})", show(a), show(b)),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let cases = [
+            ("1 + 2 * 3", "(1 + (2 * 3))"),
+            ("x² − 3x", "((x ^ 2) - (3 * x))"),
+            ("-x^2", "(-(x ^ 2))"),
+            ("2^3^2", "(2 ^ (3 ^ 2))"),
+            ("2^-1", "(2 ^ (-1))"),
+            ("1/2x", "((1 / 2) * x)"),
+            ("2(x + 1)(x - 1)", "((2 * (x + 1)) * (x - 1))"),
+            ("sin(x)^2 + cos(x)**2", "((sin(x) ^ 2) + (cos(x) ^ 2))"),
+            ("max(1, 2, 3)", "max(1, 2, 3)"),
+            ("6.02e23 + 2e", "(6.02e23 + (2 * e))"),
+            ("5!", "(5!)"),
+            ("9.81 m/s^2 * 2 kg", "((((9.81 * m) / (s ^ 2)) * 2) * kg)"),
+            ("3 ft to m", "((3 * ft) to m)"),
+            ("90 min to h", "((90 * min) to h)"),
+            ("min(1, 2) min", "(min(1, 2) * min)"),
+            ("2 × 3 ÷ 4", "((2 * 3) / 4)"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(show(&parse(input).unwrap()), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = [
+            ("", "There's no expression."),
+            ("1 +", "The expression ends too early."),
+            ("(1 + 2", "There's a `)` missing."),
+            ("1 + 2)", "I didn't expect `)` there."),
+            ("1.2.3", "1.2.3 isn't a number."),
+            ("2 % 3", "I don't know what `%` means."),
+            ("to m", "I didn't expect `to` there."),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse(input).unwrap_err().to_string(),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+        assert!(parse(&"(".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn test_to_typst() {
+        let cases = [
+            ("x^2 - 3x", "x^(2) - 3 x"),
+            ("(x + 1)/(x - 1)", "(x + 1)/(x - 1)"),
+            ("-(a + b) * 2", "-(a + b) dot 2"),
+            ("(-2)^2", "(-2)^(2)"),
+            ("a - (b - c)", "a - (b - c)"),
+            ("asin(x) + log10(x)", "arcsin(x) + log_10(x)"),
+            ("6.02e23 pi", "6.02 times 10^(23) pi"),
+            ("3 ft to m", "3 \"ft\" -> m"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(input).unwrap().to_typst(), expected, "{:?}", input);
+        }
+        let units = |name: &str| ["m", "s", "kg"].contains(&name);
+        assert_eq!(
+            parse("9.81 m/s^2 * 2 kg").unwrap().to_typst_with(&units),
+            "(9.81 thin \"m\")/(\"s\"^(2)) dot 2 thin \"kg\""
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Per-guild settings for Nano's passive features: the ones that react to ordinary messages rather
+//! than commands. Admins can turn each feature off for the whole guild, or limit it to (or keep it out
+//! of) particular channels. Threads follow the settings of the channel they're in unless listed
+//! themselves.
+
+use std::collections::HashMap;
+
+use crate::storage::Storage;
+use crate::utils::{Context, Error};
+use poise::ChoiceParameter;
+use serde::{Deserialize, Serialize};
+use serenity::model::channel::Channel;
+use serenity::model::id::{ChannelId, GuildId};
+
+/// The name of the guild setting holding a guild's feature settings.
+const FEATURES: &str = "guild-features";
+
+/// A feature that responds to ordinary messages.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize, ChoiceParameter)]
+pub(crate) enum Feature {
+    #[name = "Auto-translation"]
+    Translation,
+    #[name = "Math rendering"]
+    Math,
+    #[name = "Good/bad Nano replies"]
+    NanoReplies,
+    #[name = "Thanks"]
+    Thanks,
+}
+
+impl Feature {
+    /// Every feature, in the order they're shown in.
+    const ALL: [Feature; 4] = [
+        Feature::Translation,
+        Feature::Math,
+        Feature::NanoReplies,
+        Feature::Thanks,
+    ];
+}
+
+/// Where a feature is enabled in a guild.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct FeatureSettings {
+    /// Whether the feature is on at all.
+    pub enabled: bool,
+    /// If not empty, the only channels the feature works in.
+    pub allowed: Vec<ChannelId>,
+    /// Channels the feature doesn't work in.
+    pub denied: Vec<ChannelId>,
+}
+
+impl Default for FeatureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            allowed: vec![],
+            denied: vec![],
+        }
+    }
+}
+
+impl FeatureSettings {
+    /// Whether the feature works in a channel, given the channel's parent if it has one. A channel
+    /// that is listed itself takes precedence over its parent.
+    fn allows(&self, channel: ChannelId, parent: Option<ChannelId>) -> bool {
+        if !self.enabled || self.denied.contains(&channel) {
+            return false;
+        }
+        if self.allowed.contains(&channel) {
+            return true;
+        }
+        match parent {
+            Some(parent) if self.denied.contains(&parent) => false,
+            Some(parent) if self.allowed.contains(&parent) => true,
+            _ => self.allowed.is_empty(),
+        }
+    }
+
+    /// Whether the channel lists are empty, so the parent of a channel doesn't matter.
+    fn is_channel_independent(&self) -> bool {
+        self.allowed.is_empty() && self.denied.is_empty()
+    }
+
+    /// A one-line description of the settings.
+    fn describe(&self) -> String {
+        fn channels(ids: &[ChannelId]) -> String {
+            ids.iter()
+                .map(|c| format!("<#{}>", c.0))
+                .collect::<Vec<String>>()
+                .join(", ")
+        }
+
+        if !self.enabled {
+            return "off".to_string();
+        }
+        let mut desc = if self.allowed.is_empty() {
+            "on everywhere".to_string()
+        } else {
+            format!("on only in {}", channels(&self.allowed))
+        };
+        if !self.denied.is_empty() {
+            desc.push_str(&format!(", except in {}", channels(&self.denied)));
+        }
+        desc
+    }
+}
+
+/// Gets a guild's feature settings. Features that haven't been configured are on everywhere.
+async fn get_settings(
+    guild: GuildId,
+    storage: &dyn Storage,
+) -> Result<HashMap<Feature, FeatureSettings>, Error> {
+    let raw = storage.guild_setting(guild, FEATURES).await?;
+    Ok(raw
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default())
+}
+
+/// Sets a guild's feature settings.
+async fn set_settings(
+    guild: GuildId,
+    settings: &HashMap<Feature, FeatureSettings>,
+    storage: &dyn Storage,
+) -> Result<(), Error> {
+    let serialized = serde_json::to_string(settings).expect("Settings are always serializable");
+    storage
+        .set_guild_setting(guild, FEATURES, &serialized)
+        .await
+}
+
+/// Checks whether a feature is enabled in a channel of a guild.
+pub(crate) async fn feature_enabled(
+    ctx: &serenity::prelude::Context,
+    storage: &dyn Storage,
+    guild: GuildId,
+    channel: ChannelId,
+    feature: Feature,
+) -> Result<bool, Error> {
+    let settings = get_settings(guild, storage)
+        .await?
+        .remove(&feature)
+        .unwrap_or_default();
+
+    // looking up the parent isn't free, so only do it when it could make a difference
+    let parent = if settings.enabled && !settings.is_channel_independent() {
+        match channel.to_channel(ctx).await {
+            Ok(Channel::Guild(channel)) => channel.parent_id,
+            _ => None,
+        }
+    } else {
+        None
+    };
+    Ok(settings.allows(channel, parent))
+}
+
+/// Changes the settings of one feature in this server, then shows them.
+async fn update_feature(
+    ctx: Context<'_>,
+    feature: Feature,
+    update: impl FnOnce(&mut FeatureSettings),
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+
+    let mut settings = get_settings(guild_id, storage).await?;
+    let feature_settings = settings.entry(feature).or_default();
+    update(feature_settings);
+    let desc = feature_settings.describe();
+    set_settings(guild_id, &settings, storage).await?;
+
+    ctx.send(|m| {
+        m.content(format!("**{}** is now {}.", feature, desc))
+            .allowed_mentions(|a| a.empty_parse())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Parent command for this server's settings. Does nothing on its own.
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("show", "enable", "disable", "allow", "deny", "reset")
+)]
+pub(crate) async fn config(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Shows where each of Nano's passive features is on in this server.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let mut settings = get_settings(guild_id, ctx.data().storage.as_ref()).await?;
+
+    let lines: Vec<String> = Feature::ALL
+        .iter()
+        .map(|feature| {
+            format!(
+                "- **{}**: {}",
+                feature,
+                settings.remove(feature).unwrap_or_default().describe()
+            )
+        })
+        .collect();
+
+    ctx.send(|m| {
+        m.content(format!("# Settings\n{}", lines.join("\n")))
+            .allowed_mentions(|a| a.empty_parse())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Turns a feature on in this server, in whichever channels it's allowed in.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn enable(
+    ctx: Context<'_>,
+    #[description = "Feature to turn on"] feature: Feature,
+) -> Result<(), Error> {
+    update_feature(ctx, feature, |s| s.enabled = true).await
+}
+
+/// Turns a feature off in every channel of this server.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn disable(
+    ctx: Context<'_>,
+    #[description = "Feature to turn off"] feature: Feature,
+) -> Result<(), Error> {
+    update_feature(ctx, feature, |s| s.enabled = false).await
+}
+
+/// Allows a feature in a channel. Once any channel is allowed, the feature only works in allowed
+/// channels (and their threads).
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn allow(
+    ctx: Context<'_>,
+    #[description = "Feature to allow"] feature: Feature,
+    #[description = "Channel to allow it in"] channel: Channel,
+) -> Result<(), Error> {
+    let channel = channel.id();
+    update_feature(ctx, feature, |s| {
+        s.denied.retain(|&c| c != channel);
+        if !s.allowed.contains(&channel) {
+            s.allowed.push(channel);
+        }
+    })
+    .await
+}
+
+/// Keeps a feature out of a channel (and its threads).
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn deny(
+    ctx: Context<'_>,
+    #[description = "Feature to deny"] feature: Feature,
+    #[description = "Channel to deny it in"] channel: Channel,
+) -> Result<(), Error> {
+    let channel = channel.id();
+    update_feature(ctx, feature, |s| {
+        s.allowed.retain(|&c| c != channel);
+        if !s.denied.contains(&channel) {
+            s.denied.push(channel);
+        }
+    })
+    .await
+}
+
+/// Takes a channel off a feature's allow and deny lists or, if no channel is given, puts the feature
+/// back to being on everywhere.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn reset(
+    ctx: Context<'_>,
+    #[description = "Feature to reset"] feature: Feature,
+    #[description = "Channel to reset (leave out to reset everything)"] channel: Option<Channel>,
+) -> Result<(), Error> {
+    let channel = channel.map(|c| c.id());
+    update_feature(ctx, feature, |s| match channel {
+        Some(channel) => {
+            s.allowed.retain(|&c| c != channel);
+            s.denied.retain(|&c| c != channel);
+        }
+        None => *s = FeatureSettings::default(),
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        let (general, math, thread) = (ChannelId(1), ChannelId(2), ChannelId(3));
+
+        let mut settings = FeatureSettings::default();
+        assert!(settings.allows(general, None));
+
+        settings.denied.push(math);
+        assert!(settings.allows(general, None));
+        assert!(!settings.allows(math, None));
+        assert!(!settings.allows(thread, Some(math)));
+
+        settings.allowed.push(thread);
+        assert!(settings.allows(thread, Some(math)));
+
+        settings.denied.clear();
+        settings.allowed = vec![math];
+        assert!(!settings.allows(general, None));
+        assert!(settings.allows(math, None));
+        assert!(settings.allows(thread, Some(math)));
+
+        settings.enabled = false;
+        assert!(!settings.allows(math, None));
+    }
+
+    #[test]
+    fn test_settings_roundtrip() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            Feature::Math,
+            FeatureSettings {
+                enabled: true,
+                allowed: vec![ChannelId(5)],
+                denied: vec![],
+            },
+        );
+        let serialized = serde_json::to_string(&settings).unwrap();
+        let parsed: HashMap<Feature, FeatureSettings> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(parsed, settings);
+    }
+}
@@ -1,28 +1,83 @@
 mod ask;
+mod calc;
 mod config;
 mod dictionary;
+mod expr;
 mod geolocation;
+mod guild_settings;
 mod math_markup;
 mod message_filter;
 mod message_handler;
 mod rep;
 mod say;
+mod storage;
 mod trace_moe;
 mod translate;
 mod utils;
 mod weather;
 mod wiki;
 
+use config::{config, Config};
 use poise::serenity_prelude::GuildId;
 use serenity::model::prelude::Activity;
 use serenity::prelude::GatewayIntents;
 use std::env;
+use std::process::exit;
 use std::time::Duration;
 
+/// Command-line usage.
+const USAGE: &str = "Usage: nano-bot [--config <path>] [--check-config]";
+
 #[tokio::main]
 async fn main() {
-    // Configure the client with your Discord bot token in the environment.
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    let mut config_path = env::var("NANO_CONFIG").ok();
+    let mut check_config = false;
+    let mut render_worker = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config_path = Some(args.next().expect(USAGE)),
+            "--check-config" => check_config = true,
+            // only used by the bot to start its own rendering processes
+            math_markup::WORKER_FLAG => render_worker = true,
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(2);
+            }
+        }
+    }
+
+    let loaded = Config::load(config_path.as_deref());
+    if check_config {
+        match loaded {
+            Ok(loaded) => {
+                println!("{}", loaded.to_redacted_toml());
+                exit(0);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+    }
+    match loaded {
+        Ok(loaded) => config::init(loaded),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+    if render_worker {
+        if let Err(e) = math_markup::run_render_worker() {
+            eprintln!("Render worker stopped: {}", e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    let storage = storage::connect(&config().storage.url)
+        .await
+        .expect("Couldn't open storage");
 
     let intents = GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::GUILD_MESSAGE_REACTIONS
@@ -36,26 +91,37 @@ async fn main() {
             commands: vec![
                 ask::ask(),
                 dictionary::define(),
+                guild_settings::config(),
                 rep::leaderboard(),
                 rep::reputation(),
+                rep::rep(),
                 say::say(),
                 say::mocking_case(),
                 trace_moe::find_anime_source(),
                 translate::translate(),
                 math_markup::set_default_math_markup(),
                 math_markup::typst(),
+                math_markup::chem(),
+                math_markup::plot(),
+                calc::calc(),
                 weather::weather(),
                 wiki::wiki(),
             ],
             prefix_options: poise::PrefixFrameworkOptions {
-                prefix: env::var("PREFIX").ok(),
+                prefix: config().discord.prefix.clone(),
                 edit_tracker: Some(poise::EditTracker::for_timespan(Duration::from_secs(3600))),
                 ..Default::default()
             },
-            event_handler: |ctx, event, _framework_ctx, _user| {
+            event_handler: |ctx, event, _framework_ctx, data| {
                 Box::pin(async move {
-                    if let poise::Event::Message { new_message } = event {
-                        message_handler::handle_message(ctx, new_message).await?;
+                    match event {
+                        poise::Event::Message { new_message } => {
+                            message_handler::handle_message(ctx, new_message, data).await?;
+                        }
+                        poise::Event::ReactionAdd { add_reaction } => {
+                            rep::thank_reaction(ctx, add_reaction, data).await?;
+                        }
+                        _ => {}
                     }
 
                     Ok(())
@@ -63,15 +129,17 @@ async fn main() {
             },
             ..Default::default()
         })
-        .token(token)
+        .token(&config().discord.token)
         .intents(intents)
         .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
-                ctx.set_activity(Activity::playing("with Sakamoto")).await;
+                if !config().discord.activity.is_empty() {
+                    ctx.set_activity(Activity::playing(&config().discord.activity))
+                        .await;
+                }
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
                 // set up testing servers
-                for guild_id in [846580828942237736, 807797906132303901, 1079226248263368814] {
-                    // for guild_id in [823589317833523281] {
+                for &guild_id in &config().discord.test_guilds {
                     poise::builtins::register_in_guild(
                         ctx,
                         &framework.options().commands,
@@ -79,7 +147,7 @@ async fn main() {
                     )
                     .await?;
                 }
-                Ok(())
+                Ok(utils::Data { storage })
             })
         });
 
@@ -0,0 +1,515 @@
+//! Turns whole chat messages into documents, so a message with some math in it renders the way it
+//! reads on Discord: prose stays prose, with its bold, italics and code, and only the math is
+//! rendered as math.
+//!
+//! The math and code are taken out first and replaced by placeholder characters. Discord's markdown
+//! is then converted around them, with everything else escaped so it comes out as written, and the
+//! placeholders are swapped back for the converted math and code. Emojis are dropped, since the
+//! render fonts don't have them. Chemistry written as `\ce{...}` is checked and balanced like with
+//! `/chem`.
+
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use regex::Regex;
+use serenity::{
+    model::{id::GuildId, user::User},
+    utils::{content_safe, ContentSafeOptions},
+};
+
+use crate::math_markup::{
+    chemistry::prepare,
+    delimiters::{tokenize, Delimiter, Segment},
+    typst_base::RenderSettings,
+};
+
+/// Custom Discord emojis, Unicode emojis and the pieces they're made of, and private-use characters,
+/// which are used as placeholders.
+const DROPPED_PATTERN: &str = r"<a?:\w+:\d+>|[\p{Extended_Pictographic}\p{Emoji_Modifier}\p{Regional_Indicator}\u{FE0F}\u{20E3}\u{200D}\u{E0020}-\u{E007F}\p{Co}]";
+
+/// whalogen's chemistry, which is written in Typst even outside of math.
+const CHEM_PATTERN: &str = r#"#ce\("((?:[^"\\]|\\.)*)"\)"#;
+
+static DROPPED_RE: OnceLock<Regex> = OnceLock::new();
+static CHEM_RE: OnceLock<Regex> = OnceLock::new();
+
+/// The first placeholder character, in the private use area.
+const PLACEHOLDER: u32 = 0xE000;
+
+/// What a message is turned into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// Typst, with `$...$` math taken to be Typst.
+    Typst,
+    /// Typst, with all math taken to be LaTeX and converted with mitex.
+    TypstFromLatex,
+    /// The body of a LaTeX document.
+    Latex,
+}
+
+/// The text styles of Discord's markdown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Style {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Spoiler,
+}
+
+/// The markers for each style, longest first so `**` isn't read as two `*`.
+const MARKERS: [(&str, Style); 6] = [
+    ("**", Style::Bold),
+    ("__", Style::Underline),
+    ("~~", Style::Strikethrough),
+    ("||", Style::Spoiler),
+    ("*", Style::Italic),
+    ("_", Style::Italic),
+];
+
+/// Characters Discord lets you escape with a backslash.
+const DISCORD_ESCAPES: &str = "\\*_~|`>#-:<";
+
+/// Replaces mentions of users, roles and channels in a message with their names, using members'
+/// nicknames in a guild.
+pub(crate) fn display_mentions(
+    ctx: &serenity::prelude::Context,
+    content: &str,
+    guild: Option<GuildId>,
+    mentioned: &[User],
+) -> String {
+    let mut options = ContentSafeOptions::default().show_discriminator(false);
+    if let Some(guild) = guild {
+        options = options.display_as_member_from(guild);
+    }
+    content_safe(&ctx.cache, content, &options, mentioned)
+}
+
+impl Target {
+    fn is_typst(&self) -> bool {
+        !matches!(self, Self::Latex)
+    }
+
+    /// Writes a character of prose so it comes out as itself.
+    fn escape(&self, c: char, out: &mut String) {
+        if self.is_typst() {
+            if "\\#*_`$<>@=-+/~[]{}.".contains(c) {
+                out.push('\\');
+            }
+            out.push(c);
+        } else {
+            match c {
+                '\\' => out.push_str(r"\textbackslash{}"),
+                '~' => out.push_str(r"\textasciitilde{}"),
+                '^' => out.push_str(r"\textasciicircum{}"),
+                '<' => out.push_str(r"\textless{}"),
+                '>' => out.push_str(r"\textgreater{}"),
+                '|' => out.push_str(r"\textbar{}"),
+                '{' | '}' | '$' | '&' | '%' | '#' | '_' => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    /// Writes line breaks: one keeps to the paragraph, more start a new one.
+    fn newlines(&self, count: usize, out: &mut String) {
+        match (self.is_typst(), count) {
+            (true, 1) => out.push('\\'),
+            (false, 1) => out.push_str(r"\par"),
+            (true, _) => {}
+            (false, _) => out.push_str(r"\par\medskip"),
+        }
+        out.push_str(&"\n".repeat(count));
+    }
+
+    fn open(&self, style: Style) -> &'static str {
+        match (self.is_typst(), style) {
+            (true, Style::Bold) => "#strong[",
+            (true, Style::Italic) => "#emph[",
+            (true, Style::Underline) => "#underline[",
+            (true, Style::Strikethrough) => "#strike[",
+            (true, Style::Spoiler) => "#box(fill: gray)[#hide[",
+            (false, Style::Bold) => r"\textbf{",
+            (false, Style::Italic) => r"\emph{",
+            (false, Style::Underline) => r"\underline{",
+            (false, Style::Strikethrough) => r"\sout{",
+            (false, Style::Spoiler) => r"\colorbox{gray}{\phantom{",
+        }
+    }
+
+    fn close(&self, style: Style) -> &'static str {
+        match (self.is_typst(), style) {
+            (true, Style::Spoiler) => "]]",
+            (true, _) => "]",
+            (false, Style::Spoiler) => "}}",
+            (false, _) => "}",
+        }
+    }
+
+    /// Converts code, with its backticks.
+    fn code(&self, code: &str) -> String {
+        let fenced = code.starts_with("```");
+        let ticks = if fenced {
+            3
+        } else {
+            code.len() - code.trim_start_matches('`').len()
+        };
+        let inner = &code[ticks..code.len() - ticks];
+        if self.is_typst() {
+            // Typst's raw text is written like Discord's, except that it reads the first word of a
+            // fenced block as its language even without a newline after it
+            if (fenced && inner.contains('\n')) || ticks == 1 {
+                code.to_string()
+            } else {
+                format!("#raw(block: {}, {})", fenced, typst_string(inner))
+            }
+        } else if fenced {
+            let mut lines: Vec<&str> = inner.lines().collect();
+            if inner.contains('\n') {
+                // the language
+                lines.remove(0);
+            }
+            let lines: Vec<String> = lines.iter().map(|line| latex_code(line)).collect();
+            format!(r"\par{{\ttfamily\noindent {}}}\par ", lines.join("\\\\\n"))
+        } else {
+            format!(r"\texttt{{{}}}", latex_code(inner))
+        }
+    }
+
+    /// Converts math.
+    fn math(&self, delimiter: Delimiter, content: &str) -> String {
+        let display = matches!(delimiter, Delimiter::DoubleDollar | Delimiter::Bracket);
+        match self {
+            Self::Typst if !delimiter.is_latex() => format!("${}$", content),
+            Self::Typst | Self::TypstFromLatex => {
+                let latex = if content.contains('`') {
+                    typst_string(content)
+                } else {
+                    format!("`{}`.text", content)
+                };
+                let function = if display { "mitex" } else { "mi" };
+                format!("#{}(latex-macros + {})", function, latex)
+            }
+            Self::Latex if display => format!(r"\[{}\]", content),
+            Self::Latex => format!(r"\({}\)", content),
+        }
+    }
+
+    /// Converts whalogen chemistry, given what's in its string.
+    fn chem(&self, call: &str, formula: &str) -> String {
+        if self.is_typst() {
+            call.to_string()
+        } else {
+            format!(r"\ce{{{}}}", formula.replace("\\\"", "\""))
+        }
+    }
+}
+
+/// Writes a Typst string literal.
+pub(crate) fn typst_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Escapes a line of code for LaTeX, keeping its spacing.
+fn latex_code(line: &str) -> String {
+    let mut out = String::new();
+    for c in line.chars() {
+        match c {
+            ' ' => out.push('~'),
+            c => Target::Latex.escape(c, &mut out),
+        }
+    }
+    out
+}
+
+/// Finds where a style that starts right before `from` ends, if it does. Like Discord, styles can't
+/// start or end next to whitespace, and underscores only work at the edges of words.
+fn find_closing(text: &str, from: usize, marker: &str) -> Option<usize> {
+    if text[from..].starts_with(char::is_whitespace) {
+        return None;
+    }
+    let underscore = marker.starts_with('_');
+    let mut i = from;
+    while i < text.len() {
+        let rest = &text[i..];
+        let next = rest.chars().next().map_or(1, char::len_utf8);
+        if rest.starts_with('\\') {
+            i += 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+            continue;
+        }
+        if marker.len() == 1 && rest.starts_with(&marker.repeat(2)) {
+            // part of a longer marker
+            i += 2;
+            continue;
+        }
+        if rest.starts_with(marker) && i > from {
+            let before = text[..i].chars().next_back();
+            let after = rest[marker.len()..].chars().next();
+            if before.map_or(false, |c| !c.is_whitespace())
+                && !(underscore && after.map_or(false, char::is_alphanumeric))
+            {
+                return Some(i);
+            }
+        }
+        i += next;
+    }
+    None
+}
+
+/// Converts prose with Discord's markdown, putting the pieces back in for their placeholders.
+fn convert_prose(text: &str, target: Target, pieces: &[String], out: &mut String) {
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let c = rest.chars().next().expect("Not at the end");
+
+        if c == '\\' {
+            match rest[1..].chars().next() {
+                Some(escaped) if DISCORD_ESCAPES.contains(escaped) => {
+                    target.escape(escaped, out);
+                    i += 1 + escaped.len_utf8();
+                }
+                _ => {
+                    target.escape(c, out);
+                    i += 1;
+                }
+            }
+            continue;
+        }
+
+        if let Some(&(marker, style)) = MARKERS.iter().find(|(m, _)| rest.starts_with(m)) {
+            let start = i + marker.len();
+            let after_word = text[..i]
+                .chars()
+                .next_back()
+                .map_or(false, char::is_alphanumeric);
+            let end = if marker.starts_with('_') && after_word {
+                None
+            } else {
+                find_closing(text, start, marker)
+            };
+            match end {
+                Some(end) => {
+                    out.push_str(target.open(style));
+                    convert_prose(&text[start..end], target, pieces, out);
+                    out.push_str(target.close(style));
+                    i = end + marker.len();
+                }
+                None => {
+                    for c in marker.chars() {
+                        target.escape(c, out);
+                    }
+                    i = start;
+                }
+            }
+            continue;
+        }
+
+        if c == '\n' {
+            let count = rest.len() - rest.trim_start_matches('\n').len();
+            target.newlines(count, out);
+            i += count;
+            continue;
+        }
+
+        match (c as u32).checked_sub(PLACEHOLDER) {
+            Some(index) if (index as usize) < pieces.len() => out.push_str(&pieces[index as usize]),
+            _ => target.escape(c, out),
+        }
+        i += c.len_utf8();
+    }
+}
+
+/// A chat message turned into a document.
+#[derive(Debug)]
+pub(crate) struct MessageDocument {
+    pub source: String,
+    /// Notes on the message's chemistry, like how a reaction was balanced.
+    pub notes: Vec<String>,
+}
+
+/// Turns a chat message into a Typst document or the body of a LaTeX document. The user's LaTeX
+/// macros are defined here for mitex; LaTeX documents have them in their preamble already. Fails if
+/// the message has chemistry that isn't valid.
+pub(crate) fn message_document(
+    msg: &str,
+    target: Target,
+    settings: &RenderSettings,
+) -> Result<MessageDocument> {
+    let dropped_re = DROPPED_RE.get_or_init(|| Regex::new(DROPPED_PATTERN).unwrap());
+    let chem_re = CHEM_RE.get_or_init(|| Regex::new(CHEM_PATTERN).unwrap());
+
+    let mut prose = String::new();
+    let mut pieces = vec![];
+    let mut notes = vec![];
+    let mut uses_mitex = false;
+    let mut add_piece = |prose: &mut String, piece: String| {
+        prose.push(char::from_u32(PLACEHOLDER + pieces.len() as u32).expect("Too many pieces"));
+        pieces.push(piece);
+    };
+
+    for segment in tokenize(msg.trim()) {
+        match segment {
+            Segment::Text(text) => {
+                let text = dropped_re.replace_all(text, "");
+                let mut last = 0;
+                for chem in chem_re.captures_iter(&text) {
+                    let call = chem.get(0).expect("Always a match");
+                    prose.push_str(&text[last..call.start()]);
+                    add_piece(&mut prose, target.chem(call.as_str(), &chem[1]));
+                    last = call.end();
+                }
+                prose.push_str(&text[last..]);
+            }
+            Segment::Code(code) => add_piece(&mut prose, target.code(code)),
+            Segment::Chem(formula) => {
+                let (ce, note) = prepare(formula, true)?;
+                notes.extend(note);
+                let call = format!("#ce({})", typst_string(&ce));
+                add_piece(&mut prose, target.chem(&call, &ce))
+            }
+            Segment::Math { delimiter, content } => {
+                // with LaTeX math, $...$ is LaTeX too
+                let delimiter = match (target, delimiter) {
+                    (Target::TypstFromLatex, Delimiter::Dollar) => Delimiter::Paren,
+                    _ => delimiter,
+                };
+                uses_mitex |= target.is_typst() && delimiter.is_latex();
+                add_piece(&mut prose, target.math(delimiter, content))
+            }
+        }
+    }
+
+    let mut out = String::new();
+    match target {
+        Target::Latex => out.push_str(r"\setlength{\parindent}{0pt}"),
+        _ if uses_mitex => {
+            out.push_str(&format!(
+                "#let latex-macros = {};",
+                typst_string(&settings.latex_macros)
+            ));
+        }
+        _ => {}
+    }
+    convert_prose(&prose, target, &pieces, &mut out);
+    Ok(MessageDocument { source: out, notes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typst(msg: &str) -> String {
+        message_document(msg, Target::Typst, &RenderSettings::default())
+            .unwrap()
+            .source
+    }
+
+    #[test]
+    fn test_typst_document() {
+        let cases = [
+            ("$x^2$", "$x^2$"),
+            ("so $a = b$.", "so $a = b$\\."),
+            (
+                "**bold** and *it* or _it_ but not snake_case_name",
+                "#strong[bold] and #emph[it] or #emph[it] but not snake\\_case\\_name",
+            ),
+            ("2 * 3 * 4 = $24$", "2 \\* 3 \\* 4 \\= $24$"),
+            ("*the answer is $x$*", "#emph[the answer is $x$]"),
+            ("||$x = 2$||", "#box(fill: gray)[#hide[$x = 2$]]"),
+            ("hi <:nano:123456> 👋🏽 $x$", "hi   $x$"),
+            ("line one\nline two\n\nnew", "line one\\\nline two\n\nnew"),
+            (
+                "`a*b` then ```py\nx = $1\n```",
+                "`a*b` then ```py\nx = $1\n```",
+            ),
+            (
+                "```one line``` ``a ` b``",
+                "#raw(block: true, \"one line\") #raw(block: false, \"a ` b\")",
+            ),
+            (r"\*not italic\* $y$", "\\*not italic\\* $y$"),
+            ("#ce(\"H2O\") is $H_2 O$", "#ce(\"H2O\") is $H_2 O$"),
+            (
+                r"\ce{Na+ + Cl-} and **$x$**",
+                "#ce(\"Na+ + Cl-\") and #strong[$x$]",
+            ),
+            ("@Nano, $1 + 1$?", "\\@Nano, $1 + 1$?"),
+        ];
+        for (msg, expected) in cases {
+            assert_eq!(typst(msg), expected, "{:?}", msg);
+        }
+    }
+
+    #[test]
+    fn test_latex_math() {
+        let settings = RenderSettings {
+            latex_macros: "\\newcommand{\\R}{\\mathbb{R}}\n".to_string(),
+            ..RenderSettings::default()
+        };
+        assert_eq!(
+            message_document("$\\R$ and $$x$$", Target::TypstFromLatex, &settings)
+                .unwrap()
+                .source,
+            "#let latex-macros = \"\\\\newcommand{\\\\R}{\\\\mathbb{R}}\\n\";\
+             #mi(latex-macros + `\\R`.text) and #mitex(latex-macros + `x`.text)"
+        );
+        assert_eq!(
+            message_document(
+                "no macros \\(x\\)",
+                Target::Typst,
+                &RenderSettings::default()
+            )
+            .unwrap()
+            .source,
+            "#let latex-macros = \"\";no macros #mi(latex-macros + `x`.text)"
+        );
+        assert_eq!(
+            message_document(
+                "**50%** of $x$ is \\[\\frac{x}{2}\\] `a_b`",
+                Target::Latex,
+                &RenderSettings::default()
+            )
+            .unwrap()
+            .source,
+            "\\setlength{\\parindent}{0pt}\\textbf{50\\%} of \\(x\\) is \\[\\frac{x}{2}\\] \\texttt{a\\_b}"
+        );
+    }
+
+    #[test]
+    fn test_chemistry() {
+        let document = message_document(
+            r"so \ce{H2 + O2 -> H2O} happens",
+            Target::Typst,
+            &RenderSettings::default(),
+        )
+        .unwrap();
+        assert_eq!(document.source, "so #ce(\"2H2 + O2 -> 2H2O\") happens");
+        assert_eq!(document.notes, vec!["Balanced: `2H2 + O2 -> 2H2O`"]);
+
+        let error = message_document(
+            r"\ce{Xx2O} and $x$",
+            Target::Latex,
+            &RenderSettings::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "There's no element Xx. Did you mean Xe (xenon)?"
+        );
+    }
+}
@@ -0,0 +1,865 @@
+//! Chemistry: formulas and reactions like `2H2 + O2 -> 2H2O`, rendered with whalogen's `#ce`.
+//!
+//! Formulas are checked against the periodic table before they're rendered, so typos get an error
+//! that says what's wrong instead of a render of something that isn't chemistry. Reactions that
+//! don't balance are balanced, when there's exactly one way to do it.
+//!
+//! Anything using whalogen's own syntax for isotopes (`@...@`) or Typst code (`#`) is passed through
+//! as written.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Result};
+use poise::serenity_prelude::AttachmentType;
+use regex::Regex;
+
+use crate::math_markup::{
+    chat_message::typst_string, typst_base::typst_render, typst_main::render_settings,
+};
+use crate::utils::{Context, Error};
+
+/// Every element, by atomic number.
+const ELEMENTS: [(&str, &str); 118] = [
+    ("H", "hydrogen"),
+    ("He", "helium"),
+    ("Li", "lithium"),
+    ("Be", "beryllium"),
+    ("B", "boron"),
+    ("C", "carbon"),
+    ("N", "nitrogen"),
+    ("O", "oxygen"),
+    ("F", "fluorine"),
+    ("Ne", "neon"),
+    ("Na", "sodium"),
+    ("Mg", "magnesium"),
+    ("Al", "aluminium"),
+    ("Si", "silicon"),
+    ("P", "phosphorus"),
+    ("S", "sulfur"),
+    ("Cl", "chlorine"),
+    ("Ar", "argon"),
+    ("K", "potassium"),
+    ("Ca", "calcium"),
+    ("Sc", "scandium"),
+    ("Ti", "titanium"),
+    ("V", "vanadium"),
+    ("Cr", "chromium"),
+    ("Mn", "manganese"),
+    ("Fe", "iron"),
+    ("Co", "cobalt"),
+    ("Ni", "nickel"),
+    ("Cu", "copper"),
+    ("Zn", "zinc"),
+    ("Ga", "gallium"),
+    ("Ge", "germanium"),
+    ("As", "arsenic"),
+    ("Se", "selenium"),
+    ("Br", "bromine"),
+    ("Kr", "krypton"),
+    ("Rb", "rubidium"),
+    ("Sr", "strontium"),
+    ("Y", "yttrium"),
+    ("Zr", "zirconium"),
+    ("Nb", "niobium"),
+    ("Mo", "molybdenum"),
+    ("Tc", "technetium"),
+    ("Ru", "ruthenium"),
+    ("Rh", "rhodium"),
+    ("Pd", "palladium"),
+    ("Ag", "silver"),
+    ("Cd", "cadmium"),
+    ("In", "indium"),
+    ("Sn", "tin"),
+    ("Sb", "antimony"),
+    ("Te", "tellurium"),
+    ("I", "iodine"),
+    ("Xe", "xenon"),
+    ("Cs", "caesium"),
+    ("Ba", "barium"),
+    ("La", "lanthanum"),
+    ("Ce", "cerium"),
+    ("Pr", "praseodymium"),
+    ("Nd", "neodymium"),
+    ("Pm", "promethium"),
+    ("Sm", "samarium"),
+    ("Eu", "europium"),
+    ("Gd", "gadolinium"),
+    ("Tb", "terbium"),
+    ("Dy", "dysprosium"),
+    ("Ho", "holmium"),
+    ("Er", "erbium"),
+    ("Tm", "thulium"),
+    ("Yb", "ytterbium"),
+    ("Lu", "lutetium"),
+    ("Hf", "hafnium"),
+    ("Ta", "tantalum"),
+    ("W", "tungsten"),
+    ("Re", "rhenium"),
+    ("Os", "osmium"),
+    ("Ir", "iridium"),
+    ("Pt", "platinum"),
+    ("Au", "gold"),
+    ("Hg", "mercury"),
+    ("Tl", "thallium"),
+    ("Pb", "lead"),
+    ("Bi", "bismuth"),
+    ("Po", "polonium"),
+    ("At", "astatine"),
+    ("Rn", "radon"),
+    ("Fr", "francium"),
+    ("Ra", "radium"),
+    ("Ac", "actinium"),
+    ("Th", "thorium"),
+    ("Pa", "protactinium"),
+    ("U", "uranium"),
+    ("Np", "neptunium"),
+    ("Pu", "plutonium"),
+    ("Am", "americium"),
+    ("Cm", "curium"),
+    ("Bk", "berkelium"),
+    ("Cf", "californium"),
+    ("Es", "einsteinium"),
+    ("Fm", "fermium"),
+    ("Md", "mendelevium"),
+    ("No", "nobelium"),
+    ("Lr", "lawrencium"),
+    ("Rf", "rutherfordium"),
+    ("Db", "dubnium"),
+    ("Sg", "seaborgium"),
+    ("Bh", "bohrium"),
+    ("Hs", "hassium"),
+    ("Mt", "meitnerium"),
+    ("Ds", "darmstadtium"),
+    ("Rg", "roentgenium"),
+    ("Cn", "copernicium"),
+    ("Nh", "nihonium"),
+    ("Fl", "flerovium"),
+    ("Mc", "moscovium"),
+    ("Lv", "livermorium"),
+    ("Ts", "tennessine"),
+    ("Og", "oganesson"),
+];
+
+/// The arrows whalogen draws, longest first so `<->` isn't read as `<-`. `<=>` and `=` are read as
+/// the arrows they stand for.
+const ARROWS: [(&str, &str); 6] = [
+    ("<-->", "<-->"),
+    ("<=>", "<-->"),
+    ("<->", "<->"),
+    ("->", "->"),
+    ("<-", "<-"),
+    ("=", "->"),
+];
+
+/// The `+` between the terms of a reaction, which needs spaces around it to tell it from a charge.
+const PLUS_PATTERN: &str = r"\s+\+\s+";
+
+/// A state of matter at the end of a term, like `(aq)`.
+const STATE_PATTERN: &str = r"\((s|l|g|aq)\)$";
+
+static PLUS_RE: OnceLock<Regex> = OnceLock::new();
+static STATE_RE: OnceLock<Regex> = OnceLock::new();
+
+/// The most terms a reaction can have to be balanced.
+const MAX_BALANCED_TERMS: usize = 12;
+
+/// The biggest number that can be written in chemistry, and the most atoms of an element (or the
+/// biggest charge) a formula can have.
+const MAX_COUNT: i64 = 1_000_000;
+
+/// What a formula is made of.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Composition {
+    /// How many atoms of each element there are, by symbol.
+    atoms: BTreeMap<&'static str, i64>,
+    charge: i64,
+}
+
+impl Composition {
+    /// Adds some number of another composition, failing if there'd be more than [MAX_COUNT] of
+    /// anything.
+    fn add(&mut self, other: &Composition, times: i64) -> Result<()> {
+        let sum = |total: i64, count: i64| {
+            count
+                .checked_mul(times)
+                .and_then(|count| total.checked_add(count))
+                .filter(|total| total.abs() <= MAX_COUNT)
+                .ok_or_else(|| anyhow!("That's too many atoms for me to count."))
+        };
+        for (element, &count) in &other.atoms {
+            let total = self.atoms.entry(element).or_default();
+            *total = sum(*total, count)?;
+        }
+        self.charge = sum(self.charge, other.charge)?;
+        Ok(())
+    }
+}
+
+/// One term of a reaction, like `2H2O(l)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Term {
+    coefficient: Option<i64>,
+    /// The formula as written, without its coefficient or state.
+    formula: String,
+    /// The state of matter as written, like `(aq)`.
+    state: Option<String>,
+    composition: Composition,
+}
+
+impl Term {
+    /// Writes the term with another coefficient, leaving out a coefficient of 1.
+    fn write(&self, coefficient: i64) -> String {
+        let coefficient = if coefficient == 1 {
+            String::new()
+        } else {
+            coefficient.to_string()
+        };
+        format!(
+            "{}{}{}",
+            coefficient,
+            self.formula,
+            self.state.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// A reaction, or just some formulas if there's no arrow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reaction {
+    reactants: Vec<Term>,
+    /// The arrow and any text over it, like `->[heat]`.
+    arrow: Option<String>,
+    products: Vec<Term>,
+}
+
+impl Reaction {
+    /// The coefficients as written, with the ones left out being 1.
+    fn coefficients(&self) -> Vec<i64> {
+        self.reactants
+            .iter()
+            .chain(&self.products)
+            .map(|t| t.coefficient.unwrap_or(1))
+            .collect()
+    }
+
+    /// Writes the reaction with other coefficients, in whalogen's syntax.
+    fn write(&self, coefficients: &[i64]) -> String {
+        let mut coefficients = coefficients.iter();
+        let mut side = |terms: &[Term]| {
+            terms
+                .iter()
+                .map(|t| t.write(*coefficients.next().expect("A coefficient per term")))
+                .collect::<Vec<_>>()
+                .join(" + ")
+        };
+        let reactants = side(&self.reactants);
+        match &self.arrow {
+            Some(arrow) => format!("{} {} {}", reactants, arrow, side(&self.products)),
+            None => reactants,
+        }
+    }
+}
+
+/// Reads a number written in some chemistry, which can't be bigger than [MAX_COUNT].
+fn parse_count(digits: &str, chemistry: &str) -> Result<i64> {
+    digits
+        .parse()
+        .ok()
+        .filter(|&count| count <= MAX_COUNT)
+        .ok_or_else(|| anyhow!("{} is too big a number for {}.", digits, chemistry))
+}
+
+/// Looks up an element by its symbol.
+fn element(symbol: &str) -> Option<&'static str> {
+    ELEMENTS.iter().map(|(s, _)| *s).find(|s| *s == symbol)
+}
+
+/// The error for a symbol that isn't an element, suggesting ones that are close.
+fn unknown_element(symbol: &str) -> anyhow::Error {
+    let suggestions: Vec<String> = ELEMENTS
+        .iter()
+        .filter(|(s, _)| {
+            edit_distance::edit_distance(&s.to_lowercase(), &symbol.to_lowercase()) <= 1
+        })
+        .take(3)
+        .map(|(s, name)| format!("{} ({})", s, name))
+        .collect();
+    match suggestions.as_slice() {
+        [] => anyhow!("There's no element {}.", symbol),
+        [only] => anyhow!("There's no element {}. Did you mean {}?", symbol, only),
+        [rest @ .., last] => anyhow!(
+            "There's no element {}. Did you mean {} or {}?",
+            symbol,
+            rest.join(", "),
+            last
+        ),
+    }
+}
+
+/// Reads formulas like `Ca(OH)2`, `CuSO4·5H2O` or `SO4^2-`.
+struct FormulaParser<'a> {
+    formula: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl<'a> FormulaParser<'a> {
+    fn new(formula: &'a str) -> Self {
+        Self {
+            formula,
+            chars: formula.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn number(&mut self) -> Result<Option<i64>> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Ok(None);
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        parse_count(&digits, self.formula).map(Some)
+    }
+
+    /// Reads a whole formula, with any waters of hydration and charge.
+    fn formula(&mut self) -> Result<Composition> {
+        let mut composition = Composition::default();
+        loop {
+            let times = self.number()?.unwrap_or(1);
+            let part = self.group(None)?;
+            composition.add(&part, times)?;
+            match self.peek() {
+                Some('·' | '•' | '*' | '.') => self.pos += 1,
+                _ => break,
+            }
+        }
+        composition.charge = self.charge()?;
+        if let Some(c) = self.peek() {
+            bail!("I don't know what `{}` means in {}.", c, self.formula);
+        }
+        if composition.atoms.is_empty() {
+            bail!("{} doesn't have any elements in it.", self.formula);
+        }
+        Ok(composition)
+    }
+
+    /// Reads elements and groups up to the end of a group, or the end of the formula if it isn't in
+    /// a group.
+    fn group(&mut self, close: Option<char>) -> Result<Composition> {
+        let mut composition = Composition::default();
+        loop {
+            let Some(c) = self.peek() else {
+                if let Some(close) = close {
+                    bail!("There's a `{}` missing in {}.", close, self.formula);
+                }
+                break;
+            };
+            if Some(c) == close {
+                self.pos += 1;
+                break;
+            }
+            match c {
+                '(' | '[' | '{' => {
+                    self.pos += 1;
+                    let closing = match c {
+                        '(' => ')',
+                        '[' => ']',
+                        _ => '}',
+                    };
+                    let inner = self.group(Some(closing))?;
+                    let times = self.number()?.unwrap_or(1);
+                    composition.add(&inner, times)?;
+                }
+                ')' | ']' | '}' => bail!("There's an unmatched `{}` in {}.", c, self.formula),
+                c if c.is_ascii_uppercase() => {
+                    let mut symbol = c.to_string();
+                    self.pos += 1;
+                    if let Some(next) = self.peek().filter(char::is_ascii_lowercase) {
+                        symbol.push(next);
+                        self.pos += 1;
+                    }
+                    let element = element(&symbol).ok_or_else(|| unknown_element(&symbol))?;
+                    let count = self.number()?.unwrap_or(1);
+                    let atom = Composition {
+                        atoms: BTreeMap::from([(element, 1)]),
+                        charge: 0,
+                    };
+                    composition.add(&atom, count)?;
+                }
+                c if c.is_ascii_lowercase() => {
+                    let symbol: String = self.chars[self.pos..]
+                        .iter()
+                        .take_while(|c| c.is_ascii_lowercase())
+                        .take(2)
+                        .collect();
+                    let mut hint = symbol.clone();
+                    hint[..1].make_ascii_uppercase();
+                    if element(&hint).is_some() {
+                        bail!(
+                            "Element symbols start with a capital letter: did you mean {} instead of {}?",
+                            hint,
+                            symbol
+                        );
+                    }
+                    bail!(
+                        "Element symbols start with a capital letter, so I can't read {}.",
+                        self.formula
+                    );
+                }
+                // a charge or waters of hydration, which the whole formula handles
+                '^' | '+' | '-' | '·' | '•' | '*' | '.' if close.is_none() => break,
+                c => bail!("I don't know what `{}` means in {}.", c, self.formula),
+            }
+        }
+        Ok(composition)
+    }
+
+    /// Reads a charge at the end of a formula, like `^2-`, `^-` or just `+`.
+    fn charge(&mut self) -> Result<i64> {
+        let caret = self.peek() == Some('^');
+        if caret {
+            self.pos += 1;
+        }
+        let before = self.number()?;
+        let sign = match self.peek() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ if caret => bail!("The charge in {} needs a + or -.", self.formula),
+            _ => return Ok(0),
+        };
+        self.pos += 1;
+        let after = self.number()?;
+        if before.is_some() && after.is_some() {
+            bail!("The charge in {} has two numbers.", self.formula);
+        }
+        Ok(sign * before.or(after).unwrap_or(1))
+    }
+}
+
+/// Reads a formula.
+fn parse_formula(formula: &str) -> Result<Composition> {
+    // the electron, the only lowercase thing in a reaction
+    if matches!(formula, "e" | "e-" | "e^-") {
+        return Ok(Composition {
+            atoms: BTreeMap::new(),
+            charge: -1,
+        });
+    }
+    FormulaParser::new(formula).formula()
+}
+
+/// Reads a term of a reaction, like `2H2O(l)`.
+fn parse_term(term: &str) -> Result<Term> {
+    let state_re = STATE_RE.get_or_init(|| Regex::new(STATE_PATTERN).unwrap());
+    let term = term.trim();
+    let digits = term.len() - term.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let coefficient = match &term[..digits] {
+        "" => None,
+        digits => Some(parse_count(digits, term)?),
+    };
+    let rest = term[digits..].trim_start();
+    let (formula, state) = match state_re.find(rest) {
+        Some(state) => (&rest[..state.start()], Some(state.as_str().to_string())),
+        None => (rest, None),
+    };
+    if formula.is_empty() {
+        bail!("There's a term missing a formula.");
+    }
+    if coefficient == Some(0) {
+        bail!("{} has a coefficient of 0.", term);
+    }
+    Ok(Term {
+        coefficient,
+        formula: formula.to_string(),
+        state,
+        composition: parse_formula(formula)?,
+    })
+}
+
+/// Reads the terms on one side of a reaction.
+fn parse_side(side: &str) -> Result<Vec<Term>> {
+    let plus_re = PLUS_RE.get_or_init(|| Regex::new(PLUS_PATTERN).unwrap());
+    if side.trim().is_empty() {
+        bail!("One side of the reaction is empty.");
+    }
+    plus_re.split(side.trim()).map(parse_term).collect()
+}
+
+/// Finds the first arrow in a reaction: where it starts, how it's written and the arrow it stands
+/// for.
+fn find_arrow(reaction: &str) -> Option<(usize, &'static str, &'static str)> {
+    (0..reaction.len())
+        .filter(|&i| reaction.is_char_boundary(i))
+        .find_map(|i| {
+            ARROWS
+                .iter()
+                .find(|(written, _)| reaction[i..].starts_with(written))
+                .map(|(written, arrow)| (i, *written, *arrow))
+        })
+}
+
+/// Reads a reaction, or some formulas if there's no arrow.
+fn parse_reaction(reaction: &str) -> Result<Reaction> {
+    let Some((start, written, arrow)) = find_arrow(reaction) else {
+        return Ok(Reaction {
+            reactants: parse_side(reaction)?,
+            arrow: None,
+            products: vec![],
+        });
+    };
+    // keep any text over the arrow, like ->[heat]
+    let after = &reaction[start + written.len()..];
+    let over = match after.find(']') {
+        Some(end) if after.starts_with('[') => &after[..=end],
+        _ => "",
+    };
+    let products = &after[over.len()..];
+    if find_arrow(products).is_some() {
+        bail!("Reactions can only have one arrow: write each step separately.");
+    }
+    Ok(Reaction {
+        reactants: parse_side(&reaction[..start])?,
+        arrow: Some(format!("{}{}", arrow, over)),
+        products: parse_side(products)?,
+    })
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// How much of each element (and charge) each term brings, with products counting against
+/// reactants: a row per element, and a column per term.
+fn balance_matrix(reaction: &Reaction) -> Vec<Vec<i128>> {
+    let terms: Vec<(&Term, i128)> = reaction
+        .reactants
+        .iter()
+        .map(|t| (t, 1))
+        .chain(reaction.products.iter().map(|t| (t, -1)))
+        .collect();
+    let elements: BTreeSet<&str> = terms
+        .iter()
+        .flat_map(|(t, _)| t.composition.atoms.keys().copied())
+        .collect();
+    let mut rows: Vec<Vec<i128>> = elements
+        .iter()
+        .map(|e| {
+            terms
+                .iter()
+                .map(|(t, sign)| sign * *t.composition.atoms.get(e).unwrap_or(&0) as i128)
+                .collect()
+        })
+        .collect();
+    rows.push(
+        terms
+            .iter()
+            .map(|(t, sign)| sign * t.composition.charge as i128)
+            .collect(),
+    );
+    rows
+}
+
+/// Whether a reaction balances with some coefficients.
+fn is_balanced(reaction: &Reaction, coefficients: &[i64]) -> bool {
+    balance_matrix(reaction).iter().all(|row| {
+        row.iter()
+            .zip(coefficients)
+            .map(|(a, &c)| a * c as i128)
+            .sum::<i128>()
+            == 0
+    })
+}
+
+/// Finds the smallest whole coefficients that balance a reaction, if there's only one way to.
+fn balance(reaction: &Reaction) -> Result<Vec<i64>> {
+    let too_big = || anyhow!("The coefficients get too big.");
+    let mut rows = balance_matrix(reaction);
+    let columns = reaction.reactants.len() + reaction.products.len();
+    if columns > MAX_BALANCED_TERMS {
+        bail!("That's too many terms for me to balance.");
+    }
+
+    // row reduce, keeping everything whole
+    let mut pivots = vec![];
+    for column in 0..columns {
+        let rank = pivots.len();
+        let Some(pivot) = (rank..rows.len()).find(|&r| rows[r][column] != 0) else {
+            continue;
+        };
+        rows.swap(rank, pivot);
+        for r in 0..rows.len() {
+            if r == rank || rows[r][column] == 0 {
+                continue;
+            }
+            let (a, b) = (rows[rank][column], rows[r][column]);
+            let row: Vec<i128> = rows[r]
+                .iter()
+                .zip(&rows[rank])
+                .map(|(x, y)| x.checked_mul(a)?.checked_sub(y.checked_mul(b)?))
+                .collect::<Option<_>>()
+                .ok_or_else(too_big)?;
+            let divisor = row.iter().fold(0, |g, &x| gcd(g, x)).max(1);
+            rows[r] = row.into_iter().map(|x| x / divisor).collect();
+        }
+        pivots.push(column);
+    }
+
+    let free: Vec<usize> = (0..columns).filter(|c| !pivots.contains(c)).collect();
+    let free = match free.as_slice() {
+        [] => bail!("There's no way to balance this reaction."),
+        [free] => *free,
+        _ => bail!(
+            "There's more than one way to balance this reaction, so add the coefficients yourself."
+        ),
+    };
+
+    // each pivot row says pivot * x + row[free] * x_free = 0
+    let scale = pivots
+        .iter()
+        .enumerate()
+        .try_fold(1, |l: i128, (r, &c)| {
+            (l / gcd(l, rows[r][c])).checked_mul(rows[r][c].abs())
+        })
+        .ok_or_else(too_big)?;
+    let mut solution = vec![0; columns];
+    solution[free] = scale;
+    for (r, &c) in pivots.iter().enumerate() {
+        solution[c] = rows[r][free].checked_mul(scale).ok_or_else(too_big)? / -rows[r][c];
+    }
+    let divisor = solution.iter().fold(0, |g, &x| gcd(g, x));
+    let sign = if solution[0] < 0 { -1 } else { 1 };
+    let solution: Vec<i128> = solution.iter().map(|x| sign * x / divisor).collect();
+    if solution.iter().any(|&x| x <= 0) {
+        bail!("There's no way to balance this reaction.");
+    }
+    solution
+        .into_iter()
+        .map(|x| i64::try_from(x).map_err(|_| too_big()))
+        .collect()
+}
+
+/// Checks some chemistry and balances it if needed, giving whalogen's syntax for it and a note on
+/// any balancing.
+pub(crate) fn prepare(chemistry: &str, balance_it: bool) -> Result<(String, Option<String>)> {
+    let chemistry = chemistry.trim();
+    // whalogen's isotopes and Typst code, which are taken as written
+    if chemistry.contains('@') || chemistry.contains('#') {
+        return Ok((chemistry.to_string(), None));
+    }
+    let reaction = parse_reaction(chemistry)?;
+    let written = reaction.coefficients();
+    if reaction.arrow.is_none() || is_balanced(&reaction, &written) {
+        return Ok((reaction.write(&written), None));
+    }
+    if !balance_it {
+        return Ok((
+            reaction.write(&written),
+            Some("This reaction doesn't balance.".to_string()),
+        ));
+    }
+    match balance(&reaction) {
+        Ok(coefficients) => {
+            let balanced = reaction.write(&coefficients);
+            let note = format!("Balanced: `{}`", balanced);
+            Ok((balanced, Some(note)))
+        }
+        Err(e) => Ok((
+            reaction.write(&written),
+            Some(format!("This reaction doesn't balance. {}", e)),
+        )),
+    }
+}
+
+/// Renders chemistry, like `2H2 + O2 -> 2H2O` or `CuSO4·5H2O(s)`, balancing reactions that aren't.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    track_edits,
+    invoke_on_edit,
+    reuse_response,
+    track_deletion
+)]
+pub(crate) async fn chem(
+    ctx: Context<'_>,
+    #[description = "Balance reactions that don't: default true."] balance: Option<bool>,
+    #[description = "Formulas or a reaction, like 2H2 + O2 -> 2H2O."]
+    #[rest]
+    chemistry: String,
+) -> Result<(), Error> {
+    let (ce, note) = prepare(&chemistry, balance.unwrap_or(true))?;
+    let settings =
+        render_settings(ctx.author().id, ctx.guild_id(), ctx.data().storage.as_ref()).await?;
+    let im = typst_render(&format!("#ce({})", typst_string(&ce)), &settings).await?;
+
+    let mut content = format!("`{}`", &chemistry);
+    if let Some(note) = note {
+        content.push('\n');
+        content.push_str(&note);
+    }
+    ctx.send(|m| {
+        m.content(content).attachment(AttachmentType::Bytes {
+            data: im.into(),
+            filename: "Rendered.png".into(),
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atoms(formula: &str) -> Vec<(&'static str, i64)> {
+        parse_formula(formula).unwrap().atoms.into_iter().collect()
+    }
+
+    #[test]
+    fn test_parse_formula() {
+        assert_eq!(atoms("H2O"), vec![("H", 2), ("O", 1)]);
+        assert_eq!(atoms("Ca(OH)2"), vec![("Ca", 1), ("H", 2), ("O", 2)]);
+        assert_eq!(
+            atoms("CuSO4·5H2O"),
+            vec![("Cu", 1), ("H", 10), ("O", 9), ("S", 1)]
+        );
+        assert_eq!(
+            atoms("K4[Fe(CN)6]"),
+            vec![("C", 6), ("Fe", 1), ("K", 4), ("N", 6)]
+        );
+        assert_eq!(parse_formula("SO4^2-").unwrap().charge, -2);
+        assert_eq!(parse_formula("Na+").unwrap().charge, 1);
+        assert_eq!(parse_formula("Fe^3+").unwrap().charge, 3);
+        assert_eq!(parse_formula("e^-").unwrap().charge, -1);
+
+        let error = |formula: &str| parse_formula(formula).unwrap_err().to_string();
+        assert_eq!(
+            error("Xy2"),
+            "There's no element Xy. Did you mean Y (yttrium), Xe (xenon) or Dy (dysprosium)?"
+        );
+        assert_eq!(
+            error("nacl"),
+            "Element symbols start with a capital letter: did you mean Na instead of na?"
+        );
+        assert_eq!(error("Ca(OH2"), "There's a `)` missing in Ca(OH2.");
+        assert_eq!(error("H2O%"), "I don't know what `%` means in H2O%.");
+    }
+
+    #[test]
+    fn test_too_big() {
+        let error = |formula: &str| parse_formula(formula).unwrap_err().to_string();
+        assert_eq!(
+            error("H99999999999999999999"),
+            "99999999999999999999 is too big a number for H99999999999999999999."
+        );
+        assert_eq!(
+            error("(H999999999)99999999999"),
+            "999999999 is too big a number for (H999999999)99999999999."
+        );
+        assert_eq!(
+            error("(H1000)1001"),
+            "That's too many atoms for me to count."
+        );
+        assert_eq!(atoms("(H1000)1000"), vec![("H", 1_000_000)]);
+        assert_eq!(
+            parse_term("99999999999999999999H2O")
+                .unwrap_err()
+                .to_string(),
+            "99999999999999999999 is too big a number for 99999999999999999999H2O."
+        );
+
+        let reaction = parse_reaction("H999983 + O999979 -> H2O").unwrap();
+        assert_eq!(
+            reaction.write(&balance(&reaction).unwrap()),
+            "1999958H999983 + 999983O999979 -> 999962000357H2O"
+        );
+        let reaction =
+            parse_reaction("H999983O999979 + C999961 -> H999959C999953 + O999931N999917 + N999907")
+                .unwrap();
+        assert_eq!(
+            balance(&reaction).unwrap_err().to_string(),
+            "The coefficients get too big."
+        );
+    }
+
+    #[test]
+    fn test_parse_reaction() {
+        let reaction = parse_reaction("2H2(g) + O2(g) ->[spark] 2H2O(l)").unwrap();
+        assert_eq!(reaction.arrow.as_deref(), Some("->[spark]"));
+        assert_eq!(reaction.reactants[0].coefficient, Some(2));
+        assert_eq!(reaction.reactants[0].state.as_deref(), Some("(g)"));
+        assert_eq!(reaction.products[0].formula, "H2O");
+        assert_eq!(
+            reaction.write(&reaction.coefficients()),
+            "2H2(g) + O2(g) ->[spark] 2H2O(l)"
+        );
+        assert_eq!(
+            parse_reaction("N2 + 3H2 <=> 2NH3")
+                .unwrap()
+                .arrow
+                .as_deref(),
+            Some("<-->")
+        );
+        assert!(parse_reaction("A -> B -> C").is_err());
+        assert!(parse_reaction("H2 + -> H2O").is_err());
+    }
+
+    #[test]
+    fn test_balance() {
+        let cases = [
+            ("H2 + O2 -> H2O", Some("2H2 + O2 -> 2H2O")),
+            ("C3H8 + O2 -> CO2 + H2O", Some("C3H8 + 5O2 -> 3CO2 + 4H2O")),
+            (
+                "Fe^3+ + OH^- -> Fe(OH)3(s)",
+                Some("Fe^3+ + 3OH^- -> Fe(OH)3(s)"),
+            ),
+            ("Cu + Ag+ -> Cu^2+ + Ag", Some("Cu + 2Ag+ -> Cu^2+ + 2Ag")),
+            (
+                "KMnO4 + HCl -> KCl + MnCl2 + H2O + Cl2",
+                Some("2KMnO4 + 16HCl -> 2KCl + 2MnCl2 + 8H2O + 5Cl2"),
+            ),
+            ("H2 -> O2", None),
+            ("H2 + O2 -> H2O + H2O2", None),
+        ];
+        for (reaction, expected) in cases {
+            let reaction = parse_reaction(reaction).unwrap();
+            let balanced = balance(&reaction).ok().map(|c| reaction.write(&c));
+            assert_eq!(balanced.as_deref(), expected);
+        }
+    }
+
+    #[test]
+    fn test_prepare() {
+        assert_eq!(
+            prepare("2H2 + O2 -> 2H2O", true).unwrap(),
+            ("2H2 + O2 -> 2H2O".to_string(), None)
+        );
+        assert_eq!(
+            prepare("H2 + O2 = H2O", true).unwrap(),
+            (
+                "2H2 + O2 -> 2H2O".to_string(),
+                Some("Balanced: `2H2 + O2 -> 2H2O`".to_string())
+            )
+        );
+        assert_eq!(
+            prepare("H2 + O2 -> H2O", false).unwrap().1.as_deref(),
+            Some("This reaction doesn't balance.")
+        );
+        assert_eq!(
+            prepare("@U,235,92@ -> @Th,231,90@ + @He,4,2@", true).unwrap(),
+            ("@U,235,92@ -> @Th,231,90@ + @He,4,2@".to_string(), None)
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! Finds the math in chat messages.
+//!
+//! Messages are split into prose, code and math. Math is anything between Typst's `$...$` or LaTeX's
+//! `$$...$$`, `\(...\)` and `\[...\]`, except inside inline or fenced code, after an escaped `\$`, or
+//! when the dollar signs are money: a `$` followed by a digit doesn't end math, and math that starts
+//! with an amount like `$5 ` or `$1,000.50 ` has to end right after something other than a space, as
+//! in `$1 + 1$`.
+//!
+//! Chemistry can also be written inline as `\ce{...}`, like in LaTeX's mhchem, outside of math.
+
+/// The delimiters around a piece of math.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Delimiter {
+    /// `$...$`, Typst or LaTeX inline math.
+    Dollar,
+    /// `$$...$$`, LaTeX display math.
+    DoubleDollar,
+    /// `\(...\)`, LaTeX inline math.
+    Paren,
+    /// `\[...\]`, LaTeX display math.
+    Bracket,
+}
+
+impl Delimiter {
+    /// Whether only LaTeX uses these delimiters.
+    pub(crate) fn is_latex(&self) -> bool {
+        !matches!(self, Self::Dollar)
+    }
+
+    fn open(&self) -> &'static str {
+        match self {
+            Self::Dollar => "$",
+            Self::DoubleDollar => "$$",
+            Self::Paren => r"\(",
+            Self::Bracket => r"\[",
+        }
+    }
+
+    fn close(&self) -> &'static str {
+        match self {
+            Self::Dollar => "$",
+            Self::DoubleDollar => "$$",
+            Self::Paren => r"\)",
+            Self::Bracket => r"\]",
+        }
+    }
+}
+
+/// A piece of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Segment<'a> {
+    /// Prose.
+    Text(&'a str),
+    /// Inline or fenced code, with its backticks.
+    Code(&'a str),
+    /// Math, without its delimiters.
+    Math {
+        delimiter: Delimiter,
+        content: &'a str,
+    },
+    /// Chemistry written as `\ce{...}`, without the braces.
+    Chem(&'a str),
+}
+
+/// Whether text right after a `$` is an amount of money, like `5`, `1,000` or `4.99` followed by
+/// the end of a word.
+fn is_money(after: &str) -> bool {
+    if !after.starts_with(|c: char| c.is_ascii_digit()) {
+        return false;
+    }
+    let rest = after.trim_start_matches(|c: char| c.is_ascii_digit() || c == ',' || c == '.');
+    match rest.chars().next() {
+        None => true,
+        Some(c) => c.is_whitespace() || "!?;:)'\"".contains(c),
+    }
+}
+
+/// Finds the end of math starting at `from`, skipping escaped characters. A `$` followed by a digit
+/// doesn't count as the end of `$...$` math, since it's money.
+fn find_close(msg: &str, from: usize, delimiter: Delimiter) -> Option<usize> {
+    let close = delimiter.close();
+    let mut i = from;
+    while i < msg.len() {
+        let rest = &msg[i..];
+        if rest.starts_with(close) {
+            let after = &rest[close.len()..];
+            if delimiter != Delimiter::Dollar || !after.starts_with(|c: char| c.is_ascii_digit()) {
+                return Some(i);
+            }
+        }
+        // skip escapes, so \$ doesn't end math
+        i += if rest.starts_with('\\') {
+            1 + rest[1..].chars().next().map_or(0, char::len_utf8)
+        } else {
+            rest.chars().next().map_or(1, char::len_utf8)
+        };
+    }
+    None
+}
+
+/// Finds the end of code starting with a run of backticks at `from`, if it's closed.
+fn find_code_end(msg: &str, from: usize) -> Option<usize> {
+    let ticks = msg[from..].len() - msg[from..].trim_start_matches('`').len();
+    let fence = &msg[from..from + ticks];
+    msg[from + ticks..]
+        .find(fence)
+        .map(|i| from + ticks + i + ticks)
+}
+
+/// Finds the brace that closes a group starting at `from`, skipping escapes and nested groups.
+fn find_brace_end(msg: &str, from: usize) -> Option<usize> {
+    let mut depth = 1;
+    let mut escaped = false;
+    for (i, c) in msg[from..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(from + i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits a message into prose, code, math and chemistry.
+pub(crate) fn tokenize(msg: &str) -> Vec<Segment<'_>> {
+    let mut segments = vec![];
+    let mut text_start = 0;
+    let mut i = 0;
+
+    while i < msg.len() {
+        let rest = &msg[i..];
+        let delimiter = if rest.starts_with("$$") {
+            Some(Delimiter::DoubleDollar)
+        } else if rest.starts_with('$') {
+            Some(Delimiter::Dollar)
+        } else if rest.starts_with(r"\(") {
+            Some(Delimiter::Paren)
+        } else if rest.starts_with(r"\[") {
+            Some(Delimiter::Bracket)
+        } else {
+            None
+        };
+
+        let found = if rest.starts_with('`') {
+            find_code_end(msg, i).map(|end| (Segment::Code(&msg[i..end]), end))
+        } else if rest.starts_with(r"\ce{") {
+            let start = i + r"\ce{".len();
+            find_brace_end(msg, start)
+                .filter(|&end| !msg[start..end].trim().is_empty())
+                .map(|end| (Segment::Chem(&msg[start..end]), end + 1))
+        } else if let Some(delimiter) = delimiter {
+            let start = i + delimiter.open().len();
+            let money = delimiter == Delimiter::Dollar && is_money(&msg[start..]);
+            find_close(msg, start, delimiter)
+                .filter(|&end| !msg[start..end].trim().is_empty())
+                .filter(|&end| {
+                    let content = &msg[start..end];
+                    !money || !(content.ends_with(char::is_whitespace) || content.contains('$'))
+                })
+                .map(|end| {
+                    let segment = Segment::Math {
+                        delimiter,
+                        content: &msg[start..end],
+                    };
+                    (segment, end + delimiter.close().len())
+                })
+        } else {
+            None
+        };
+
+        match found {
+            Some((segment, end)) => {
+                if text_start < i {
+                    segments.push(Segment::Text(&msg[text_start..i]));
+                }
+                segments.push(segment);
+                i = end;
+                text_start = end;
+            }
+            None => {
+                // step over escapes and unmatched delimiters as plain text
+                let step = if rest.starts_with('\\') || rest.starts_with("$$") {
+                    1 + rest[1..].chars().next().map_or(0, char::len_utf8)
+                } else if rest.starts_with('`') {
+                    rest.len() - rest.trim_start_matches('`').len()
+                } else {
+                    rest.chars().next().map_or(1, char::len_utf8)
+                };
+                i += step;
+            }
+        }
+    }
+    if text_start < msg.len() {
+        segments.push(Segment::Text(&msg[text_start..]));
+    }
+    segments
+}
+
+/// Whether math looks like LaTeX: it uses LaTeX-only delimiters or has a command like `\frac`.
+pub(crate) fn looks_like_latex(msg: &str) -> bool {
+    tokenize(msg).iter().any(|s| match s {
+        Segment::Math { delimiter, content } => {
+            delimiter.is_latex()
+                || content
+                    .split('\\')
+                    .skip(1)
+                    .any(|after| after.starts_with(|c: char| c.is_ascii_alphabetic()))
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Delimiter::*;
+
+    fn has_math(msg: &str) -> bool {
+        tokenize(msg)
+            .iter()
+            .any(|s| matches!(s, Segment::Math { .. }))
+    }
+
+    #[test]
+    fn test_has_math() {
+        let cases = [
+            ("what's $x^2 + y^2$ when x = 3?", true),
+            ("$ sum_(i=1)^n i = (n(n+1))/2 $", true),
+            ("I paid $5 and $10 for lunch", false),
+            ("it's like $1,000.50 per month", false),
+            ("costs $5", false),
+            ("is $5$ prime?", true),
+            ("$2x + 1$", true),
+            ("the price went from $5 to $x$ dollars", true),
+            ("$1 + 1 = 2$", true),
+            ("$5 and $10 and $20", false),
+            ("use `$x$` for math", false),
+            ("```\nlet total = $price + $tax;\n```", false),
+            ("```tex\n$x$\n```\nbut $y$ renders", true),
+            (r"escaped \$x\$ isn't math", false),
+            (r"$$\int_0^1 x \, dx$$", true),
+            (r"inline \(\frac{1}{2}\) latex", true),
+            (r"display \[ e^{i\pi} + 1 = 0 \]", true),
+            ("just one $ sign", false),
+            ("empty $$ and $ $", false),
+            ("$a$$b$", true),
+            ("unclosed `code with $x$", true),
+            ("¿cuánto es $x²$?", true),
+        ];
+        for (msg, expected) in cases {
+            assert_eq!(has_math(msg), expected, "{:?}", msg);
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("if $x > 0$ then `$y$` is \\(x^2\\)."),
+            vec![
+                Segment::Text("if "),
+                Segment::Math {
+                    delimiter: Dollar,
+                    content: "x > 0"
+                },
+                Segment::Text(" then "),
+                Segment::Code("`$y$`"),
+                Segment::Text(" is "),
+                Segment::Math {
+                    delimiter: Paren,
+                    content: "x^2"
+                },
+                Segment::Text("."),
+            ]
+        );
+        assert_eq!(
+            tokenize(r"\ce{[Cu(H2O)4]^2+} is blue, but \ce{} and $\ce{H2O}$ aren't"),
+            vec![
+                Segment::Chem("[Cu(H2O)4]^2+"),
+                Segment::Text(r" is blue, but \ce{} and "),
+                Segment::Math {
+                    delimiter: Dollar,
+                    content: r"\ce{H2O}"
+                },
+                Segment::Text(" aren't"),
+            ]
+        );
+        assert_eq!(
+            tokenize("$5 and $10 for $x$"),
+            vec![
+                Segment::Text("$5 and $10 for "),
+                Segment::Math {
+                    delimiter: Dollar,
+                    content: "x"
+                },
+            ]
+        );
+        assert_eq!(
+            tokenize("$$ a \\$ b $$ costs $5"),
+            vec![
+                Segment::Math {
+                    delimiter: DoubleDollar,
+                    content: " a \\$ b "
+                },
+                Segment::Text(" costs $5"),
+            ]
+        );
+        assert_eq!(
+            tokenize("\\[x\\]``a ` b``"),
+            vec![
+                Segment::Math {
+                    delimiter: Bracket,
+                    content: "x"
+                },
+                Segment::Code("``a ` b``"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_looks_like_latex() {
+        let cases = [
+            (r"$\frac{1}{2}$", true),
+            ("$1/2$", false),
+            (r"$a \$ b$", false),
+            (r"\(x\)", true),
+            ("$$x$$", true),
+            (r"`$\frac{1}{2}$` and $x$", false),
+        ];
+        for (msg, expected) in cases {
+            assert_eq!(looks_like_latex(msg), expected, "{:?}", msg);
+        }
+    }
+}
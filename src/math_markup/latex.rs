@@ -1,4 +1,35 @@
-//! LaTeX rendering.
+//! LaTeX rendering with a local TeX installation, for everything mitex can't convert to Typst:
+//! environments, commutative diagrams and the like.
+//!
+//! Each message is compiled as its own `standalone` document in a fresh temporary directory, with shell
+//! escape off and TeX only allowed to touch files inside that directory. The PDF is then turned into a
+//! PNG with `pdftocairo`, in the user's theme like Typst output. Both steps share the configured timeout,
+//! after which the processes are killed.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use tokio::process::Command;
+
+use super::theme::Theme;
+use super::typst_base::{RenderErrors, RenderSettings};
+use crate::config::config;
+
+/// The program that turns PDFs into PNGs, from poppler.
+const CONVERTER: &str = "pdftocairo";
+
+/// The most TeX errors shown to the user.
+const MAX_ERRORS: usize = 5;
+
+/// Whether the TeX engine and converter are installed, checked once.
+static TEX_AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+/// Numbers render jobs, to keep their directories apart.
+static NEXT_JOB: AtomicUsize = AtomicUsize::new(0);
 
 const PREAMBLE: &str = r"
 % Required to support mathematical unicode
@@ -17,6 +48,8 @@ const PREAMBLE: &str = r"
 
 % Symbol and utility packages
 \usepackage{cancel, textcomp}
+\usepackage[normalem]{ulem}  % Strikethrough, for Discord's ~~
+\usepackage[version=4]{mhchem}  % Chemistry
 \usepackage[mathscr]{euscript}
 \usepackage[nointegrals]{wasysym}
 
@@ -54,3 +87,216 @@ const PREAMBLE: &str = r"
 
 \renewcommand{\div}{\divisionsymbol}
 ";
+
+/// Checks whether a program exists, either at the given path or on the `PATH`.
+fn find_program(name: &str) -> bool {
+    if name.contains(std::path::MAIN_SEPARATOR) {
+        return Path::new(name).is_file();
+    }
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
+}
+
+/// Whether LaTeX can be rendered natively. If not, it has to be converted to Typst instead.
+pub(crate) fn tex_available() -> bool {
+    *TEX_AVAILABLE.get_or_init(|| find_program(&config().latex.engine) && find_program(CONVERTER))
+}
+
+/// Formats a color for the `rgb` model of the `color` package.
+fn tex_color([r, g, b]: [u8; 3]) -> String {
+    let scale = |c: u8| f64::from(c) / 255.0;
+    format!("{:.3},{:.3},{:.3}", scale(r), scale(g), scale(b))
+}
+
+/// Wraps a message in a full document, with the user's own macros after the built-in ones and in
+/// the user's theme.
+fn document(msg: &str, settings: &RenderSettings) -> String {
+    let theme = &settings.theme;
+    format!(
+        r"\documentclass[preview, border=10pt]{{standalone}}
+{preamble}
+{macros}
+\changefontsizes{{{size}pt}}
+\definecolor{{nanofg}}{{rgb}}{{{fg}}}
+\definecolor{{nanobg}}{{rgb}}{{{bg}}}
+
+\begin{{document}}
+{page_color}\color{{nanofg}}
+{msg}
+\end{{document}}
+",
+        preamble = PREAMBLE,
+        macros = settings.latex_macros,
+        size = theme.size,
+        fg = tex_color(theme.colors.fg()),
+        bg = tex_color(theme.colors.bg()),
+        page_color = if theme.transparent {
+            ""
+        } else {
+            r"\pagecolor{nanobg}"
+        },
+        msg = msg
+    )
+}
+
+/// Picks the errors out of a TeX log: each starts with `!` and ends with the line it happened on.
+fn tex_errors(log: &str) -> String {
+    let mut errors = vec![];
+    let mut current: Option<String> = None;
+    for line in log.lines() {
+        if let Some(message) = line.strip_prefix("! ") {
+            current = Some(message.to_string());
+        } else if let Some(message) = current.take() {
+            if let Some(location) = line.strip_prefix("l.") {
+                errors.push(format!("{} (line {})", message, location.trim_end()));
+            } else {
+                current = Some(message);
+            }
+        }
+    }
+    errors.extend(current);
+
+    if errors.is_empty() {
+        "LaTeX couldn't render this.".to_string()
+    } else {
+        errors.truncate(MAX_ERRORS);
+        errors.join("\n")
+    }
+}
+
+/// A temporary directory for one render, removed when dropped.
+struct JobDir(PathBuf);
+
+impl JobDir {
+    fn create() -> std::io::Result<Self> {
+        let path = env::temp_dir().join(format!(
+            "nano-latex-{}-{}",
+            std::process::id(),
+            NEXT_JOB.fetch_add(1, Ordering::Relaxed)
+        ));
+        // a leftover from a crashed run with the same process ID would be in the way
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir(&path)?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for JobDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Describes a failure to run one of the programs.
+fn io_error(program: &str, e: std::io::Error) -> RenderErrors {
+    RenderErrors::TexError(format!("Couldn't run {}: {}", program, e))
+}
+
+/// Runs a program in the job directory, with nothing on stdin and no way to leave the directory.
+async fn run(program: &str, args: &[&str], dir: &Path) -> Result<bool, RenderErrors> {
+    let output = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .env("openin_any", "p")
+        .env("openout_any", "p")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| io_error(program, e))?;
+    Ok(output.status.success())
+}
+
+/// Compiles a message in a job directory and converts the result to a PNG.
+async fn compile(
+    msg: &str,
+    settings: &RenderSettings,
+    dir: &Path,
+) -> Result<Vec<u8>, RenderErrors> {
+    let latex = &config().latex;
+    tokio::fs::write(dir.join("nano.tex"), document(msg, settings))
+        .await
+        .map_err(|e| io_error(&latex.engine, e))?;
+
+    let compiled = run(
+        &latex.engine,
+        &[
+            "-no-shell-escape",
+            "-interaction=nonstopmode",
+            "-halt-on-error",
+            "nano.tex",
+        ],
+        dir,
+    )
+    .await?;
+    if !compiled {
+        let log = tokio::fs::read(dir.join("nano.log"))
+            .await
+            .unwrap_or_default();
+        return Err(RenderErrors::TexError(tex_errors(
+            &String::from_utf8_lossy(&log),
+        )));
+    }
+
+    // the configured resolution is for the default theme
+    let dpi = (latex.dpi * settings.theme.resolution / Theme::default().resolution).to_string();
+    let mut args = vec!["-png", "-singlefile", "-r", dpi.as_str()];
+    if settings.theme.transparent {
+        args.push("-transp");
+    }
+    args.extend(["nano.pdf", "nano"]);
+    let converted = run(CONVERTER, &args, dir).await?;
+    if !converted {
+        return Err(RenderErrors::TexError(
+            "Couldn't convert the rendered document to an image.".to_string(),
+        ));
+    }
+    tokio::fs::read(dir.join("nano.png"))
+        .await
+        .map_err(|e| io_error(CONVERTER, e))
+}
+
+/// Renders a LaTeX message to a PNG. Only works if [tex_available] says so.
+pub(crate) async fn latex_render(
+    msg: &str,
+    settings: &RenderSettings,
+) -> Result<Vec<u8>, RenderErrors> {
+    let dir = JobDir::create().map_err(|e| io_error(&config().latex.engine, e))?;
+    let limit = Duration::from_secs(config().latex.timeout);
+    tokio::time::timeout(limit, compile(msg, settings, &dir.0))
+        .await
+        .unwrap_or(Err(RenderErrors::Timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document() {
+        let mut settings = RenderSettings {
+            latex_macros: r"\newcommand{\iu}{\mathrm{i}}".to_string(),
+            ..RenderSettings::default()
+        };
+        let doc = document(r"$\bR^n$", &settings);
+        assert!(doc.starts_with(r"\documentclass"));
+        assert!(doc.contains(r"\usepackage{tikz-cd}"));
+        assert!(doc.contains("\\newcommand{\\iu}{\\mathrm{i}}\n\\changefontsizes{18pt}"));
+        assert!(doc.contains("{0.859,0.871,0.882}"));
+        assert!(doc.contains("\\pagecolor{nanobg}"));
+        assert!(doc.contains("\n$\\bR^n$\n\\end{document}"));
+
+        settings.theme.transparent = true;
+        assert!(!document("", &settings).contains("\\pagecolor"));
+    }
+
+    #[test]
+    fn test_tex_errors() {
+        let log = "This is pdfTeX\n! Undefined control sequence.\nl.58 $\\foo\n                  $\nmore\n! Emergency stop.\n";
+        assert_eq!(
+            tex_errors(log),
+            "Undefined control sequence. (line 58 $\\foo)\nEmergency stop."
+        );
+        assert_eq!(tex_errors("nothing wrong"), "LaTeX couldn't render this.");
+    }
+}
@@ -0,0 +1,433 @@
+//! User-defined macros: Typst or LaTeX definitions that users save once, for themselves or for
+//! everyone in a server, and that are then added to the preamble of every render they ask for.
+//!
+//! A library is a list of named definitions, kept in order so later ones can use earlier ones. A
+//! server's library comes before a user's own, so users can override the server's definitions.
+
+use crate::{
+    math_markup::{
+        latex::{latex_render, tex_available},
+        typst_base::{typst_render, RenderSettings},
+        typst_main::latex2typst,
+    },
+    storage::Storage,
+    utils::{Context, Error},
+};
+use anyhow::anyhow;
+use poise::ChoiceParameter;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{GuildId, UserId};
+
+/// The name macro libraries are stored under, both as a user preference and as a guild setting.
+const MACROS: &str = "typst-macros";
+
+/// The most macros a library can have.
+const MAX_MACROS: usize = 25;
+
+/// The most bytes the definitions in a library can add up to.
+const MAX_LIBRARY_SIZE: usize = 4000;
+
+/// The longest a macro's name can be.
+const MAX_NAME_LEN: usize = 32;
+
+/// How many characters of a definition are shown when listing macros.
+const MAX_SHOWN_LEN: usize = 100;
+
+/// How long a list of macros can get, leaving room to say how many didn't fit in a message.
+const MAX_LISTING_LEN: usize = 1900;
+
+/// The markup a macro is written in.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize, ChoiceParameter)]
+pub(crate) enum MacroLanguage {
+    Typst,
+    #[name = "LaTeX"]
+    Latex,
+}
+
+/// Whose macros to change.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, ChoiceParameter)]
+pub(crate) enum MacroScope {
+    #[name = "Mine"]
+    Personal,
+    #[name = "This server's"]
+    Server,
+}
+
+/// A saved definition.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Macro {
+    name: String,
+    language: MacroLanguage,
+    definition: String,
+}
+
+/// An ordered list of macros.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Library(Vec<Macro>);
+
+impl Library {
+    fn parse(raw: Option<String>) -> Self {
+        raw.and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn serialize(&self) -> String {
+        serde_json::to_string(self).expect("Macros are always serializable")
+    }
+
+    /// Adds a macro, replacing any other with the same name, unless that would make the library too
+    /// big.
+    fn add(&mut self, new: Macro) -> Result<(), Error> {
+        match self.0.iter_mut().find(|m| m.name == new.name) {
+            Some(old) => *old = new,
+            None => self.0.push(new),
+        }
+        let size: usize = self.0.iter().map(|m| m.definition.len()).sum();
+        if self.0.len() > MAX_MACROS {
+            Err(anyhow!("You can only have {} macros.", MAX_MACROS).into())
+        } else if size > MAX_LIBRARY_SIZE {
+            Err(anyhow!(
+                "Macros can only add up to {} characters, and these would be {}.",
+                MAX_LIBRARY_SIZE,
+                size
+            )
+            .into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Removes the macro with the given name, returning whether there was one.
+    fn remove(&mut self, name: &str) -> bool {
+        let len = self.0.len();
+        self.0.retain(|m| m.name != name);
+        self.0.len() != len
+    }
+
+    /// Adds the definitions in this library to render settings.
+    fn apply(&self, settings: &mut RenderSettings) {
+        for m in &self.0 {
+            let definitions = match m.language {
+                MacroLanguage::Typst => &mut settings.macros,
+                MacroLanguage::Latex => &mut settings.latex_macros,
+            };
+            definitions.push_str(&m.definition);
+            definitions.push('\n');
+        }
+    }
+
+    /// Lists the macros in this library, one line each, with long definitions cut short.
+    fn describe(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|m| {
+                let mut shown: String = m.definition.chars().take(MAX_SHOWN_LEN).collect();
+                if shown.len() < m.definition.len() {
+                    shown.push('…');
+                }
+                format!(
+                    "- **{}** ({}): `{}`",
+                    m.name,
+                    m.language,
+                    shown.replace('`', "\u{02cb}")
+                )
+            })
+            .collect()
+    }
+}
+
+/// Lists libraries under their headings, leaving out empty ones, and saying how many macros didn't
+/// fit if they don't all fit in a message.
+fn listing(libraries: &[(&str, &Library)]) -> String {
+    let total: usize = libraries.iter().map(|(_, library)| library.0.len()).sum();
+    if total == 0 {
+        return "No macros yet: add some with `/typst macros add`.".to_string();
+    }
+    let mut content = String::new();
+    let mut shown = 0;
+    'libraries: for (heading, library) in libraries {
+        for (i, line) in library.describe().iter().enumerate() {
+            let heading = match i {
+                0 => format!("# {}\n", heading),
+                _ => String::new(),
+            };
+            if content.len() + heading.len() + line.len() > MAX_LISTING_LEN {
+                content.push_str(&format!("...and {} more.", total - shown));
+                break 'libraries;
+            }
+            content.push_str(&heading);
+            content.push_str(line);
+            content.push('\n');
+            shown += 1;
+        }
+    }
+    content
+}
+
+async fn user_library(user: UserId, storage: &dyn Storage) -> Result<Library, Error> {
+    Ok(Library::parse(storage.preference(MACROS, user).await?))
+}
+
+async fn guild_library(guild: Option<GuildId>, storage: &dyn Storage) -> Result<Library, Error> {
+    match guild {
+        Some(guild) => Ok(Library::parse(storage.guild_setting(guild, MACROS).await?)),
+        None => Ok(Library::default()),
+    }
+}
+
+/// Adds the macros for a user's renders in a guild (or in DMs, if no guild is given) to render
+/// settings.
+pub(crate) async fn add_macros(
+    user: UserId,
+    guild: Option<GuildId>,
+    storage: &dyn Storage,
+    settings: &mut RenderSettings,
+) -> Result<(), Error> {
+    guild_library(guild, storage).await?.apply(settings);
+    user_library(user, storage).await?.apply(settings);
+    Ok(())
+}
+
+/// Checks that a definition compiles after the definitions it will follow.
+async fn check_compiles(new: &Macro, settings: &RenderSettings) -> Result<(), Error> {
+    match new.language {
+        MacroLanguage::Typst => typst_render(&new.definition, settings).await?,
+        MacroLanguage::Latex if tex_available() => {
+            latex_render(&format!("{}\n\\mbox{{}}", new.definition), settings).await?
+        }
+        MacroLanguage::Latex => {
+            let latex = format!("{}{}", settings.latex_macros, new.definition);
+            typst_render(&latex2typst(&latex), settings).await?
+        }
+    };
+    Ok(())
+}
+
+/// Whether the author of a command can manage the server it was used in.
+async fn can_manage_guild(ctx: Context<'_>) -> bool {
+    match ctx.author_member().await {
+        Some(member) => member
+            .permissions(ctx.serenity_context())
+            .is_ok_and(|p| p.manage_guild()),
+        None => false,
+    }
+}
+
+/// Works out which guild's library a command changes, if it changes a guild's library at all.
+async fn scope_guild(
+    ctx: Context<'_>,
+    scope: Option<MacroScope>,
+) -> Result<Option<GuildId>, Error> {
+    match scope.unwrap_or(MacroScope::Personal) {
+        MacroScope::Personal => Ok(None),
+        MacroScope::Server => match ctx.guild_id() {
+            Some(_) if !can_manage_guild(ctx).await => {
+                Err(anyhow!("Only people who can manage this server can change its macros.").into())
+            }
+            Some(guild) => Ok(Some(guild)),
+            None => Err(anyhow!("Server macros can only be changed in a server.").into()),
+        },
+    }
+}
+
+/// Parent command for macros. Does nothing on its own.
+#[poise::command(slash_command, subcommands("add", "list", "remove"))]
+pub(crate) async fn macros(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Saves a definition that's added to everything you render, or everything rendered in this
+/// server. Saving one with the name of another replaces it.
+#[poise::command(slash_command)]
+pub(crate) async fn add(
+    ctx: Context<'_>,
+    #[description = "Name to list and remove it by"] name: String,
+    #[description = "What it's written in"] language: MacroLanguage,
+    #[description = "The definition, e.g. #let R = $bb(R)$ or \\newcommand{\\R}{\\mathbb{R}}"]
+    definition: String,
+    #[description = "Whose macros to add it to: default yours"] scope: Option<MacroScope>,
+) -> Result<(), Error> {
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN || name.contains(char::is_whitespace)
+    {
+        return Err(anyhow!(
+            "Macro names need to be 1 to {} characters, without spaces.",
+            MAX_NAME_LEN
+        )
+        .into());
+    }
+    let storage = ctx.data().storage.as_ref();
+    let guild = scope_guild(ctx, scope).await?;
+    let new = Macro {
+        name,
+        language,
+        definition,
+    };
+
+    // the macro has to work after everything that comes before it when rendering, and fit in the
+    // library, which is checked first since it's much quicker
+    let mut settings = RenderSettings::default();
+    let mut library = match guild {
+        Some(guild) => guild_library(Some(guild), storage).await?,
+        None => {
+            guild_library(ctx.guild_id(), storage)
+                .await?
+                .apply(&mut settings);
+            user_library(ctx.author().id, storage).await?
+        }
+    };
+    let mut before = library.clone();
+    before.remove(&new.name);
+    library.add(new.clone())?;
+    before.apply(&mut settings);
+    check_compiles(&new, &settings).await?;
+
+    match guild {
+        Some(guild) => {
+            storage
+                .set_guild_setting(guild, MACROS, &library.serialize())
+                .await?
+        }
+        None => {
+            storage
+                .set_preference(MACROS, ctx.author().id, &library.serialize())
+                .await?
+        }
+    }
+
+    ctx.say(format!("Saved **{}**.", new.name)).await?;
+    Ok(())
+}
+
+/// Lists your macros and this server's.
+#[poise::command(slash_command)]
+pub(crate) async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let storage = ctx.data().storage.as_ref();
+    let guild = guild_library(ctx.guild_id(), storage).await?;
+    let user = user_library(ctx.author().id, storage).await?;
+    let content = listing(&[("This server's macros", &guild), ("Your macros", &user)]);
+
+    ctx.send(|m| m.content(content).allowed_mentions(|a| a.empty_parse()))
+        .await?;
+    Ok(())
+}
+
+/// Removes a macro.
+#[poise::command(slash_command)]
+pub(crate) async fn remove(
+    ctx: Context<'_>,
+    #[description = "Name of the macro"] name: String,
+    #[description = "Whose macros to remove it from: default yours"] scope: Option<MacroScope>,
+) -> Result<(), Error> {
+    let storage = ctx.data().storage.as_ref();
+    let guild = scope_guild(ctx, scope).await?;
+    let name = name.trim();
+
+    let removed = match guild {
+        Some(guild) => {
+            let mut library = guild_library(Some(guild), storage).await?;
+            let removed = library.remove(name);
+            if removed {
+                storage
+                    .set_guild_setting(guild, MACROS, &library.serialize())
+                    .await?;
+            }
+            removed
+        }
+        None => {
+            let mut library = user_library(ctx.author().id, storage).await?;
+            let removed = library.remove(name);
+            if removed {
+                storage
+                    .set_preference(MACROS, ctx.author().id, &library.serialize())
+                    .await?;
+            }
+            removed
+        }
+    };
+
+    if removed {
+        ctx.say(format!("Removed **{}**.", name)).await?;
+    } else {
+        ctx.say(format!("There's no macro called **{}**.", name))
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typst_macro(name: &str, definition: &str) -> Macro {
+        Macro {
+            name: name.to_string(),
+            language: MacroLanguage::Typst,
+            definition: definition.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_library() {
+        let mut library = Library::default();
+        library.add(typst_macro("R", "#let R = $bb(R)$")).unwrap();
+        library.add(typst_macro("C", "#let C = $bb(C)$")).unwrap();
+        library
+            .add(Macro {
+                name: "iu".to_string(),
+                language: MacroLanguage::Latex,
+                definition: r"\newcommand{\iu}{\mathrm{i}}".to_string(),
+            })
+            .unwrap();
+        // replacing keeps the order
+        library.add(typst_macro("R", "#let R = $RR$")).unwrap();
+
+        let mut settings = RenderSettings::default();
+        library.apply(&mut settings);
+        assert_eq!(settings.macros, "#let R = $RR$\n#let C = $bb(C)$\n");
+        assert_eq!(settings.latex_macros, "\\newcommand{\\iu}{\\mathrm{i}}\n");
+
+        assert!(library.remove("C"));
+        assert!(!library.remove("C"));
+        assert_eq!(
+            Library::parse(Some(library.serialize())),
+            library,
+            "libraries survive storage"
+        );
+    }
+
+    #[test]
+    fn test_library_limits() {
+        let mut library = Library::default();
+        for i in 0..MAX_MACROS {
+            library.add(typst_macro(&i.to_string(), "")).unwrap();
+        }
+        assert!(library.add(typst_macro("one-more", "")).is_err());
+
+        let mut library = Library::default();
+        let long = "x".repeat(MAX_LIBRARY_SIZE / 2 + 1);
+        library.add(typst_macro("a", &long)).unwrap();
+        assert!(library.add(typst_macro("b", &long)).is_err());
+    }
+
+    #[test]
+    fn test_listing() {
+        let mut server = Library::default();
+        server.add(typst_macro("R", "#let R = $bb(R)$")).unwrap();
+        assert_eq!(
+            listing(&[("Server", &server), ("Yours", &Library::default())]),
+            "# Server\n- **R** (Typst): `#let R = $bb(R)$`\n"
+        );
+
+        // full libraries don't fit in a message
+        let (mut server, mut user) = (Library::default(), Library::default());
+        let long = "x".repeat(MAX_LIBRARY_SIZE / MAX_MACROS);
+        for i in 0..MAX_MACROS {
+            server.add(typst_macro(&i.to_string(), &long)).unwrap();
+            user.add(typst_macro(&i.to_string(), &long)).unwrap();
+        }
+        let content = listing(&[("Server", &server), ("Yours", &user)]);
+        assert!(content.len() <= 2000);
+        assert!(content.ends_with(" more."));
+    }
+}
@@ -1,10 +1,25 @@
 //! Utilities to deal with math markup.
 
+mod chat_message;
+mod chemistry;
+mod delimiters;
+mod latex;
+mod macros;
+mod packages;
+mod plot;
 mod preferred_markup;
+mod render_cache;
+mod render_pool;
+mod theme;
 mod typst_base;
 mod typst_main;
 
+pub(crate) use chat_message::display_mentions;
+pub(crate) use chemistry::chem;
+pub(crate) use plot::plot;
 pub(crate) use preferred_markup::{
     get_preferred_markup, set_default_math_markup, set_preferred_markup,
 };
-pub(crate) use typst_main::{catch_typst_message, render_math, typst};
+pub(crate) use render_pool::{run_worker as run_render_worker, WORKER_FLAG};
+pub(crate) use typst_base::typst_render;
+pub(crate) use typst_main::{catch_typst_message, render_math, render_settings, typst};
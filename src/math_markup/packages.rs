@@ -0,0 +1,410 @@
+//! The Typst packages renders can import, like `#import "@preview/cetz:0.2.0": canvas`.
+//!
+//! Packages live in the package directory, one directory each, holding the package's `typst.toml`
+//! manifest. Archives from the package registry (`{name}-{version}.tar.gz`, from
+//! https://packages.typst.org/preview/) can be dropped into the same directory. The bot unpacks them
+//! before its next render, at most once every [RESCAN_INTERVAL], or when the packages are listed.
+//! Only the bot's own process unpacks them, never a render worker, so that an unpack isn't cut short
+//! by a worker's time or memory limit. Typst reads a package's manifest itself to find its
+//! entrypoint; they're read here to list the packages and to leave out any that are broken.
+//!
+//! If an allow-list is configured, only the packages on it can be imported, along with the ones
+//! every render imports.
+//!
+//! Importing a package that isn't known looks through the directory again, but at most once every
+//! [RESCAN_INTERVAL], since anyone can import packages that don't exist.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use typst::diag::{FileError, FileResult, PackageError};
+use typst::syntax::PackageSpec;
+
+use crate::config::config;
+use crate::utils::{Context, Error};
+
+/// The packages every render imports, which are always allowed.
+const PREAMBLE_PACKAGES: [&str; 2] = ["whalogen", "mitex"];
+
+/// The only namespace there is packages for.
+const NAMESPACE: &str = "preview";
+
+/// The end of the names of package archives.
+const ARCHIVE_EXTENSION: &str = ".tar.gz";
+
+/// How long after the package directory was looked through an unknown import can look through it
+/// again.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A package's `typst.toml`.
+#[derive(Deserialize)]
+struct Manifest {
+    package: PackageInfo,
+}
+
+/// The `[package]` section of a manifest. Only the fields that are used are read.
+#[derive(Deserialize)]
+struct PackageInfo {
+    name: String,
+    version: String,
+    entrypoint: String,
+    description: Option<String>,
+}
+
+/// An unpacked package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Package {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    /// The directory with the package's files.
+    pub dir: PathBuf,
+}
+
+/// Reads the manifest of the package in a directory, checking that its entrypoint is there.
+fn read_package(dir: &Path) -> Result<Package, String> {
+    let manifest = std::fs::read_to_string(dir.join("typst.toml"))
+        .map_err(|e| format!("couldn't read typst.toml: {}", e))?;
+    let manifest: Manifest =
+        toml::from_str(&manifest).map_err(|e| format!("invalid typst.toml: {}", e))?;
+    let info = manifest.package;
+    if !dir.join(&info.entrypoint).is_file() {
+        return Err(format!("its entrypoint {} is missing", info.entrypoint));
+    }
+    Ok(Package {
+        name: info.name,
+        version: info.version,
+        description: info.description,
+        dir: dir.to_path_buf(),
+    })
+}
+
+/// Unpacks an archive into a directory. The files are unpacked into a directory of their own first,
+/// so a half-unpacked package is never used, and so that unpacking the same archive twice at once
+/// works too: whichever finishes last finds the package already there.
+fn unpack(archive: &Path, dest: &Path, partial: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(partial)?;
+    tar::Archive::new(GzDecoder::new(File::open(archive)?)).unpack(partial)?;
+    match std::fs::rename(partial, dest) {
+        Err(_) if dest.is_dir() => std::fs::remove_dir_all(partial),
+        result => result,
+    }
+}
+
+/// Unpacks every archive in the package directory that hasn't been unpacked yet, into a directory
+/// with the archive's name.
+fn unpack_archives(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some(stem) = file_name.strip_suffix(ARCHIVE_EXTENSION) else {
+            continue;
+        };
+        let dest = dir.join(stem);
+        if dest.exists() {
+            continue;
+        }
+        let partial = dir.join(format!(
+            ".{}.{}-{:08x}.partial",
+            stem,
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        match unpack(&entry.path(), &dest, &partial) {
+            Ok(()) => println!("Unpacked package {}", file_name),
+            Err(e) => {
+                println!("Couldn't unpack package {}: {}", file_name, e);
+                let _ = std::fs::remove_dir_all(&partial);
+            }
+        }
+    }
+}
+
+/// Finds the packages in a directory. Broken packages are left out.
+fn scan(dir: &Path) -> Vec<Package> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        println!("Couldn't read the package directory {}", dir.display());
+        return vec![];
+    };
+    let mut packages = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if hidden || !path.is_dir() {
+            continue;
+        }
+        match read_package(&path) {
+            Ok(package) => packages.push(package),
+            Err(e) => println!("Skipping package {}: {}", path.display(), e),
+        }
+    }
+    packages.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| version_key(&b.version).cmp(&version_key(&a.version)))
+    });
+    packages
+}
+
+/// Orders versions newest first: `0.10.0` is newer than `0.9.1`.
+fn version_key(version: &str) -> Vec<u64> {
+    version.split('.').map(|n| n.parse().unwrap_or(0)).collect()
+}
+
+/// Whether a package can be imported, given the allow-list: either names, which allow every version,
+/// or `name:version`. An empty list allows everything.
+fn is_allowed(name: &str, version: &str, allowed: &[String]) -> bool {
+    allowed.is_empty()
+        || PREAMBLE_PACKAGES.contains(&name)
+        || allowed.iter().map(|a| a.trim()).any(|a| {
+            a == name
+                || a.split_once(':')
+                    .is_some_and(|(n, v)| n.trim() == name && v.trim() == version)
+        })
+}
+
+/// The packages found the last time the package directory was looked through.
+struct Index {
+    packages: Vec<Package>,
+    scanned: Instant,
+}
+
+impl Index {
+    fn scan(dir: &Path) -> Self {
+        Self {
+            packages: scan(dir),
+            scanned: Instant::now(),
+        }
+    }
+
+    /// Whether an import of a package that isn't in the index can look through the directory again.
+    fn can_rescan(&self) -> bool {
+        self.scanned.elapsed() >= RESCAN_INTERVAL
+    }
+}
+
+fn index() -> &'static RwLock<Index> {
+    static INDEX: OnceLock<RwLock<Index>> = OnceLock::new();
+    INDEX.get_or_init(|| RwLock::new(Index::scan(Path::new(&config().typst.package_dir))))
+}
+
+/// Looks through the package directory again, for packages added since the last time.
+fn refresh() -> Vec<Package> {
+    let scanned = Index::scan(Path::new(&config().typst.package_dir));
+    let packages = scanned.packages.clone();
+    *index().write().expect("Package index poisoned") = scanned;
+    packages
+}
+
+/// Unpacks any new archives in the package directory, unless that was done less than
+/// [RESCAN_INTERVAL] ago. Called by the bot before it hands a render to a worker.
+pub(crate) async fn unpack_new_archives() {
+    static UNPACKED: Mutex<Option<Instant>> = Mutex::new(None);
+    {
+        let mut unpacked = UNPACKED.lock().expect("Unpack time poisoned");
+        if unpacked.is_some_and(|at| at.elapsed() < RESCAN_INTERVAL) {
+            return;
+        }
+        *unpacked = Some(Instant::now());
+    }
+    let _ = tokio::task::spawn_blocking(|| unpack_archives(Path::new(&config().typst.package_dir)))
+        .await;
+}
+
+/// Finds the directory of a package an import asks for.
+pub(crate) fn package_dir(spec: &PackageSpec) -> FileResult<PathBuf> {
+    let version = spec.version.to_string();
+    if !is_allowed(&spec.name, &version, &config().typst.allowed_packages) {
+        return Err(FileError::AccessDenied);
+    }
+    let find = |packages: &[Package]| {
+        packages
+            .iter()
+            .find(|p| {
+                spec.namespace.as_str() == NAMESPACE
+                    && p.name == spec.name.as_str()
+                    && p.version == version
+            })
+            .map(|p| p.dir.clone())
+    };
+    let (found, can_rescan) = {
+        let index = index().read().expect("Package index poisoned");
+        (find(&index.packages), index.can_rescan())
+    };
+    found
+        .or_else(|| can_rescan.then(|| find(&refresh())).flatten())
+        .ok_or_else(|| FileError::Package(PackageError::NotFound(spec.clone())))
+}
+
+/// Lists the packages you can import, like `#import "@preview/cetz:0.2.0": canvas`.
+#[poise::command(slash_command)]
+pub(crate) async fn packages(ctx: Context<'_>) -> Result<(), Error> {
+    let allowed = &config().typst.allowed_packages;
+    let packages = tokio::task::spawn_blocking(|| {
+        unpack_archives(Path::new(&config().typst.package_dir));
+        refresh()
+    })
+    .await?;
+
+    let mut lines: Vec<String> = vec![];
+    let mut last_name = None;
+    for package in packages
+        .iter()
+        .filter(|p| is_allowed(&p.name, &p.version, allowed))
+    {
+        if last_name == Some(&package.name) {
+            // another version of the last package
+            let line = lines
+                .last_mut()
+                .expect("There's a line for the last package");
+            line.push_str(&format!(", `{}`", package.version));
+            continue;
+        }
+        last_name = Some(&package.name);
+        lines.push(format!(
+            "- **{}**{}: `{}`",
+            package.name,
+            package
+                .description
+                .as_deref()
+                .map(|d| format!(" ({})", d.trim()))
+                .unwrap_or_default(),
+            package.version
+        ));
+    }
+
+    let mut content = format!(
+        "Packages you can `#import \"@{}/name:version\"`:",
+        NAMESPACE
+    );
+    if lines.is_empty() {
+        content = "There aren't any packages yet.".to_string();
+    }
+    for (i, line) in lines.iter().enumerate() {
+        // leave room to say how many didn't fit
+        if content.len() + line.len() > 1900 {
+            content.push_str(&format!("\n...and {} more.", lines.len() - i));
+            break;
+        }
+        content.push('\n');
+        content.push_str(line);
+    }
+    ctx.send(|m| m.content(content).allowed_mentions(|a| a.empty_parse()))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_allowed() {
+        let allowed = vec!["cetz".to_string(), "fletcher:0.4.0".to_string()];
+        assert!(is_allowed("cetz", "0.2.0", &allowed));
+        assert!(is_allowed("fletcher", "0.4.0", &allowed));
+        assert!(!is_allowed("fletcher", "0.3.0", &allowed));
+        assert!(!is_allowed("tablex", "0.0.8", &allowed));
+        assert!(is_allowed("mitex", "0.2.1", &allowed));
+        assert!(is_allowed("tablex", "0.0.8", &[]));
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = std::env::temp_dir().join(format!("nano-packages-{}", std::process::id()));
+        let add = |dir_name: &str, manifest: &str, entrypoint: Option<&str>| {
+            let package = dir.join(dir_name);
+            std::fs::create_dir_all(&package).unwrap();
+            std::fs::write(package.join("typst.toml"), manifest).unwrap();
+            if let Some(entrypoint) = entrypoint {
+                std::fs::write(package.join(entrypoint), "#let x = 1").unwrap();
+            }
+        };
+        let manifest = |name: &str, version: &str| {
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"{}\"\nentrypoint = \"src/lib.typ\"\n\
+                 authors = [\"someone\"]\n",
+                name, version
+            )
+        };
+        std::fs::create_dir_all(dir.join("cetz-0.10.0/src")).unwrap();
+        add(
+            "cetz-0.10.0",
+            &manifest("cetz", "0.10.0"),
+            Some("src/lib.typ"),
+        );
+        std::fs::create_dir_all(dir.join("renamed/src")).unwrap();
+        add("renamed", &manifest("cetz", "0.9.1"), Some("src/lib.typ"));
+        // no entrypoint
+        add("broken-1.0.0", &manifest("broken", "1.0.0"), None);
+        add("invalid-1.0.0", "[package]\nname = \"invalid\"", None);
+
+        let found: Vec<(String, String)> = scan(&dir)
+            .into_iter()
+            .map(|p| (p.name, p.version))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("cetz".to_string(), "0.10.0".to_string()),
+                ("cetz".to_string(), "0.9.1".to_string()),
+            ]
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_unpack_at_once() {
+        let dir = std::env::temp_dir().join(format!("nano-archives-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest =
+            "[package]\nname = \"cetz\"\nversion = \"0.2.0\"\nentrypoint = \"lib.typ\"\n";
+        let archive = File::create(dir.join("cetz-0.2.0.tar.gz")).unwrap();
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            archive,
+            flate2::Compression::default(),
+        ));
+        for (path, contents) in [("typst.toml", manifest), ("lib.typ", "#let x = 1")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        // like several processes finding the archive at the same time
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| unpack_archives(&dir));
+            }
+        });
+        let found: Vec<String> = scan(&dir).into_iter().map(|p| p.name).collect();
+        assert_eq!(found, vec!["cetz".to_string()]);
+        let leftovers = std::fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".partial"))
+            .count();
+        assert_eq!(leftovers, 0);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_can_rescan() {
+        let dir = std::env::temp_dir().join(format!("nano-no-packages-{}", std::process::id()));
+        let mut index = Index::scan(&dir);
+        assert!(index.packages.is_empty());
+        assert!(!index.can_rescan());
+        index.scanned -= RESCAN_INTERVAL;
+        assert!(index.can_rescan());
+    }
+}
@@ -0,0 +1,706 @@
+//! Plots of functions like `y = x^2 - 3x`, curves like `(cos(t), sin(2t))` and polar curves like
+//! `r = 1 + cos(t)`.
+//!
+//! Expressions are evaluated here, at evenly spaced points across the domain, and the plot is drawn
+//! as a Typst document so it's rendered (and themed) like any other math. Graphs of functions only
+//! show the range most of their values fall in, so asymptotes don't squash the rest of the plot.
+
+use anyhow::{anyhow, bail, Result};
+use poise::serenity_prelude::AttachmentType;
+
+use crate::expr::{self, BinaryOp, Expr};
+use crate::math_markup::{theme::Theme, typst_base::typst_render, typst_main::render_settings};
+use crate::utils::{Context, Error};
+
+/// How many points each function is evaluated at.
+const SAMPLES: usize = 500;
+
+/// The most functions one plot can have, one for each color.
+const MAX_SERIES: usize = 6;
+
+/// The most points one plot can mark.
+const MAX_POINTS: usize = 20;
+
+/// Where x goes for graphs of functions, unless a domain is given.
+const DEFAULT_DOMAIN: (f64, f64) = (-10.0, 10.0);
+
+/// Where t goes for curves, unless a domain is given.
+const DEFAULT_CURVE_DOMAIN: (f64, f64) = (0.0, std::f64::consts::TAU);
+
+/// The size of the plot, in ems of the theme's text size.
+const WIDTH: f64 = 20.0;
+const HEIGHT: f64 = 13.0;
+
+/// The colors of the functions, in order. They're readable on both light and dark backgrounds.
+const PALETTE: [&str; MAX_SERIES] = [
+    "#5865f2", "#ed4245", "#3ba55c", "#faa61a", "#eb459e", "#1abc9c",
+];
+
+/// The share of a graph's values that decides what range is shown, leaving out the most extreme
+/// ones at each end.
+const SHOWN_QUANTILE: f64 = 0.02;
+
+/// What's plotted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Curve {
+    /// y as a function of x.
+    Graph(Expr),
+    /// x and y as functions of t.
+    Parametric(Expr, Expr),
+    /// r as a function of t.
+    Polar(Expr),
+}
+
+/// A function to plot, with its legend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Series {
+    curve: Curve,
+    /// How the legend writes it, in Typst math.
+    label: String,
+}
+
+/// The pieces of a curve where it's defined, each a run of points.
+type Pieces = Vec<Vec<(f64, f64)>>;
+
+/// Splits text at a character, except inside parentheses.
+fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// If text is a pair like `(a, b)`, gives what's in it.
+fn pair(text: &str) -> Option<(&str, &str)> {
+    let inner = text.trim().strip_prefix('(')?.strip_suffix(')')?;
+    match split_top_level(inner, ',').as_slice() {
+        [a, b] => Some((a, b)),
+        _ => None,
+    }
+}
+
+/// Reads one function: `y = ...`, `f(x) = ...` or just an expression in x for a graph, `(x, y)`
+/// in t for a curve, or `r = ...` in t for a polar curve.
+fn parse_series(text: &str) -> Result<Series> {
+    let (lhs, rhs) = match text.split_once('=') {
+        Some((lhs, rhs)) => (Some(lhs.replace(char::is_whitespace, "")), rhs),
+        None => (None, text),
+    };
+    match lhs.as_deref() {
+        None if pair(rhs).is_some() => {
+            let (x, y) = pair(rhs).expect("Just checked");
+            let (x, y) = (expr::parse(x)?, expr::parse(y)?);
+            Ok(Series {
+                label: format!("(x, y) = ({}, {})", x.to_typst(), y.to_typst()),
+                curve: Curve::Parametric(x, y),
+            })
+        }
+        Some("r" | "r(t)" | "r(θ)" | "r(theta)") => {
+            let r = expr::parse(rhs)?;
+            Ok(Series {
+                label: format!("r = {}", r.to_typst()),
+                curve: Curve::Polar(r),
+            })
+        }
+        None | Some("y" | "f(x)" | "g(x)" | "h(x)" | "y(x)") => {
+            let y = expr::parse(rhs)?;
+            Ok(Series {
+                label: format!("y = {}", y.to_typst()),
+                curve: Curve::Graph(y),
+            })
+        }
+        Some(_) => bail!(
+            "Write functions like y = x^2, curves like (cos(t), sin(t)) and polar curves like \
+             r = 1 + cos(t)."
+        ),
+    }
+}
+
+/// Calls a function at some numbers.
+fn call(function: &str, args: &[f64]) -> Result<f64> {
+    let one = |f: fn(f64) -> f64| match args {
+        [x] => Ok(f(*x)),
+        _ => Err(anyhow!("{} takes one number.", function)),
+    };
+    match function {
+        "sqrt" => one(f64::sqrt),
+        "cbrt" => one(f64::cbrt),
+        "abs" => one(f64::abs),
+        "exp" => one(f64::exp),
+        "ln" => one(f64::ln),
+        "log" | "log10" => one(f64::log10),
+        "log2" => one(f64::log2),
+        "sin" => one(f64::sin),
+        "cos" => one(f64::cos),
+        "tan" => one(f64::tan),
+        "asin" => one(f64::asin),
+        "acos" => one(f64::acos),
+        "atan" => one(f64::atan),
+        "sinh" => one(f64::sinh),
+        "cosh" => one(f64::cosh),
+        "tanh" => one(f64::tanh),
+        "floor" => one(f64::floor),
+        "ceil" => one(f64::ceil),
+        "round" => one(f64::round),
+        "sign" => one(|x| if x == 0.0 { 0.0 } else { x.signum() }),
+        "min" => Ok(args.iter().copied().fold(f64::INFINITY, f64::min)),
+        "max" => Ok(args.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+        _ => bail!("{} doesn't work in plots.", function),
+    }
+}
+
+/// Evaluates an expression, given its variables. Where it's undefined, it's NaN.
+fn eval(expr: &Expr, vars: &[(&str, f64)]) -> Result<f64> {
+    Ok(match expr {
+        Expr::Number(n) => n.parse().map_err(|_| anyhow!("{} isn't a number.", n))?,
+        Expr::Name(name) => match vars.iter().find(|(var, _)| var == name) {
+            Some((_, value)) => *value,
+            None => match name.as_str() {
+                "pi" | "π" => std::f64::consts::PI,
+                "tau" | "τ" => std::f64::consts::TAU,
+                "e" => std::f64::consts::E,
+                _ => match vars.first() {
+                    Some((var, _)) if !expr::FUNCTIONS.contains(&name.as_str()) => {
+                        bail!("I don't know what {} is: plot in terms of {}.", name, var)
+                    }
+                    _ => return Err(expr::unknown_name(name)),
+                },
+            },
+        },
+        Expr::Neg(e) => -eval(e, vars)?,
+        Expr::Factorial(e) => {
+            let n = eval(e, vars)?;
+            if n >= 0.0 && n.fract() == 0.0 && n <= 170.0 {
+                (1..=n as u32).map(f64::from).product()
+            } else {
+                f64::NAN
+            }
+        }
+        Expr::Binary(op, a, b) => {
+            let (a, b) = (eval(a, vars)?, eval(b, vars)?);
+            match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                BinaryOp::Pow => a.powf(b),
+            }
+        }
+        Expr::Call(function, args) => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, vars))
+                .collect::<Result<Vec<_>>>()?;
+            call(function, &args)?
+        }
+        Expr::Convert(..) => bail!("Plots don't have units to convert."),
+    })
+}
+
+/// Evaluates an expression that's just a number, like `2pi`.
+fn eval_number(text: &str) -> Result<f64> {
+    let value = eval(&expr::parse(text)?, &[])?;
+    if !value.is_finite() {
+        bail!("{} isn't a number.", text.trim());
+    }
+    Ok(value)
+}
+
+/// Reads a domain like `-5..5`, `0 to 2pi` or `-1, 1`.
+fn parse_domain(text: &str) -> Result<(f64, f64)> {
+    let (from, to) = text
+        .split_once("..")
+        .or_else(|| text.split_once(" to "))
+        .or_else(|| text.split_once(','))
+        .ok_or_else(|| anyhow!("Write the domain like -5..5."))?;
+    let (from, to) = (eval_number(from)?, eval_number(to)?);
+    if from >= to {
+        bail!("The domain has to go from a smaller number to a bigger one.");
+    }
+    Ok((from, to))
+}
+
+/// Reads points like `(1, 2) (3, -1)`, separated by spaces, commas or semicolons.
+fn parse_points(text: &str) -> Result<Vec<(f64, f64)>> {
+    let separator = |c: char| c.is_whitespace() || c == ',' || c == ';';
+    let mut points = vec![];
+    let mut rest = text.trim_start_matches(separator);
+    while !rest.is_empty() {
+        if !rest.starts_with('(') {
+            bail!("Write points like (1, 2) (3, -1).");
+        }
+        // the ) that closes the point
+        let mut depth = 0;
+        let end = rest
+            .char_indices()
+            .find_map(|(i, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                (depth == 0).then_some(i + 1)
+            })
+            .ok_or_else(|| anyhow!("There's a `)` missing in the points."))?;
+        let (x, y) = pair(&rest[..end]).ok_or_else(|| anyhow!("Points need two coordinates."))?;
+        points.push((eval_number(x)?, eval_number(y)?));
+        rest = rest[end..].trim_start_matches(separator);
+    }
+    if points.len() > MAX_POINTS {
+        bail!("A plot can only mark {} points.", MAX_POINTS);
+    }
+    Ok(points)
+}
+
+/// Evaluates a function across its domain, giving the pieces of it that are defined.
+fn sample(curve: &Curve, domain: (f64, f64)) -> Result<Pieces> {
+    let mut pieces = vec![];
+    let mut piece = vec![];
+    for i in 0..=SAMPLES {
+        let v = domain.0 + (domain.1 - domain.0) * i as f64 / SAMPLES as f64;
+        let t = [("t", v), ("θ", v), ("theta", v)];
+        let point = match curve {
+            Curve::Graph(y) => (v, eval(y, &[("x", v)])?),
+            Curve::Parametric(x, y) => (eval(x, &t)?, eval(y, &t)?),
+            Curve::Polar(r) => {
+                let r = eval(r, &t)?;
+                (r * v.cos(), r * v.sin())
+            }
+        };
+        if point.0.is_finite() && point.1.is_finite() {
+            piece.push(point);
+        } else if !piece.is_empty() {
+            pieces.push(std::mem::take(&mut piece));
+        }
+    }
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+    Ok(pieces)
+}
+
+/// The smallest and largest of some numbers, if there are any.
+fn bounds(values: impl Iterator<Item = f64>) -> Option<(f64, f64)> {
+    values.fold(None, |bounds, v| match bounds {
+        None => Some((v, v)),
+        Some((lo, hi)) => Some((lo.min(v), hi.max(v))),
+    })
+}
+
+/// The range most of some numbers fall in, leaving out the most extreme ones.
+fn typical_bounds(mut values: Vec<f64>) -> Option<(f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let at = |q: f64| values[((values.len() - 1) as f64 * q).round() as usize];
+    Some((at(SHOWN_QUANTILE), at(1.0 - SHOWN_QUANTILE)))
+}
+
+/// The union of two ranges, either of which might be missing.
+fn union(a: Option<(f64, f64)>, b: Option<(f64, f64)>) -> Option<(f64, f64)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.max(b.1))),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Widens a range by a share of its size on each side, or to some size if it's empty.
+fn pad((lo, hi): (f64, f64), share: f64) -> (f64, f64) {
+    if hi - lo < 1e-9 * lo.abs().max(1.0) {
+        (lo - 1.0, hi + 1.0)
+    } else {
+        let pad = (hi - lo) * share;
+        (lo - pad, hi + pad)
+    }
+}
+
+/// What part of the plane a plot shows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct View {
+    x: (f64, f64),
+    y: (f64, f64),
+}
+
+impl View {
+    /// Works out what to show: the domain of any graphs, and everything else that's plotted.
+    /// Without graphs, both axes get the same scale, so circles come out round.
+    fn fit(
+        series: &[(Series, Pieces)],
+        points: &[(f64, f64)],
+        domain: (f64, f64),
+        aspect: f64,
+    ) -> Self {
+        let (graphs, curves): (Vec<_>, Vec<_>) = series
+            .iter()
+            .partition(|(s, _)| matches!(s.curve, Curve::Graph(_)));
+        let values = |list: &[&(Series, Pieces)], y: bool| -> Vec<f64> {
+            list.iter()
+                .flat_map(|(_, pieces)| pieces.iter().flatten())
+                .map(|&(px, py)| if y { py } else { px })
+                .collect()
+        };
+        let point_x = bounds(points.iter().map(|p| p.0));
+        let point_y = bounds(points.iter().map(|p| p.1));
+
+        let curve_x = bounds(values(&curves, false).into_iter());
+        let curve_y = bounds(values(&curves, true).into_iter());
+        let graph_y = typical_bounds(values(&graphs, true));
+        let graph_x = if graphs.is_empty() {
+            None
+        } else {
+            Some(domain)
+        };
+
+        let x = union(union(graph_x, curve_x), point_x).unwrap_or(DEFAULT_DOMAIN);
+        let y = union(union(graph_y, curve_y), point_y).unwrap_or((-1.0, 1.0));
+        let x = if graphs.is_empty() { pad(x, 0.05) } else { x };
+        let y = pad(y, 0.05);
+
+        if !graphs.is_empty() {
+            return Self { x, y };
+        }
+        // stretch the shorter side so a unit is as long along both axes
+        let per_unit = ((x.1 - x.0) / aspect).max(y.1 - y.0);
+        let grow = |(lo, hi): (f64, f64), span: f64| {
+            let middle = (lo + hi) / 2.0;
+            (middle - span / 2.0, middle + span / 2.0)
+        };
+        Self {
+            x: grow(x, per_unit * aspect),
+            y: grow(y, per_unit),
+        }
+    }
+}
+
+/// Evenly spaced round numbers across a range, about five of them, and how many decimals they need.
+fn ticks((lo, hi): (f64, f64)) -> (Vec<f64>, usize) {
+    let rough = (hi - lo) / 5.0;
+    let magnitude = 10f64.powf(rough.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|&step| step >= rough)
+        .unwrap_or(10.0 * magnitude);
+    let first = (lo / step).ceil() as i64;
+    let last = (hi / step).floor() as i64;
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    ((first..=last).map(|i| i as f64 * step).collect(), decimals)
+}
+
+/// Writes a number for a label, with a proper minus sign.
+fn label(value: f64, decimals: usize) -> String {
+    let text = format!("{:.*}", decimals, value);
+    if text
+        .trim_start_matches('-')
+        .chars()
+        .all(|c| c == '0' || c == '.')
+    {
+        return text.trim_start_matches('-').to_string();
+    }
+    text.replace('-', "−")
+}
+
+/// Writes a coordinate of a point, like `1.5` or `−2`.
+fn coordinate(value: f64) -> String {
+    label(value, 2)
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Blends two colors, taking a share of the first.
+fn blend(a: [u8; 3], b: [u8; 3], share: f64) -> String {
+    let mix = |i: usize| (a[i] as f64 * share + b[i] as f64 * (1.0 - share)).round() as u8;
+    format!("rgb({}, {}, {})", mix(0), mix(1), mix(2))
+}
+
+/// Writes the Typst document for a plot, sized to go with the text size.
+fn document(
+    series: &[(Series, Pieces)],
+    points: &[(f64, f64)],
+    view: View,
+    theme: &Theme,
+) -> String {
+    let em = theme.size as f64;
+    let (width, height) = (WIDTH * em, HEIGHT * em);
+    let (left, top, bottom) = (3.0 * em, 1.4 * em, 2.6 * em);
+    let to_pt = |(x, y): (f64, f64)| {
+        let px = (x - view.x.0) / (view.x.1 - view.x.0) * width;
+        let py = (view.y.1 - y) / (view.y.1 - view.y.0) * height;
+        // far off the plot, only the direction matters
+        (
+            px.clamp(-10.0 * width, 11.0 * width),
+            py.clamp(-10.0 * height, 11.0 * height),
+        )
+    };
+    let grid = blend(theme.colors.fg(), theme.colors.bg(), 0.2);
+    let axis = blend(theme.colors.fg(), theme.colors.bg(), 0.6);
+
+    let mut plot = String::new();
+    let (x_ticks, x_decimals) = ticks(view.x);
+    let (y_ticks, y_decimals) = ticks(view.y);
+    let mut labels = String::new();
+    for &x in &x_ticks {
+        let (px, _) = to_pt((x, 0.0));
+        let stroke = if x == 0.0 { &axis } else { &grid };
+        plot.push_str(&format!(
+            "place(line(start: ({px:.2}pt, 0pt), end: ({px:.2}pt, {height:.2}pt), stroke: 0.6pt + {stroke}))\n"
+        ));
+        labels.push_str(&format!(
+            "place(dx: {:.2}pt, dy: {:.2}pt, box(width: {:.2}pt, align(center, text(size: 0.7em)[{}])))\n",
+            left + px - 2.0 * em,
+            top + height + 0.3 * em,
+            4.0 * em,
+            label(x, x_decimals)
+        ));
+    }
+    for &y in &y_ticks {
+        let (_, py) = to_pt((0.0, y));
+        let stroke = if y == 0.0 { &axis } else { &grid };
+        plot.push_str(&format!(
+            "place(line(start: (0pt, {py:.2}pt), end: ({width:.2}pt, {py:.2}pt), stroke: 0.6pt + {stroke}))\n"
+        ));
+        labels.push_str(&format!(
+            "place(dx: 0pt, dy: {:.2}pt, box(width: {:.2}pt, align(right, text(size: 0.7em)[{}])))\n",
+            top + py - 0.4 * em,
+            left - 0.4 * em,
+            label(y, y_decimals)
+        ));
+    }
+    labels.push_str(&format!(
+        "place(dx: {:.2}pt, dy: {:.2}pt, box(width: {:.2}pt, align(center)[$x$]))\n",
+        left,
+        top + height + 1.3 * em,
+        width
+    ));
+    labels.push_str(&format!(
+        "place(dx: 0pt, dy: 0pt, box(width: {:.2}pt, align(right)[$y$]))\n",
+        left - 0.4 * em
+    ));
+
+    let y_span = view.y.1 - view.y.0;
+    for ((_, pieces), color) in series.iter().zip(PALETTE) {
+        for piece in pieces {
+            let mut vertices = vec![];
+            for (i, &point) in piece.iter().enumerate() {
+                // a jump across the whole plot is an asymptote, not part of the curve
+                if i > 0 && (point.1 - piece[i - 1].1).abs() > y_span {
+                    if vertices.len() > 1 {
+                        plot.push_str(&path(&vertices, color));
+                    }
+                    vertices.clear();
+                }
+                let (px, py) = to_pt(point);
+                vertices.push(format!("({:.2}pt, {:.2}pt)", px, py));
+            }
+            if vertices.len() > 1 {
+                plot.push_str(&path(&vertices, color));
+            }
+        }
+    }
+    let radius = 0.22 * em;
+    for &(x, y) in points {
+        let (px, py) = to_pt((x, y));
+        plot.push_str(&format!(
+            "place(dx: {:.2}pt, dy: {:.2}pt, circle(radius: {radius:.2}pt, fill: fg))\n",
+            px - radius,
+            py - radius
+        ));
+        plot.push_str(&format!(
+            "place(dx: {:.2}pt, dy: {:.2}pt, text(size: 0.6em)[({}, {})])\n",
+            px + radius,
+            py - 1.1 * em,
+            coordinate(x),
+            coordinate(y)
+        ));
+    }
+
+    let legend: Vec<String> = series
+        .iter()
+        .zip(PALETTE)
+        .map(|((s, _), color)| {
+            format!(
+                "#box(move(dy: -0.3em, line(length: 1.2em, stroke: 2pt + rgb(\"{}\")))) ${}$",
+                color, s.label
+            )
+        })
+        .collect();
+
+    format!(
+        "#box(width: {total_width:.2}pt, height: {total_height:.2}pt, {{\n\
+         place(dx: {left:.2}pt, dy: {top:.2}pt, box(width: {width:.2}pt, height: {height:.2}pt, \
+         clip: true, stroke: 0.75pt + fg, {{\n{plot}}}))\n{labels}}})\n\
+         #block(width: {total_width:.2}pt)[{legend}]",
+        total_width = left + width + 0.5 * em,
+        total_height = top + height + bottom,
+        legend = legend.join(" #h(1em) "),
+    )
+}
+
+/// Writes a line through some vertices.
+fn path(vertices: &[String], color: &str) -> String {
+    format!(
+        "place(path(stroke: 1.5pt + rgb(\"{}\"), {}))\n",
+        color,
+        vertices.join(", ")
+    )
+}
+
+/// Reads what to plot and evaluates it, giving the Typst document for the plot.
+fn plot_document(
+    functions: &str,
+    domain: Option<&str>,
+    points: Option<&str>,
+    theme: &Theme,
+) -> Result<String> {
+    let series = functions
+        .split(';')
+        .filter(|f| !f.trim().is_empty())
+        .map(|f| parse_series(f).map_err(|e| anyhow!("In `{}`: {}", f.trim(), e)))
+        .collect::<Result<Vec<_>>>()?;
+    if series.is_empty() {
+        bail!("There's nothing to plot.");
+    } else if series.len() > MAX_SERIES {
+        bail!("A plot can only have {} functions.", MAX_SERIES);
+    }
+    let domain = domain.map(parse_domain).transpose()?;
+    let points = points.map(parse_points).transpose()?.unwrap_or_default();
+
+    let sampled = series
+        .into_iter()
+        .map(|s| {
+            let domain = match s.curve {
+                Curve::Graph(_) => domain.unwrap_or(DEFAULT_DOMAIN),
+                _ => domain.unwrap_or(DEFAULT_CURVE_DOMAIN),
+            };
+            let pieces = sample(&s.curve, domain)?;
+            Ok((s, pieces))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let view = View::fit(
+        &sampled,
+        &points,
+        domain.unwrap_or(DEFAULT_DOMAIN),
+        WIDTH / HEIGHT,
+    );
+    Ok(document(&sampled, &points, view, theme))
+}
+
+/// Plots functions like `y = x^2 - 3x`, curves like `(cos(t), sin(2t))` and polar curves like
+/// `r = 1 + cos(t)`. Separate several with `;`.
+#[poise::command(slash_command)]
+pub(crate) async fn plot(
+    ctx: Context<'_>,
+    #[description = "Functions of x, (x(t), y(t)) curves or r = ... polar curves, separated by ;"]
+    functions: String,
+    #[description = "Where x (or t) goes, like -5..5: default -10..10, or 0..2pi for curves"]
+    domain: Option<String>,
+    #[description = "Points to mark, like (1, 2) (3, -1)"] points: Option<String>,
+) -> Result<(), Error> {
+    let settings =
+        render_settings(ctx.author().id, ctx.guild_id(), ctx.data().storage.as_ref()).await?;
+    let document = plot_document(
+        &functions,
+        domain.as_deref(),
+        points.as_deref(),
+        &settings.theme,
+    )?;
+    let im = typst_render(&document, &settings).await?;
+    ctx.send(|m| {
+        m.content(format!("`{}`", &functions))
+            .attachment(AttachmentType::Bytes {
+                data: im.into(),
+                filename: "Plot.png".into(),
+            })
+    })
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_series() {
+        let curve = |text: &str| parse_series(text).unwrap().curve;
+        assert!(matches!(curve("x^2 - 3x"), Curve::Graph(_)));
+        assert!(matches!(curve("f(x) = sin(x)"), Curve::Graph(_)));
+        assert!(matches!(curve("(cos(t), sin(2t))"), Curve::Parametric(..)));
+        assert!(matches!(curve("r(θ) = 1 + cos(θ)"), Curve::Polar(_)));
+        assert_eq!(
+            parse_series("y = x² − 3x").unwrap().label,
+            "y = x^(2) - 3 x"
+        );
+        assert!(parse_series("z = 2").is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let at = |text: &str, x: f64| eval(&expr::parse(text).unwrap(), &[("x", x)]).unwrap();
+        assert_eq!(at("x^2 - 3x", 2.0), -2.0);
+        assert_eq!(at("2pi", 0.0), std::f64::consts::TAU);
+        assert_eq!(at("max(x, 1, 5) + 4!", 7.0), 31.0);
+        assert!(at("sqrt(x)", -1.0).is_nan());
+        assert_eq!(
+            eval(&expr::parse("y + 1").unwrap(), &[("x", 0.0)])
+                .unwrap_err()
+                .to_string(),
+            "I don't know what y is: plot in terms of x."
+        );
+    }
+
+    #[test]
+    fn test_domain_and_points() {
+        assert_eq!(parse_domain("-5..5").unwrap(), (-5.0, 5.0));
+        assert_eq!(
+            parse_domain("0 to 2pi").unwrap(),
+            (0.0, std::f64::consts::TAU)
+        );
+        assert!(parse_domain("5..-5").is_err());
+        assert_eq!(
+            parse_points("(1, 2) (sqrt(4), -1); (0,0)").unwrap(),
+            vec![(1.0, 2.0), (2.0, -1.0), (0.0, 0.0)]
+        );
+        assert!(parse_points("(1, 2, 3)").is_err());
+        assert!(parse_points("1, 2").is_err());
+    }
+
+    #[test]
+    fn test_sample() {
+        // 1/x isn't defined at 0, which splits it in two
+        let pieces = sample(&Curve::Graph(expr::parse("1/x").unwrap()), (-1.0, 1.0)).unwrap();
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].len() + pieces[1].len(), SAMPLES);
+        let circle = sample(
+            &Curve::Polar(expr::parse("1").unwrap()),
+            DEFAULT_CURVE_DOMAIN,
+        );
+        let circle = circle.unwrap().concat();
+        assert!(circle
+            .iter()
+            .all(|(x, y)| ((x * x + y * y).sqrt() - 1.0).abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_ticks() {
+        assert_eq!(ticks((-10.0, 10.0)), (vec![-10.0, -5.0, 0.0, 5.0, 10.0], 0));
+        assert_eq!(ticks((0.0, 1.0)).0.len(), 6);
+        assert_eq!(ticks((0.0, 1.0)).1, 1);
+        assert_eq!(label(-2.5, 1), "−2.5");
+        assert_eq!(label(-0.0001, 2), "0.00");
+    }
+}
@@ -1,17 +1,23 @@
 //! Lets users configure the preferred markup.
 
+use std::str::FromStr;
+
 use crate::{
-    config::REDIS_URL,
+    storage::Storage,
     utils::{Context, Error},
 };
+use anyhow::anyhow;
 use poise::{serenity_prelude::User, ChoiceParameter};
-use redis::{Commands, ErrorKind, FromRedisValue, RedisError, RedisResult, ToRedisArgs};
 
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, ChoiceParameter)]
 /// The preferred math markup to use inside dollar signs.
 pub(crate) enum MathMarkup {
     Typst,
+    /// LaTeX, converted to Typst.
     Latex,
+    /// LaTeX, rendered by a real TeX installation if there is one.
+    #[name = "LaTeX (full TeX)"]
+    Tex,
 }
 
 impl Default for MathMarkup {
@@ -20,48 +26,61 @@ impl Default for MathMarkup {
     }
 }
 
-impl FromRedisValue for MathMarkup {
-    fn from_redis_value(v: &redis::Value) -> RedisResult<Self> {
-        let str_v: String = FromRedisValue::from_redis_value(v)?;
-        match str_v.as_str() {
-            "latex" => Ok(Self::Latex),
-            "typst" => Ok(Self::Typst),
-            _ => Err(RedisError::from((
-                ErrorKind::TypeError,
-                "Not valid math markup lang",
-                str_v,
-            ))),
+impl MathMarkup {
+    /// The name the preference is stored under.
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Latex => "latex",
+            Self::Typst => "typst",
+            Self::Tex => "tex",
         }
     }
 }
 
-impl ToRedisArgs for MathMarkup {
-    fn write_redis_args<W>(&self, out: &mut W)
-    where
-        W: ?Sized + redis::RedisWrite,
-    {
-        out.write_arg(match *self {
-            Self::Latex => b"latex",
-            Self::Typst => b"typst",
-        })
+impl FromStr for MathMarkup {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latex" => Ok(Self::Latex),
+            "typst" => Ok(Self::Typst),
+            "tex" => Ok(Self::Tex),
+            _ => Err(anyhow!("Not valid math markup lang: {}", s).into()),
+        }
     }
 }
 
-/// The name of the math markup preference DB.
+/// The name of the math markup preference.
 const MATH_MARKUP: &str = "math_markup";
 
-/// Get the preferred markup language for a user.
-pub(crate) fn get_preferred_markup(user: &User) -> RedisResult<Option<MathMarkup>> {
-    let mut client = redis::Client::open(REDIS_URL)?;
-    let pref: Option<MathMarkup> = client.hget(MATH_MARKUP, &user.name)?;
-    Ok(pref)
+/// Get the preferred markup language for a user. Preferences saved under the user's name, from
+/// before they were saved under their ID, are moved over the first time they're read.
+pub(crate) async fn get_preferred_markup(
+    user: &User,
+    storage: &dyn Storage,
+) -> Result<Option<MathMarkup>, Error> {
+    let mut pref = storage.preference(MATH_MARKUP, user.id).await?;
+    if pref.is_none() {
+        pref = storage.legacy_preference(MATH_MARKUP, &user.name).await?;
+        if let Some(legacy) = &pref {
+            storage.set_preference(MATH_MARKUP, user.id, legacy).await?;
+            storage
+                .remove_legacy_preference(MATH_MARKUP, &user.name)
+                .await?;
+        }
+    }
+    pref.map(|p| p.parse()).transpose()
 }
 
 /// Set the preferred markup language for a user.
-pub(crate) fn set_preferred_markup(user: &User, pref: &MathMarkup) -> RedisResult<()> {
-    let mut client = redis::Client::open(REDIS_URL)?;
-    client.hset(MATH_MARKUP, &user.name, pref)?;
-    Ok(())
+pub(crate) async fn set_preferred_markup(
+    user: &User,
+    pref: &MathMarkup,
+    storage: &dyn Storage,
+) -> Result<(), Error> {
+    storage
+        .set_preference(MATH_MARKUP, user.id, pref.as_str())
+        .await
 }
 
 /// Set what markup language you want $$ to be interpreted as. Only applies to
@@ -73,7 +92,7 @@ pub(crate) async fn set_default_math_markup(
     ctx: Context<'_>,
     #[description = "Math markup language to render inside $$"] preference: MathMarkup,
 ) -> Result<(), Error> {
-    match set_preferred_markup(ctx.author(), &preference) {
+    match set_preferred_markup(ctx.author(), &preference, ctx.data().storage.as_ref()).await {
         Ok(()) => {
             ctx.say(format!(
                 "Success! Your preferred math markup is now {}.
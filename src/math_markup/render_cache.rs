@@ -0,0 +1,278 @@
+//! A cache of Typst renders, so formulas that are posted again (or messages edited back and forth)
+//! don't have to be compiled again.
+//!
+//! Renders are addressed by a hash of everything that goes into them: the full source including the
+//! preamble (and so the user's theme and macros), the resolution and the output format. The most
+//! recently used ones are kept in memory up to a size limit. If a directory is configured, every
+//! render is also written there, so the cache survives restarts; that directory isn't cleaned up
+//! automatically.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+
+use crate::config::config;
+use crate::math_markup::typst_base::{PageOutput, RenderedFile};
+use crate::utils::{Context, Error};
+
+/// Numbers files being written, to keep concurrent writes apart.
+static NEXT_WRITE: AtomicUsize = AtomicUsize::new(0);
+
+/// Works out the key a render is cached under.
+pub(crate) fn cache_key(source: &str, resolution: u32, output: PageOutput) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_bytes());
+    hasher.update(resolution.to_le_bytes());
+    hasher.update(format!("{:?}", output).as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The total size of some files.
+fn size(files: &[RenderedFile]) -> usize {
+    files.iter().map(|f| f.data.len()).sum()
+}
+
+/// The renders kept in memory.
+struct Memory {
+    entries: LruCache<String, Vec<RenderedFile>>,
+    /// The total size of the entries.
+    bytes: usize,
+}
+
+pub(crate) struct RenderCache {
+    memory: Mutex<Memory>,
+    /// The most bytes of renders to keep in memory.
+    max_bytes: usize,
+    /// Where renders are written, if anywhere.
+    dir: Option<PathBuf>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl RenderCache {
+    fn new(max_bytes: usize, dir: Option<PathBuf>) -> Self {
+        Self {
+            memory: Mutex::new(Memory {
+                entries: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            max_bytes,
+            dir,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Looks up a render, in memory and then on disk.
+    pub(crate) async fn get(&self, key: &str) -> Option<Vec<RenderedFile>> {
+        let remembered = self
+            .memory
+            .lock()
+            .expect("Render cache poisoned")
+            .entries
+            .get(key)
+            .cloned();
+        let found = match remembered {
+            Some(files) => Some(files),
+            None => {
+                let read = self.read(key).await;
+                if let Some(files) = &read {
+                    self.remember(key.to_string(), files.clone());
+                }
+                read
+            }
+        };
+
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Adds a render to the cache.
+    pub(crate) async fn insert(&self, key: String, files: &[RenderedFile]) {
+        if let Err(e) = self.write(&key, files).await {
+            println!("Couldn't write render {} to the cache: {}", key, e);
+        }
+        self.remember(key, files.to_vec());
+    }
+
+    /// Keeps a render in memory, forgetting the least recently used ones to make room.
+    fn remember(&self, key: String, files: Vec<RenderedFile>) {
+        let new_bytes = size(&files);
+        if new_bytes > self.max_bytes {
+            return;
+        }
+        let mut memory = self.memory.lock().expect("Render cache poisoned");
+        if let Some(old) = memory.entries.put(key, files) {
+            memory.bytes -= size(&old);
+        }
+        memory.bytes += new_bytes;
+        while memory.bytes > self.max_bytes {
+            match memory.entries.pop_lru() {
+                Some((_, old)) => memory.bytes -= size(&old),
+                None => break,
+            }
+        }
+    }
+
+    /// Reads a render from disk. Each render is a directory of its files, numbered to keep them in
+    /// order.
+    async fn read(&self, key: &str) -> Option<Vec<RenderedFile>> {
+        let dir = self.dir.as_ref()?.join(key);
+        let mut entries = tokio::fs::read_dir(&dir).await.ok()?;
+        let mut files = vec![];
+        while let Some(entry) = entries.next_entry().await.ok()? {
+            let numbered = entry.file_name().into_string().ok()?;
+            let (_, name) = numbered.split_once('-')?;
+            files.push((
+                numbered.clone(),
+                RenderedFile {
+                    name: name.to_string(),
+                    data: tokio::fs::read(entry.path()).await.ok()?,
+                },
+            ));
+        }
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Some(files.into_iter().map(|(_, file)| file).collect())
+    }
+
+    /// Writes a render to disk, if there's somewhere to write it.
+    async fn write(&self, key: &str, files: &[RenderedFile]) -> std::io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let dest = dir.join(key);
+        if tokio::fs::metadata(&dest).await.is_ok() {
+            return Ok(());
+        }
+        // write everything somewhere else first, so a half-written render is never read
+        let partial = dir.join(format!(
+            "{}.partial-{}",
+            key,
+            NEXT_WRITE.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::create_dir_all(&partial).await?;
+        for (i, file) in files.iter().enumerate() {
+            tokio::fs::write(partial.join(format!("{:03}-{}", i, file.name)), &file.data).await?;
+        }
+        if tokio::fs::rename(&partial, &dest).await.is_err() {
+            // someone else got there first
+            let _ = tokio::fs::remove_dir_all(&partial).await;
+        }
+        Ok(())
+    }
+
+    fn stats(&self) -> String {
+        let memory = self.memory.lock().expect("Render cache poisoned");
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let rate = if hits + misses == 0 {
+            0.0
+        } else {
+            100.0 * hits as f64 / (hits + misses) as f64
+        };
+        format!(
+            "{} hits and {} misses ({:.1}% hit rate). {} renders in memory, taking up {:.1} MiB.",
+            hits,
+            misses,
+            rate,
+            memory.entries.len(),
+            memory.bytes as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+/// The render cache, set up from the configuration the first time it's used.
+pub(crate) fn render_cache() -> &'static RenderCache {
+    static CACHE: OnceLock<RenderCache> = OnceLock::new();
+    CACHE.get_or_init(|| {
+        let typst = &config().typst;
+        let dir = Some(&typst.cache_dir)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+        RenderCache::new(typst.cache_size * 1024 * 1024, dir)
+    })
+}
+
+/// Shows how well the render cache is doing.
+#[poise::command(slash_command)]
+pub(crate) async fn cache(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say(format!("Render cache: {}", render_cache().stats()))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(name: &str, bytes: usize) -> Vec<RenderedFile> {
+        vec![RenderedFile {
+            name: name.to_string(),
+            data: vec![0; bytes],
+        }]
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let key = cache_key("$x$", 2000, PageOutput::Image);
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, cache_key("$x$", 2000, PageOutput::Image));
+        assert_ne!(key, cache_key("$y$", 2000, PageOutput::Image));
+        assert_ne!(key, cache_key("$x$", 1000, PageOutput::Image));
+        assert_ne!(key, cache_key("$x$", 2000, PageOutput::Pdf));
+    }
+
+    #[tokio::test]
+    async fn test_memory_limit() {
+        let cache = RenderCache::new(100, None);
+        cache.insert("a".to_string(), &png("a.png", 40)).await;
+        cache.insert("b".to_string(), &png("b.png", 40)).await;
+        assert!(cache.get("a").await.is_some());
+        // b is now the least recently used, so it makes way
+        cache.insert("c".to_string(), &png("c.png", 40)).await;
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("c").await.is_some());
+        // too big to keep at all
+        cache.insert("d".to_string(), &png("d.png", 101)).await;
+        assert!(cache.get("d").await.is_none());
+
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 3);
+        assert_eq!(cache.misses.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_disk() {
+        let dir = std::env::temp_dir().join(format!("nano-render-cache-{}", std::process::id()));
+        let files = vec![
+            RenderedFile {
+                name: "Rendered-1.png".to_string(),
+                data: vec![1],
+            },
+            RenderedFile {
+                name: "Rendered-2.png".to_string(),
+                data: vec![2, 2],
+            },
+        ];
+        RenderCache::new(0, Some(dir.clone()))
+            .insert("key".to_string(), &files)
+            .await;
+        // a fresh cache, as after a restart
+        let cache = RenderCache::new(100, Some(dir.clone()));
+        assert_eq!(cache.get("key").await, Some(files));
+        assert_eq!(cache.get("other").await, None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
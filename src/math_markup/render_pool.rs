@@ -0,0 +1,396 @@
+//! Renders Typst in worker processes, so that a document that never finishes or uses too much memory
+//! can be killed without taking the bot with it or holding up other renders.
+//!
+//! Workers are this program started again with [WORKER_FLAG]. A worker reads requests from stdin, a
+//! line of JSON each, and answers each on stdout with a line of JSON describing the result, followed by
+//! the bytes of the rendered files.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::io::FromRawFd;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use crate::config::config;
+use crate::math_markup::typst_base::{
+    evict_unused, PageOutput, RenderErrors, RenderSettings, RenderedFile, TypstRendered,
+};
+
+/// The command-line flag that makes the program a worker.
+pub(crate) const WORKER_FLAG: &str = "--render-worker";
+
+type RenderResult = Result<Vec<RenderedFile>, RenderErrors>;
+
+/// A render for a worker to do.
+#[derive(Serialize, Deserialize)]
+struct Request {
+    source: String,
+    settings: RenderSettings,
+    output: PageOutput,
+}
+
+/// A rendered file in a worker's answer, whose bytes come after the answer's first line.
+#[derive(Serialize, Deserialize)]
+struct FileHeader {
+    name: String,
+    length: usize,
+}
+
+/// Sends the result of a render back from a worker.
+fn write_response(out: &mut impl Write, result: &RenderResult) -> io::Result<()> {
+    let header: Result<Vec<FileHeader>, &RenderErrors> = match result {
+        Ok(files) => Ok(files
+            .iter()
+            .map(|file| FileHeader {
+                name: file.name.clone(),
+                length: file.data.len(),
+            })
+            .collect()),
+        Err(e) => Err(e),
+    };
+    serde_json::to_writer(&mut *out, &header)?;
+    out.write_all(b"\n")?;
+    for file in result.iter().flatten() {
+        out.write_all(&file.data)?;
+    }
+    out.flush()
+}
+
+/// Reads the result of a render from a worker.
+fn read_response(input: &mut impl BufRead) -> io::Result<RenderResult> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let header: Result<Vec<FileHeader>, RenderErrors> = serde_json::from_str(&line)?;
+    let headers = match header {
+        Ok(headers) => headers,
+        Err(e) => return Ok(Err(e)),
+    };
+    let mut files = vec![];
+    for header in headers {
+        let mut data = vec![0; header.length];
+        input.read_exact(&mut data)?;
+        files.push(RenderedFile {
+            name: header.name,
+            data,
+        });
+    }
+    Ok(Ok(files))
+}
+
+/// Limits the memory this process can use to a number of MiB, or doesn't if it's 0.
+fn limit_memory(mib: usize) -> io::Result<()> {
+    if mib == 0 {
+        return Ok(());
+    }
+    let bytes = (mib as libc::rlim_t).saturating_mul(1024 * 1024);
+    let limit = libc::rlimit {
+        rlim_cur: bytes,
+        rlim_max: bytes,
+    };
+    // SAFETY: the limit is a valid `rlimit` that outlives the call
+    match unsafe { libc::setrlimit(libc::RLIMIT_DATA, &limit) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Takes stdout for answers, and sends everything else printed there to stderr instead, so that it
+/// can't be mistaken for an answer.
+fn take_stdout() -> io::Result<BufWriter<File>> {
+    io::stdout().flush()?;
+    // SAFETY: only the standard streams are duplicated, and the new descriptor is owned by the file
+    // alone
+    unsafe {
+        let answers = libc::dup(libc::STDOUT_FILENO);
+        if answers < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(BufWriter::new(File::from_raw_fd(answers)))
+    }
+}
+
+/// Runs this process as a worker, rendering each request on stdin until it's closed.
+pub(crate) fn run_worker() -> io::Result<()> {
+    limit_memory(config().typst.memory_limit)?;
+    let mut answers = take_stdout()?;
+    for line in io::stdin().lock().lines() {
+        let request: Request = serde_json::from_str(&line?)?;
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            TypstRendered::render(&request.source, &request.settings, request.output)
+        }))
+        .unwrap_or(Err(RenderErrors::Crashed));
+        evict_unused();
+        write_response(&mut answers, &result)?;
+    }
+    Ok(())
+}
+
+/// A running worker.
+struct WorkerProcess {
+    child: Child,
+    requests: ChildStdin,
+    /// The worker's answers, read on their own thread so that waiting for one can time out.
+    responses: mpsc::Receiver<RenderResult>,
+}
+
+impl WorkerProcess {
+    fn start(mut command: Command) -> io::Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let requests = child.stdin.take().expect("Worker stdin is piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("Worker stdout is piped"));
+        let (answers, responses) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("typst-render-output".to_string())
+            .spawn(move || {
+                while let Ok(response) = read_response(&mut stdout) {
+                    if answers.send(response).is_err() {
+                        return;
+                    }
+                }
+            })
+            .expect("Couldn't start a render worker");
+        Ok(Self {
+            child,
+            requests,
+            responses,
+        })
+    }
+
+    /// Renders a document. If it isn't done by the deadline the render fails with
+    /// [RenderErrors::Timeout], and if the worker dies with [RenderErrors::Crashed]; either way the
+    /// worker can't be used again.
+    fn render(
+        &mut self,
+        request: &Request,
+        deadline: Instant,
+    ) -> Result<RenderResult, RenderErrors> {
+        let mut line = serde_json::to_vec(request).expect("Render requests can be serialized");
+        line.push(b'\n');
+        self.requests
+            .write_all(&line)
+            .and_then(|()| self.requests.flush())
+            .map_err(|_| RenderErrors::Crashed)?;
+        match self
+            .responses
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            Ok(result) => Ok(result),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(RenderErrors::Timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(RenderErrors::Crashed),
+        }
+    }
+}
+
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A render waiting for a worker.
+struct Job {
+    request: Request,
+    /// When the render has to be done by, counting the time spent waiting for a worker.
+    deadline: Instant,
+    result: oneshot::Sender<RenderResult>,
+}
+
+/// Hands renders out to a fixed number of threads, each with a worker it kills and starts again
+/// whenever a render takes too long.
+pub(crate) struct RenderPool {
+    jobs: Mutex<mpsc::Sender<Job>>,
+    limit: Duration,
+}
+
+impl RenderPool {
+    /// Starts `workers` threads, which start their workers with `command` when they first need them.
+    fn new(workers: usize, limit: Duration, command: fn() -> io::Result<Command>) -> Self {
+        let (jobs, queue) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..workers {
+            let queue = Arc::clone(&queue);
+            std::thread::Builder::new()
+                .name("typst-render".to_string())
+                .spawn(move || work(&queue, command))
+                .expect("Couldn't start a render thread");
+        }
+        Self {
+            jobs: Mutex::new(jobs),
+            limit,
+        }
+    }
+
+    pub(crate) async fn render(
+        &self,
+        msg: &str,
+        settings: &RenderSettings,
+        output: PageOutput,
+    ) -> RenderResult {
+        let (result, received) = oneshot::channel();
+        let job = Job {
+            request: Request {
+                source: msg.to_string(),
+                settings: settings.clone(),
+                output,
+            },
+            deadline: Instant::now() + self.limit,
+            result,
+        };
+        self.jobs
+            .lock()
+            .expect("Render queue poisoned")
+            .send(job)
+            .expect("Render threads never stop");
+
+        match tokio::time::timeout(self.limit, received).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(RenderErrors::Crashed),
+            Err(_) => Err(RenderErrors::Timeout),
+        }
+    }
+}
+
+/// Does jobs from the queue until the pool is gone, starting a worker whenever there isn't one that
+/// can be used.
+fn work(queue: &Mutex<mpsc::Receiver<Job>>, command: fn() -> io::Result<Command>) {
+    let mut process = None;
+    loop {
+        let job: Job = match queue.lock().expect("Render queue poisoned").recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        // whoever asked for it has stopped waiting
+        if job.result.is_closed() || Instant::now() >= job.deadline {
+            continue;
+        }
+        let mut worker = match process
+            .take()
+            .map_or_else(|| command().and_then(WorkerProcess::start), Ok)
+        {
+            Ok(worker) => worker,
+            Err(e) => {
+                eprintln!("Couldn't start a render worker: {}", e);
+                let _ = job.result.send(Err(RenderErrors::Crashed));
+                continue;
+            }
+        };
+        let result = match worker.render(&job.request, job.deadline) {
+            Ok(result) => {
+                process = Some(worker);
+                result
+            }
+            // the worker is killed as it's dropped, and another is started for the next job
+            Err(e) => Err(e),
+        };
+        let _ = job.result.send(result);
+    }
+}
+
+/// Starts this program again as a worker, with the same configuration.
+fn worker_command() -> io::Result<Command> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command.args(std::env::args_os().skip(1)).arg(WORKER_FLAG);
+    Ok(command)
+}
+
+pub(crate) fn render_pool() -> &'static RenderPool {
+    static POOL: OnceLock<RenderPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        RenderPool::new(
+            config().typst.workers,
+            Duration::from_secs(config().typst.timeout),
+            worker_command,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A stand-in for a worker, which never answers requests with `loop` in them and answers the
+    /// rest with the same file.
+    fn fake_worker() -> io::Result<Command> {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            r#"while read -r line; do
+                case "$line" in
+                    *loop*) exec sleep 1000 ;;
+                    *) printf '{"Ok":[{"name":"Rendered.png","length":3}]}\nabc' ;;
+                esac
+            done"#,
+        ]);
+        Ok(command)
+    }
+
+    #[test]
+    fn test_response() {
+        let files = vec![
+            RenderedFile {
+                name: "Rendered-1.png".to_string(),
+                data: b"first\nline".to_vec(),
+            },
+            RenderedFile {
+                name: "Rendered-2.png".to_string(),
+                data: vec![],
+            },
+        ];
+        let mut sent = vec![];
+        write_response(&mut sent, &Ok(files.clone())).unwrap();
+        write_response(
+            &mut sent,
+            &Err(RenderErrors::TooManyPages {
+                pages: 12,
+                allowed: 10,
+            }),
+        )
+        .unwrap();
+
+        let mut received = Cursor::new(sent);
+        assert_eq!(read_response(&mut received).unwrap().unwrap(), files);
+        assert!(matches!(
+            read_response(&mut received).unwrap(),
+            Err(RenderErrors::TooManyPages {
+                pages: 12,
+                allowed: 10
+            })
+        ));
+        assert!(read_response(&mut received).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_runaway_render() {
+        let pool = RenderPool::new(1, Duration::from_secs(1), fake_worker);
+        let settings = RenderSettings::default();
+        // more runaway renders than there are workers
+        for _ in 0..3 {
+            assert!(matches!(
+                pool.render("#loop {}", &settings, PageOutput::Image).await,
+                Err(RenderErrors::Timeout)
+            ));
+        }
+        // the stuck worker was killed, so the next render still gets one
+        assert_eq!(
+            pool.render("$x$", &settings, PageOutput::Image)
+                .await
+                .unwrap(),
+            vec![RenderedFile {
+                name: "Rendered.png".to_string(),
+                data: b"abc".to_vec(),
+            }]
+        );
+    }
+}
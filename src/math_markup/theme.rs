@@ -0,0 +1,225 @@
+//! Lets users configure how their renders look: colors, font, text size and resolution.
+
+use std::sync::OnceLock;
+
+use crate::{
+    math_markup::typst_base::get_fonts,
+    storage::Storage,
+    utils::{Context, Error},
+};
+use anyhow::anyhow;
+use poise::ChoiceParameter;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::UserId;
+
+/// The name of the theme preference.
+const RENDER_THEME: &str = "render_theme";
+
+/// The smallest and largest text sizes, in points.
+const SIZES: (u32, u32) = (8, 48);
+
+/// The lowest and highest resolutions, in pixels.
+const RESOLUTIONS: (u32, u32) = (500, 4000);
+
+/// The colors of a render.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize, ChoiceParameter)]
+pub(crate) enum ColorScheme {
+    /// Light text on Discord's dark background.
+    Dark,
+    /// Dark text on white, for Discord's light theme.
+    Light,
+}
+
+impl ColorScheme {
+    /// The text color, as RGB.
+    pub(crate) fn fg(&self) -> [u8; 3] {
+        match self {
+            Self::Dark => [219, 222, 225],
+            Self::Light => [49, 51, 56],
+        }
+    }
+
+    /// The background color, as RGB.
+    pub(crate) fn bg(&self) -> [u8; 3] {
+        match self {
+            Self::Dark => [49, 51, 56],
+            Self::Light => [255, 255, 255],
+        }
+    }
+}
+
+/// How a user's renders look.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Theme {
+    pub colors: ColorScheme,
+    /// Whether to leave out the background, so renders blend in with any Discord theme.
+    pub transparent: bool,
+    /// The font family for text, if not the default. Only applies to Typst.
+    pub font: Option<String>,
+    /// The text size, in points.
+    pub size: u32,
+    /// Roughly how many pixels across a render should be.
+    pub resolution: u32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            colors: ColorScheme::Dark,
+            transparent: false,
+            font: None,
+            size: 18,
+            resolution: 2000,
+        }
+    }
+}
+
+impl Theme {
+    /// A one-line description of the theme.
+    fn describe(&self) -> String {
+        format!(
+            "{}{}, {} at {}pt, about {}px across",
+            self.colors,
+            if self.transparent {
+                " (transparent)"
+            } else {
+                ""
+            },
+            self.font.as_deref().unwrap_or("the default font"),
+            self.size,
+            self.resolution
+        )
+    }
+}
+
+/// The families of the fonts renders can use, sorted.
+pub(crate) fn font_families() -> &'static [String] {
+    static FAMILIES: OnceLock<Vec<String>> = OnceLock::new();
+    FAMILIES.get_or_init(|| {
+        let mut families: Vec<String> = get_fonts()
+            .iter()
+            .map(|font| font.info().family.clone())
+            .collect();
+        families.sort();
+        families.dedup();
+        families
+    })
+}
+
+/// Get a user's theme.
+pub(crate) async fn get_theme(user: UserId, storage: &dyn Storage) -> Result<Theme, Error> {
+    let raw = storage.preference(RENDER_THEME, user).await?;
+    Ok(raw
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default())
+}
+
+/// Set a user's theme.
+async fn set_theme(user: UserId, theme: &Theme, storage: &dyn Storage) -> Result<(), Error> {
+    let serialized = serde_json::to_string(theme).expect("Themes are always serializable");
+    storage
+        .set_preference(RENDER_THEME, user, &serialized)
+        .await
+}
+
+/// Checks that a value is within bounds.
+fn check_range(name: &str, value: u32, (min, max): (u32, u32)) -> Result<u32, Error> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(anyhow!("The {} has to be between {} and {}.", name, min, max).into())
+    }
+}
+
+async fn autocomplete_font(_ctx: Context<'_>, partial: &str) -> impl Iterator<Item = String> {
+    let partial = partial.to_lowercase();
+    font_families()
+        .iter()
+        .filter(move |family| family.to_lowercase().contains(&partial))
+        .take(25)
+        .cloned()
+}
+
+/// Changes how the math you render looks. Leave everything out to see your current settings.
+#[poise::command(slash_command)]
+pub(crate) async fn theme(
+    ctx: Context<'_>,
+    #[description = "Dark or light colors"] colors: Option<ColorScheme>,
+    #[description = "Whether to leave out the background"] transparent: Option<bool>,
+    #[description = "Font for text (Typst only), or \"default\""]
+    #[autocomplete = "autocomplete_font"]
+    font: Option<String>,
+    #[description = "Text size in points"] size: Option<u32>,
+    #[description = "About how many pixels wide renders are"] resolution: Option<u32>,
+) -> Result<(), Error> {
+    let storage = ctx.data().storage.as_ref();
+    let mut theme = get_theme(ctx.author().id, storage).await?;
+    let changed = colors.is_some()
+        || transparent.is_some()
+        || font.is_some()
+        || size.is_some()
+        || resolution.is_some();
+
+    if let Some(colors) = colors {
+        theme.colors = colors;
+    }
+    if let Some(transparent) = transparent {
+        theme.transparent = transparent;
+    }
+    match font {
+        Some(font) if font.eq_ignore_ascii_case("default") => theme.font = None,
+        Some(font) => match font_families()
+            .iter()
+            .find(|family| family.eq_ignore_ascii_case(font.trim()))
+        {
+            Some(family) => theme.font = Some(family.clone()),
+            None => {
+                return Err(anyhow!(
+                    "There's no font called {:?}. The fonts are: {}.",
+                    font,
+                    font_families().join(", ")
+                )
+                .into())
+            }
+        },
+        None => {}
+    }
+    if let Some(size) = size {
+        theme.size = check_range("size", size, SIZES)?;
+    }
+    if let Some(resolution) = resolution {
+        theme.resolution = check_range("resolution", resolution, RESOLUTIONS)?;
+    }
+
+    if changed {
+        set_theme(ctx.author().id, &theme, storage).await?;
+        ctx.say(format!("Your renders are now {}.", theme.describe()))
+            .await?;
+    } else {
+        ctx.say(format!("Your renders are {}.", theme.describe()))
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_describe() {
+        let theme = Theme {
+            colors: ColorScheme::Light,
+            transparent: true,
+            font: Some("Fira Math".to_string()),
+            ..Theme::default()
+        };
+        assert_eq!(
+            theme.describe(),
+            "Light (transparent), Fira Math at 18pt, about 2000px across"
+        );
+        assert!(check_range("size", 7, SIZES).is_err());
+        assert_eq!(check_range("size", 12, SIZES).unwrap(), 12);
+    }
+}
@@ -1,24 +1,26 @@
+use crate::config::config;
+use crate::math_markup::packages::{package_dir, unpack_new_archives};
+use crate::math_markup::render_cache::{cache_key, render_cache};
+use crate::math_markup::render_pool::render_pool;
+use crate::math_markup::theme::Theme;
 use comemo::Prehashed;
-use serenity::futures::lock::Mutex;
-use std::cell::RefCell;
+use poise::ChoiceParameter;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io::Cursor;
-use std::sync::OnceLock;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use tiny_skia::{Pixmap, PixmapPaint, Transform};
 use typst::diag::{FileError, FileResult, Severity, SourceDiagnostic};
 use typst::eval::Tracer;
 use typst::foundations::{Bytes, Datetime};
-use typst::layout::{Abs, Axes};
+use typst::layout::{Abs, Axes, Frame};
 use typst::syntax::{FileId, Source};
 use typst::text::{Font, FontBook};
 use typst::Library;
 
-//Needed for reading files for packages
-#[derive(Clone)]
-struct FileEntry {
-    bytes: Bytes,
-    source: Option<Source>,
-}
-
-fn get_fonts() -> Vec<Font> {
+pub(crate) fn get_fonts() -> Vec<Font> {
     std::fs::read_dir("fonts")
         .unwrap()
         .map(Result::unwrap)
@@ -29,116 +31,152 @@ fn get_fonts() -> Vec<Font> {
         })
         .collect()
 }
-/*
-To explain the package layout; because this might not be obvious
-packages/
-    {package_name}-{version}/
-        entry_point.typ -> The important file
-        LICENSE -> I need to check if this is legal, milord
-        typst.toml -> tells us where the entrypoint is
-
-I do not plan on doing stuff that involves downloading packages as needed, but if needed, the packages can be downloaded from
-https://packages.typst.org/{namespace}/{name}-{version}.tar.gz
-namespace will be preview, as per typst 0.10.0; the tar file should just be extracted into the folder
-*/
-
-impl FileEntry {
-    fn source(&mut self, id: FileId) -> FileResult<Source> {
-        // Fallible `get_or_insert`.
-        let src = match &self.source {
-            Some(e) => e,
-            None => {
+
+/// How many renders a package file can go unused before it's forgotten.
+const FILE_MAX_AGE: usize = 200;
+
+/// How many renders Typst's memoized results can go unused before they're forgotten. This is what the
+/// Typst CLI uses when watching files.
+const COMEMO_MAX_AGE: usize = 10;
+
+/// A file that's been read, and its source once it's parsed.
+struct FileSlot {
+    bytes: Bytes,
+    source: OnceLock<FileResult<Source>>,
+    /// The number of the render that last used this file.
+    last_used: AtomicUsize,
+}
+
+impl FileSlot {
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        self.source
+            .get_or_init(|| {
                 let contents =
                     std::str::from_utf8(&self.bytes).map_err(|_| FileError::InvalidUtf8)?;
                 // Defuse the BOM!
                 let contents = contents.trim_start_matches('\u{feff}');
-                let source = Source::new(id, contents.into());
-                self.source.insert(source)
-            }
-        };
-        Ok(src.clone())
+                Ok(Source::new(id, contents.into()))
+            })
+            .clone()
     }
 }
 
-pub(crate) struct TypstRendered {
+/// What every render shares, so it's only loaded once: the standard library, the fonts, and the
+/// package files that have been read.
+struct Resources {
     library: Prehashed<Library>,
     fontbook: Prehashed<FontBook>,
     fonts: Vec<Font>,
-    files: RefCell<Vec<(FileId, FileEntry)>>, //This used to be a hashmap, but bruh, there are like 3 to 5 files here maximum, its easier to traverse a list; RefCell is needed because I need to mutate this sometimes.
-    source: Option<Source>,
+    files: RwLock<HashMap<FileId, Arc<FileSlot>>>,
+    /// The number of renders so far.
+    renders: AtomicUsize,
 }
-impl TypstRendered {
-    pub(crate) fn new() -> Self {
+
+impl Resources {
+    fn new() -> Self {
         let fonts = get_fonts();
         Self {
             library: Prehashed::new(Library::build()),
             fontbook: Prehashed::new(FontBook::from_fonts(&fonts)),
             fonts,
-            files: RefCell::new(Vec::new()),
-            source: None,
+            files: RwLock::new(HashMap::new()),
+            renders: AtomicUsize::new(0),
         }
     }
-    fn file_id(&self, file_id: &FileId) -> Option<FileEntry> {
-        self.files
-            .borrow()
-            .iter()
-            .find_map(|(a, b)| if a == file_id { Some(b.clone()) } else { None })
-    }
-    fn file_entry(&self, id: &FileId) -> FileResult<FileEntry> {
-        //Check if file is already in vector
-        if let Some(a) = self.file_id(id) {
-            return Ok(a);
+
+    /// Gets a package file, reading it if it hasn't been read yet.
+    fn file(&self, id: FileId) -> FileResult<Arc<FileSlot>> {
+        let now = self.renders.load(Ordering::Relaxed);
+        if let Some(slot) = self.files.read().expect("File cache poisoned").get(&id) {
+            slot.last_used.store(now, Ordering::Relaxed);
+            return Ok(Arc::clone(slot));
         }
 
-        //Check if it is a package, should be infallible, but yeah
-        let p = id
+        let package = id
             .package()
-            .ok_or(FileError::NotFound(id.vpath().as_rootless_path().into()))?;
+            .ok_or_else(|| FileError::NotFound(id.vpath().as_rootless_path().into()))?;
+        let dir = package_dir(package)?;
+        let path = id.vpath().resolve(&dir).ok_or(FileError::AccessDenied)?;
+        let bytes = std::fs::read(&path).map_err(|error| FileError::from_io(error, &path))?;
+        let slot = Arc::new(FileSlot {
+            bytes: bytes.into(),
+            source: OnceLock::new(),
+            last_used: AtomicUsize::new(now),
+        });
+        // another render might have read it in the meantime
+        let mut files = self.files.write().expect("File cache poisoned");
+        Ok(Arc::clone(files.entry(id).or_insert(slot)))
+    }
 
-        println!(
-            "Loading new file: {}, {} - {} is the size of list of files",
-            p.name.as_str(),
-            id.vpath().as_rooted_path().to_str().unwrap_or("oopsie!"),
-            self.files.borrow().len()
-        );
-        let mut dir = std::path::PathBuf::new();
-        dir.push("packages");
-        let package_folder = format!(
-            "{}-{}.{}.{}",
-            p.name, p.version.major, p.version.minor, p.version.patch
-        );
-        dir.push(&package_folder);
+    /// Counts a finished render, forgetting files and memoized results that haven't been used in a
+    /// while.
+    fn evict(&self) {
+        let now = self.renders.fetch_add(1, Ordering::Relaxed) + 1;
+        self.files
+            .write()
+            .expect("File cache poisoned")
+            .retain(|_, slot| {
+                now.saturating_sub(slot.last_used.load(Ordering::Relaxed)) <= FILE_MAX_AGE
+            });
+        comemo::evict(COMEMO_MAX_AGE);
+    }
+}
 
-        let path = id.vpath().resolve(dir.as_path()).unwrap();
-        let contents = std::fs::read(&path).map_err(|error| FileError::from_io(error, &path))?;
+fn resources() -> &'static Resources {
+    static RESOURCES: OnceLock<Resources> = OnceLock::new();
+    RESOURCES.get_or_init(Resources::new)
+}
 
-        let fe = FileEntry {
-            bytes: contents.into(),
-            source: None,
-        };
-        self.files.borrow_mut().push((*id, fe));
-        // self.files.push((id.clone(), fe));
-        //Should return the above file_entry, and there should be an easy way to do it, but i dont know;
+/// Counts a finished render, forgetting what earlier renders loaded that hasn't been used in a while.
+pub(crate) fn evict_unused() {
+    resources().evict();
+}
+
+/// The world a single render happens in: its source, and the [Resources] every render shares. Any
+/// number of these can compile at once.
+pub(crate) struct TypstRendered {
+    resources: &'static Resources,
+    source: Source,
+}
 
-        Ok(self.file_id(id).unwrap().clone())
+impl TypstRendered {
+    fn new(src: &str, settings: &RenderSettings) -> Self {
+        Self {
+            resources: resources(),
+            source: Source::detached(Self::full_source(src, settings)),
+        }
     }
-    fn preamble() -> String {
+
+    fn preamble(theme: &Theme) -> String {
         let imports = r#"
             #import "@preview/whalogen:0.1.0": *
             #import "@preview/mitex:0.2.1": *        
             "#;
-        let theme = r#"
-            #let fg = rgb(219, 222, 225)
-            #let bg = rgb(49, 51, 56)
-        "#;
+        let [fr, fg, fb] = theme.colors.fg();
+        let [br, bg, bb] = theme.colors.bg();
+        let colors = format!(
+            "
+            #let fg = rgb({fr}, {fg}, {fb})
+            #let bg = rgb({br}, {bg}, {bb})
+        "
+        );
         let page_size = "#set page(width: auto, height: auto, margin: 10pt)\n";
-        let text_options = r#"
+        let font = theme
+            .font
+            .as_ref()
+            .map(|f| format!("{:?},", f))
+            .unwrap_or_default();
+        let size = theme.size;
+        let page_fill = if theme.transparent { "none" } else { "bg" };
+        let text_options = format!(
+            r#"
             #set text(
                 font: (
+                    {font}
                     "EB Garamond 12",
                     "DejaVu Sans Mono"
                       ),
-                size: 18pt,
+                size: {size}pt,
                 number-type: "lining",
                 number-width: "tabular",
                 weight: "regular",
@@ -148,12 +186,13 @@ impl TypstRendered {
 
             // Preamble starts here
             #set page(
-              fill: bg
+              fill: {page_fill}
             )
             #set text(
               fill: fg,
             )
-        "#;
+        "#
+        );
         let math_remaining = r#"
             #let infty = [#sym.infinity];
 
@@ -190,61 +229,207 @@ impl TypstRendered {
                     content)})
             };
         "#;
-        imports.to_string() + theme + page_size + text_options + math_remaining
+        imports.to_string() + &colors + page_size + &text_options + math_remaining
     }
-    fn add_source(&mut self, src: &str) {
-        self.source = Some(Source::detached(Self::preamble() + src))
+    /// The full source of a render: the preamble, the user's macros and then their code.
+    fn full_source(src: &str, settings: &RenderSettings) -> String {
+        Self::preamble(&settings.theme) + &settings.macros + "\n" + src
     }
-    fn revert(&mut self) {
-        self.source = None;
+    /// Finds where diagnostics of the current source are in `msg`, the user's part of it.
+    fn locate(&self, diagnostics: &[SourceDiagnostic], msg: &str) -> Vec<Diagnostic> {
+        let main = self.main();
+        let offset = main.text().len() - msg.len();
+        diagnostics
+            .iter()
+            .map(|d| Diagnostic {
+                severity: d.severity,
+                message: d.message.to_string(),
+                hints: d.hints.iter().map(|h| h.to_string()).collect(),
+                location: Some(d.span)
+                    .filter(|span| span.id() == Some(main.id()))
+                    .and_then(|span| main.range(span))
+                    .filter(|range| range.start >= offset)
+                    .and_then(|range| {
+                        Location::find(msg, range.start - offset..range.end - offset)
+                    }),
+            })
+            .collect()
     }
 
-    pub(crate) fn render(&mut self, msg: &str) -> Result<Vec<u8>, RenderErrors> {
-        self.add_source(msg);
+    pub(crate) fn render(
+        msg: &str,
+        settings: &RenderSettings,
+        output: PageOutput,
+    ) -> Result<Vec<RenderedFile>, RenderErrors> {
+        let world = Self::new(msg, settings);
         let mut tracer = Tracer::default();
-        let document =
-            typst::compile(self, &mut tracer).map_err(|e| RenderErrors::SourceError(e.to_vec()))?;
-        let frame = document.pages.first().ok_or(RenderErrors::NoPageError)?;
-        let pixels = determine_pixels_per_point(frame.size())?;
-
-        let pixmap = typst_render::render(
-            frame,
-            pixels as f32,
-            typst::visualize::Color::from_u8(0, 0, 0, 0),
-        );
+        let document = typst::compile(&world, &mut tracer)
+            .map_err(|e| RenderErrors::SourceError(world.locate(&e, msg)))?;
 
-        let mut writer = Cursor::new(Vec::new());
+        let pages = &document.pages;
+        let max_pages = match output {
+            PageOutput::Album => config().typst.max_pages.min(MAX_ATTACHMENTS),
+            _ => config().typst.max_pages,
+        };
+        if pages.is_empty() {
+            return Err(RenderErrors::NoPageError);
+        } else if pages.len() > max_pages {
+            return Err(RenderErrors::TooManyPages {
+                pages: pages.len(),
+                allowed: max_pages,
+            });
+        }
 
-        image::write_buffer_with_format(
-            &mut writer,
-            bytemuck::cast_slice(pixmap.pixels()),
-            pixmap.width(),
-            pixmap.height(),
-            image::ColorType::Rgba8,
-            image::ImageFormat::Png,
-        )
-        .unwrap();
+        match output {
+            PageOutput::Album if pages.len() > 1 => pages
+                .iter()
+                .enumerate()
+                .map(|(i, frame)| {
+                    let pixels =
+                        determine_pixels_per_point(frame.size(), settings.theme.resolution)?;
+                    Ok(RenderedFile {
+                        name: format!("Rendered-{}.png", i + 1),
+                        data: encode_png(&render_frame(frame, pixels)),
+                    })
+                })
+                .collect(),
+            PageOutput::Album | PageOutput::Image => Ok(vec![RenderedFile {
+                name: "Rendered.png".to_string(),
+                data: encode_png(&stitch_pages(pages, settings.theme.resolution)?),
+            }]),
+            PageOutput::Pdf => Ok(vec![RenderedFile {
+                name: "Rendered.pdf".to_string(),
+                data: typst_pdf::pdf(&document, None, None),
+            }]),
+        }
+    }
+}
+
+/// The most attachments Discord allows on one message.
+const MAX_ATTACHMENTS: usize = 10;
+
+/// The most pixels an image can have along either side, beyond which Discord won't show it.
+const MAX_IMAGE_SIDE: f64 = 16000.0;
+
+/// How to output a document.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize, ChoiceParameter)]
+pub(crate) enum PageOutput {
+    /// One image with every page, one above the other.
+    #[name = "One image"]
+    Image,
+    /// An image for each page.
+    #[name = "An image per page"]
+    Album,
+    #[name = "PDF"]
+    Pdf,
+}
+
+impl Default for PageOutput {
+    fn default() -> Self {
+        Self::Image
+    }
+}
 
-        let image = writer.into_inner();
-        self.revert();
+/// What a render adds to the built-in preamble, for whoever asked for it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct RenderSettings {
+    /// Typst definitions, added after the built-in ones.
+    pub macros: String,
+    /// LaTeX definitions, added before LaTeX messages.
+    pub latex_macros: String,
+    pub theme: Theme,
+}
+
+/// A rendered file, ready to be attached to a message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RenderedFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+fn render_frame(frame: &Frame, pixels_per_point: f64) -> Pixmap {
+    typst_render::render(
+        frame,
+        pixels_per_point as f32,
+        typst::visualize::Color::from_u8(0, 0, 0, 0),
+    )
+}
+
+/// Renders pages one above the other, at the same scale, as one image.
+fn stitch_pages(pages: &[Frame], resolution: u32) -> Result<Pixmap, RenderErrors> {
+    let mut pixels = f64::INFINITY;
+    for frame in pages {
+        pixels = pixels.min(determine_pixels_per_point(frame.size(), resolution)?);
+    }
+    let width = pages.iter().map(|f| f.width().to_pt()).fold(0.0, f64::max);
+    let height: f64 = pages.iter().map(|f| f.height().to_pt()).sum();
+    let pixels = fit_to_max_side(pixels, width.max(height))?;
 
-        Ok(image)
+    let rendered: Vec<Pixmap> = pages.iter().map(|f| render_frame(f, pixels)).collect();
+    if let [single] = &rendered[..] {
+        return Ok(single.clone());
     }
+    let mut stitched = Pixmap::new(
+        rendered.iter().map(Pixmap::width).max().unwrap_or(1),
+        rendered.iter().map(Pixmap::height).sum(),
+    )
+    .ok_or(RenderErrors::PageSizeTooBig)?;
+    let mut y = 0;
+    for page in &rendered {
+        stitched.draw_pixmap(
+            0,
+            y as i32,
+            page.as_ref(),
+            &PixmapPaint::default(),
+            Transform::identity(),
+            None,
+        );
+        y += page.height();
+    }
+    Ok(stitched)
+}
+
+fn encode_png(pixmap: &Pixmap) -> Vec<u8> {
+    let mut writer = Cursor::new(Vec::new());
+
+    image::write_buffer_with_format(
+        &mut writer,
+        bytemuck::cast_slice(pixmap.pixels()),
+        pixmap.width(),
+        pixmap.height(),
+        image::ColorType::Rgba8,
+        image::ImageFormat::Png,
+    )
+    .unwrap();
+
+    writer.into_inner()
 }
 
-fn determine_pixels_per_point(size: Axes<Abs>) -> Result<f64, RenderErrors> {
-    let desired_resolution = 2000.0;
-    let max_size = 10000.0;
+/// Works out the scale to render a page at so it's roughly `resolution` pixels across.
+fn determine_pixels_per_point(size: Axes<Abs>, resolution: u32) -> Result<f64, RenderErrors> {
+    let desired_resolution = f64::from(resolution);
     let max_pixels_per_point = 15.0;
 
     let x = size.x.to_pt();
     let y = size.y.to_pt();
 
-    if x > max_size || y > max_size {
+    let area = x * y;
+    fit_to_max_side(
+        (desired_resolution / area.sqrt()).min(max_pixels_per_point),
+        x.max(y),
+    )
+}
+
+/// Scales down a resolution so that a side of the given length fits in an image, unless that would
+/// make it unreadable.
+fn fit_to_max_side(pixels_per_point: f64, side: f64) -> Result<f64, RenderErrors> {
+    let min_pixels_per_point = 0.5;
+
+    let pixels_per_point = pixels_per_point.min(MAX_IMAGE_SIDE / side);
+    if pixels_per_point < min_pixels_per_point {
         Err(RenderErrors::PageSizeTooBig)
     } else {
-        let area = x * y;
-        Ok((desired_resolution / area.sqrt()).min(max_pixels_per_point))
+        Ok(pixels_per_point)
     }
 }
 
@@ -253,20 +438,17 @@ impl typst::World for TypstRendered {
     #[doc = ""]
     #[doc = " Can be created through `Library::build()`."]
     fn library(&self) -> &Prehashed<Library> {
-        &self.library
+        &self.resources.library
     }
 
     #[doc = " Metadata about all known fonts."]
     fn book(&self) -> &Prehashed<FontBook> {
-        &self.fontbook
+        &self.resources.fontbook
     }
 
     #[doc = " Access the main source file."]
     fn main(&self) -> Source {
-        let Some(e) = &self.source else {
-            unreachable!()
-        };
-        e.clone()
+        self.source.clone()
     }
 
     #[doc = " Try to access the specified source file."]
@@ -274,21 +456,17 @@ impl typst::World for TypstRendered {
         if id == self.main().id() {
             return Ok(self.main());
         }
-        self.file_entry(&id)
-            .map_err(|_| FileError::NotSource)
-            .and_then(|f| f.clone().source(id))
+        self.resources.file(id)?.source(id)
     }
 
     #[doc = " Try to access the specified file."]
     fn file(&self, id: FileId) -> FileResult<Bytes> {
-        self.file_entry(&id)
-            .map_err(|_| FileError::NotSource)
-            .map(|f| f.clone().bytes)
+        self.resources.file(id).map(|f| f.bytes.clone())
     }
 
     #[doc = " Try to access the font with the given index in the font book."]
     fn font(&self, index: usize) -> Option<Font> {
-        self.fonts.get(index).cloned()
+        self.resources.fonts.get(index).cloned()
     }
 
     #[doc = " Get the current date."]
@@ -302,41 +480,144 @@ impl typst::World for TypstRendered {
         None
     }
 }
-#[derive(Debug)]
+/// Where in the user's code a diagnostic is.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Location {
+    /// The line number, from 1.
+    line: usize,
+    /// The column, in characters from 1.
+    column: usize,
+    /// The whole line.
+    text: String,
+    /// The number of characters to underline, at least 1.
+    length: usize,
+}
+
+impl Location {
+    /// Finds a byte range of `src`, or nothing if it isn't in it.
+    fn find(src: &str, range: Range<usize>) -> Option<Self> {
+        let before = src.get(..range.start)?;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let text = src[line_start..].lines().next().unwrap_or_default();
+        let underlined = src.get(range.start..range.end.min(line_start + text.len()))?;
+        Some(Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            text: text.to_string(),
+            length: underlined.chars().count().max(1),
+        })
+    }
+}
+
+/// A problem with a document, found in the user's code if it's there.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Diagnostic {
+    #[serde(with = "severity")]
+    severity: Severity,
+    message: String,
+    hints: Vec<String>,
+    location: Option<Location>,
+}
+
+/// Passes a [Severity] between processes as whether it's an error, since Typst can't serialize it.
+mod severity {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use typst::diag::Severity;
+
+    pub(super) fn serialize<S: Serializer>(severity: &Severity, s: S) -> Result<S::Ok, S::Error> {
+        (*severity == Severity::Error).serialize(s)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Severity, D::Error> {
+        Ok(if bool::deserialize(d)? {
+            Severity::Error
+        } else {
+            Severity::Warning
+        })
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "Error: ")?,
+            Severity::Warning => write!(f, "Warning: ")?,
+        }
+        write!(f, "{}", self.message)?;
+        match &self.location {
+            Some(location) => {
+                // keep the line from closing the code block early
+                let text = location.text.replace("```", "`\u{200b}``");
+                writeln!(f, " (line {}, column {})", location.line, location.column)?;
+                writeln!(
+                    f,
+                    "```\n{}\n{}{}\n```",
+                    text,
+                    " ".repeat(location.column - 1),
+                    "^".repeat(location.length)
+                )?;
+            }
+            None => writeln!(f)?,
+        }
+        for hint in self.hints.iter().filter(|h| !h.trim().is_empty()) {
+            writeln!(f, "Hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub(crate) enum RenderErrors {
-    SourceError(Vec<SourceDiagnostic>),
+    SourceError(Vec<Diagnostic>),
     NoPageError,
     PageSizeTooBig,
+    /// The document has more pages than allowed.
+    TooManyPages {
+        pages: usize,
+        allowed: usize,
+    },
+    /// Typst crashed.
+    Crashed,
+    /// TeX couldn't render a document, with the reason.
+    TexError(String),
+    /// A message's chemistry isn't valid, with the reason.
+    ChemistryError(String),
+    /// Rendering took longer than it's allowed to.
+    Timeout,
 }
 
 impl std::fmt::Display for RenderErrors {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RenderErrors::SourceError(e) => {
-                let s = String::new();
-                let a = e.iter().fold(s, |acc, x| {
-                    let q = x
-                        .hints
-                        .clone()
-                        .into_iter()
-                        .filter(|a| !a.as_str().trim().is_empty())
-                        .fold(String::new(), |acc, e| acc + e.as_str() + "\n");
-                    let ret = match x.severity {
-                        Severity::Error => acc + "Error:",
-                        Severity::Warning => acc + "Warning:",
-                    } + " "
-                        + x.message.as_str()
-                        + if !q.is_empty() { "\nHints:" } else { "" }
-                        + q.as_str();
-                    ret
-                });
-                write!(f, "{a}")
+                for diagnostic in e {
+                    write!(f, "{diagnostic}")?;
+                }
+                Ok(())
             }
             RenderErrors::NoPageError => {
                 write!(f, "No pages found...")
             }
             RenderErrors::PageSizeTooBig => {
-                write!(f, "Page too big...")
+                write!(f, "Page too big... try rendering it as a PDF.")
+            }
+            RenderErrors::TooManyPages { pages, allowed } => {
+                write!(
+                    f,
+                    "Too many pages: {pages}, but only {allowed} are allowed..."
+                )
+            }
+            RenderErrors::Crashed => {
+                write!(f, "Typst crashed while rendering this...")
+            }
+            RenderErrors::TexError(e) => {
+                write!(f, "LaTeX error:\n{e}")
+            }
+            RenderErrors::ChemistryError(e) => {
+                write!(f, "{e}")
+            }
+            RenderErrors::Timeout => {
+                write!(f, "Rendering took too long...")
             }
         }
     }
@@ -344,11 +625,121 @@ impl std::fmt::Display for RenderErrors {
 
 impl std::error::Error for RenderErrors {}
 
-fn typst_init() -> &'static Mutex<TypstRendered> {
-    static TYPST: OnceLock<Mutex<TypstRendered>> = OnceLock::new();
-    TYPST.get_or_init(|| Mutex::new(TypstRendered::new()))
+/// Renders Typst markup in the given format, giving up once the configured timeout runs out. Renders
+/// that were done before come from the cache instead.
+pub(crate) async fn typst_render_as(
+    msg: &str,
+    settings: &RenderSettings,
+    output: PageOutput,
+) -> Result<Vec<RenderedFile>, RenderErrors> {
+    let key = cache_key(
+        &TypstRendered::full_source(msg, settings),
+        settings.theme.resolution,
+        output,
+    );
+    if let Some(files) = render_cache().get(&key).await {
+        return Ok(files);
+    }
+    unpack_new_archives().await;
+    let files = render_pool().render(msg, settings, output).await?;
+    render_cache().insert(key, &files).await;
+    Ok(files)
+}
+
+/// Renders Typst markup to a PNG with every page, giving up once the configured timeout runs out.
+pub(crate) async fn typst_render(
+    msg: &str,
+    settings: &RenderSettings,
+) -> Result<Vec<u8>, RenderErrors> {
+    let mut files = typst_render_as(msg, settings, PageOutput::Image).await?;
+    Ok(files.remove(0).data)
 }
 
-pub(crate) async fn typst_render(msg: &str) -> Result<Vec<u8>, RenderErrors> {
-    typst_init().lock().await.render(msg)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use typst::syntax::VirtualPath;
+
+    #[test]
+    fn test_pixels_per_point() {
+        let size = |x, y| Axes::new(Abs::pt(x), Abs::pt(y));
+        // small equations get the highest resolution
+        assert_eq!(
+            determine_pixels_per_point(size(50.0, 20.0), 2000).unwrap(),
+            15.0
+        );
+        // long documents are scaled to fit rather than rejected
+        let pixels = determine_pixels_per_point(size(400.0, 20000.0), 2000).unwrap();
+        assert!(20000.0 * pixels <= MAX_IMAGE_SIDE);
+        assert!(matches!(
+            determine_pixels_per_point(size(400.0, 100000.0), 2000),
+            Err(RenderErrors::PageSizeTooBig)
+        ));
+    }
+
+    #[test]
+    fn test_location() {
+        let src = "Let $x = 1$.\nThen $é + foo$ and\nmore";
+        let start = src.find("foo").unwrap();
+        assert_eq!(
+            Location::find(src, start..start + 3),
+            Some(Location {
+                line: 2,
+                column: 11,
+                text: "Then $é + foo$ and".to_string(),
+                length: 3,
+            })
+        );
+        // spans running over several lines are underlined to the end of the first
+        let start = src.find("and").unwrap();
+        assert_eq!(Location::find(src, start..src.len()).unwrap().length, 3);
+        assert_eq!(Location::find(src, 100..101), None);
+    }
+
+    #[test]
+    fn test_diagnostic_display() {
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            message: "unknown variable: foo".to_string(),
+            hints: vec!["if you meant to display multiple letters as is, try adding spaces between each letter: `f o o`".to_string()],
+            location: Location::find("$a + foo$", 5..8),
+        };
+        assert_eq!(
+            diagnostic.to_string(),
+            "Error: unknown variable: foo (line 1, column 6)\n```\n$a + foo$\n     ^^^\n```\nHint: if you meant to display multiple letters as is, try adding spaces between each letter: `f o o`\n"
+        );
+    }
+
+    #[test]
+    fn test_file_eviction() {
+        let resources = Resources {
+            library: Prehashed::new(Library::build()),
+            fontbook: Prehashed::new(FontBook::new()),
+            fonts: vec![],
+            files: RwLock::new(HashMap::new()),
+            renders: AtomicUsize::new(0),
+        };
+        let id = |name: &str| FileId::new(None, VirtualPath::new(name));
+        for name in ["old.typ", "used.typ"] {
+            let slot = FileSlot {
+                bytes: Bytes::from(b"#let x = 1".to_vec()),
+                source: OnceLock::new(),
+                last_used: AtomicUsize::new(0),
+            };
+            resources
+                .files
+                .write()
+                .unwrap()
+                .insert(id(name), Arc::new(slot));
+        }
+        for _ in 0..=FILE_MAX_AGE {
+            resources.file(id("used.typ")).unwrap();
+            resources.evict();
+        }
+
+        let files = resources.files.read().unwrap();
+        assert!(!files.contains_key(&id("old.typ")));
+        let used = &files[&id("used.typ")];
+        assert_eq!(used.source(id("used.typ")).unwrap().text(), "#let x = 1");
+    }
 }
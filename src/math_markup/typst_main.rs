@@ -1,12 +1,22 @@
 use crate::{
-    math_markup::{get_preferred_markup, set_preferred_markup, typst_base::typst_render},
+    math_markup::{
+        chat_message::{message_document, Target},
+        delimiters::{looks_like_latex, tokenize, Segment},
+        get_preferred_markup,
+        latex::{latex_render, tex_available},
+        macros::{add_macros, macros},
+        packages::packages,
+        render_cache::cache,
+        theme::{get_theme, theme},
+        typst_base::{typst_render, typst_render_as, PageOutput, RenderErrors, RenderSettings},
+    },
+    storage::Storage,
     utils::{Context, Error},
 };
 use poise::{
-    serenity_prelude::{AttachmentType, User},
+    serenity_prelude::{AttachmentType, GuildId, User, UserId},
     ChoiceParameter,
 };
-use regex::Regex;
 
 use super::preferred_markup::MathMarkup;
 
@@ -14,53 +24,89 @@ use super::preferred_markup::MathMarkup;
 /// identifiable as Typst, then the cleaned message suitable for Typst rendering
 /// is returned instead.
 pub(crate) fn catch_typst_message(msg: &str) -> Option<String> {
-    if msg.contains("#ce") {
-        return Some(msg.to_string());
-    }
-    let math_check_regex = Regex::new(r"(?s).*\$.+(\$[^0-9].*)|(\$)$").unwrap();
-    if math_check_regex.is_match(msg) {
+    let wants_render = tokenize(msg).iter().any(|segment| match segment {
+        Segment::Math { .. } | Segment::Chem(_) => true,
+        Segment::Text(text) => text.contains("#ce"),
+        Segment::Code(_) => false,
+    });
+    if wants_render {
         Some(msg.to_string())
     } else {
         None
     }
 }
 
-fn latex2typst(msg: &str) -> String {
+pub(crate) fn latex2typst(msg: &str) -> String {
     "#mitext(`\n".to_string() + msg + "\n`)"
 }
 
-/// Checks if the text is latex or typst, presently just checking if there is a \ in between 2 $ signs and a non-whitespace immediately after; meh it works
+/// Checks if the text is latex or typst, going by whether any of its math uses LaTeX delimiters or
+/// commands.
 fn latex_or_typst(msg: &str) -> MathMarkup {
-    let latex_regex = Regex::new(r"(?s).*\$.*\\S.*\$.*").unwrap();
-    if latex_regex.is_match(msg) {
+    if looks_like_latex(msg) {
         MathMarkup::Latex
     } else {
         MathMarkup::Typst
     }
 }
 
+/// Gets the settings for a user's renders in a guild (or in DMs, if no guild is given).
+pub(crate) async fn render_settings(
+    user: UserId,
+    guild: Option<GuildId>,
+    storage: &dyn Storage,
+) -> Result<RenderSettings, Error> {
+    let mut settings = RenderSettings {
+        theme: get_theme(user, storage).await?,
+        ..RenderSettings::default()
+    };
+    add_macros(user, guild, storage, &mut settings).await?;
+    Ok(settings)
+}
+
+/// Renders a message the way its author prefers, in the guild it was sent in: its math as math, and
+/// the rest as text. Along with the image come any notes on the message's chemistry.
 pub(crate) async fn render_math(
     msg: &str,
     author: &User,
-) -> Result<Vec<u8>, crate::math_markup::typst_base::RenderErrors> {
-    let pref = get_preferred_markup(author)
-        .unwrap()
+    guild: Option<GuildId>,
+    storage: &dyn Storage,
+) -> Result<(Vec<u8>, Vec<String>), RenderErrors> {
+    let pref = get_preferred_markup(author, storage)
+        .await
+        .unwrap_or_default()
         .unwrap_or_else(|| latex_or_typst(msg));
-    // let pref = None;
-    match pref {
-        MathMarkup::Typst => typst_render(msg).await,
-        MathMarkup::Latex => typst_render(latex2typst(msg).as_str()).await,
-    }
+    let settings = render_settings(author.id, guild, storage)
+        .await
+        .unwrap_or_default();
+    let target = match pref {
+        MathMarkup::Typst => Target::Typst,
+        MathMarkup::Tex if tex_available() => Target::Latex,
+        // without TeX, do the best we can
+        MathMarkup::Latex | MathMarkup::Tex => Target::TypstFromLatex,
+    };
+    let document = message_document(msg, target, &settings)
+        .map_err(|e| RenderErrors::ChemistryError(e.to_string()))?;
+    let image = match target {
+        Target::Latex => latex_render(&document.source, &settings).await?,
+        Target::Typst | Target::TypstFromLatex => typst_render(&document.source, &settings).await?,
+    };
+    Ok((image, document.notes))
 }
 
 /// Parent command for rendering Typst code. Does nothing on its own.
-#[poise::command(prefix_command, slash_command, subcommands("render", "equation"))]
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("render", "equation", "macros", "theme", "cache", "packages")
+)]
 pub(crate) async fn typst(_ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
 /// Renders Typst markup. To include math, use `$`: padding with a space sets
-/// display.
+/// display. Documents with several pages can be sent as one image, an image per
+/// page or a PDF.
 #[poise::command(
     prefix_command,
     slash_command,
@@ -71,18 +117,24 @@ pub(crate) async fn typst(_ctx: Context<'_>) -> Result<(), Error> {
 )]
 pub(crate) async fn render(
     ctx: Context<'_>,
+    #[description = "How to send the pages: default one image."] output: Option<PageOutput>,
     #[description = "Code to render. $ used for math."]
     #[rest]
     code: String,
 ) -> Result<(), Error> {
-    let im = typst_render(code.as_str()).await?;
+    let settings =
+        render_settings(ctx.author().id, ctx.guild_id(), ctx.data().storage.as_ref()).await?;
+    let files = typst_render_as(code.as_str(), &settings, output.unwrap_or_default()).await?;
 
     ctx.send(|m| {
-        m.content(format!("`{}`", &code))
-            .attachment(AttachmentType::Bytes {
-                data: im.into(),
-                filename: "Rendered.png".into(),
-            })
+        m.content(format!("`{}`", &code));
+        for file in files {
+            m.attachment(AttachmentType::Bytes {
+                data: file.data.into(),
+                filename: file.name.into(),
+            });
+        }
+        m
     })
     .await?;
     Ok(())
@@ -122,7 +174,9 @@ pub(crate) async fn equation(
         RenderMode::Display => format!("$ {code} $"),
         RenderMode::Inline => format!("${code}$"),
     };
-    let im = typst_render(eqn_code.as_str()).await?;
+    let settings =
+        render_settings(ctx.author().id, ctx.guild_id(), ctx.data().storage.as_ref()).await?;
+    let im = typst_render(eqn_code.as_str(), &settings).await?;
     ctx.send(|m| {
         m.content(format!("`{}`", &code))
             .attachment(AttachmentType::Bytes {
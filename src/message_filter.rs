@@ -1,18 +1,23 @@
 //! Message filtering logic.
 
-use crate::{math_markup::catch_typst_message, translate::detection::detect_language};
+use crate::{
+    guild_settings::{feature_enabled, Feature},
+    math_markup::catch_typst_message,
+    storage::Storage,
+    translate::{detection::detect_language, thank_phrases::is_thanks},
+    utils::Error,
+};
 use lingua::Language;
-use poise::serenity_prelude::Message;
+use poise::serenity_prelude::{Channel, ChannelType, Message, MessageId};
 use pomsky_macro::pomsky;
 use regex::{Regex, RegexBuilder};
 use serenity::prelude::Context;
 use std::sync::OnceLock;
 
-const THANK_RE_PATTERN: &str = pomsky!(
+/// Marks a forum thread's question as answered. Only counts when sent by the thread's author.
+const SOLVED_RE_PATTERN: &str = pomsky!(
 (^ | %)
-(| ("than" ("k"|"x"|"ks"))
- | "tysm"
- | "ty")
+"!"? "solved"
 ($ | %)
 );
 
@@ -21,7 +26,7 @@ const GOOD_RE_PATTERN: &str = pomsky!(
     ("good" | "nice" | "awesome") " " ("bot" | "job" | "work")
 );
 
-static THANK_RE: OnceLock<Regex> = OnceLock::new();
+static SOLVED_RE: OnceLock<Regex> = OnceLock::new();
 static GOOD_RE: OnceLock<Regex> = OnceLock::new();
 static BAD_RE: OnceLock<Regex> = OnceLock::new();
 
@@ -36,51 +41,121 @@ pub(crate) enum MessageType {
     Normal,
 }
 
-/// Determines if the message matches any of the categories that prompt a response.
-pub(crate) async fn get_message_type(message: &Message, ctx: &Context) -> MessageType {
+/// Checks if a channel is a thread in a forum channel.
+async fn is_forum_thread(channel: &Channel, ctx: &Context) -> bool {
+    let Channel::Guild(channel) = channel else {
+        return false;
+    };
+    let Some(parent) = channel
+        .parent_id
+        .filter(|_| channel.thread_metadata.is_some())
+    else {
+        return false;
+    };
+    matches!(
+        parent.to_channel(ctx).await,
+        Ok(Channel::Guild(parent)) if parent.kind == ChannelType::Forum
+    )
+}
+
+/// Checks if a message is its forum thread's author marking the thread as solved.
+async fn is_solved_marker(message: &Message, ctx: &Context) -> bool {
+    let solved_re = SOLVED_RE.get_or_init(|| {
+        RegexBuilder::new(SOLVED_RE_PATTERN)
+            .case_insensitive(true)
+            .build()
+            .unwrap()
+    });
+    if !solved_re.is_match(&message.content) {
+        return false;
+    }
+
+    let Ok(channel) = message.channel_id.to_channel(ctx).await else {
+        return false;
+    };
+    if !is_forum_thread(&channel, ctx).await {
+        return false;
+    }
+    // the opening post of a forum thread shares the thread's ID
+    let thread = channel.id();
+    match thread.message(ctx, MessageId(thread.0)).await {
+        Ok(starter) => starter.author.id == message.author.id,
+        Err(_) => false,
+    }
+}
+
+/// Checks if a feature is turned on where the message was sent. Direct messages allow everything.
+async fn enabled_for(
+    message: &Message,
+    ctx: &Context,
+    storage: &dyn Storage,
+    feature: Feature,
+) -> Result<bool, Error> {
+    match message.guild_id {
+        Some(guild_id) => {
+            feature_enabled(ctx, storage, guild_id, message.channel_id, feature).await
+        }
+        None => Ok(true),
+    }
+}
+
+/// Determines if the message matches any of the categories that prompt a response. Categories whose
+/// feature is disabled in the channel are skipped, so a later category can still match.
+pub(crate) async fn get_message_type(
+    message: &Message,
+    ctx: &Context,
+    storage: &dyn Storage,
+) -> Result<MessageType, Error> {
     if message.author.bot {
-        return MessageType::BotMessage;
+        return Ok(MessageType::BotMessage);
     }
 
-    if !message.mentions.is_empty() {
-        let thank_re = THANK_RE.get_or_init(|| {
-            RegexBuilder::new(THANK_RE_PATTERN)
+    // thanks can be directed at mentioned users or the author of the message being replied to
+    let has_target = !message.mentions.is_empty() || message.referenced_message.is_some();
+    if has_target
+        && (is_thanks(&message.content) || is_solved_marker(message, ctx).await)
+        && enabled_for(message, ctx, storage, Feature::Thanks).await?
+    {
+        return Ok(MessageType::Thank);
+    }
+
+    if !message.mentions.is_empty() && message.mentions_me(ctx).await.unwrap_or(false) {
+        let bad_re = BAD_RE.get_or_init(|| {
+            RegexBuilder::new(r"bad\b")
                 .case_insensitive(true)
                 .build()
                 .unwrap()
         });
-        if dbg!(thank_re).is_match(&message.content) {
-            return MessageType::Thank;
-        }
-
-        if message.mentions_me(ctx).await.unwrap_or(false) {
-            let bad_re = BAD_RE.get_or_init(|| {
-                RegexBuilder::new(r"bad\b")
-                    .case_insensitive(true)
-                    .build()
-                    .unwrap()
-            });
-            if bad_re.is_match(&message.content) {
-                return MessageType::BadNano;
-            }
-
-            let good_re = GOOD_RE.get_or_init(|| {
-                RegexBuilder::new(GOOD_RE_PATTERN)
-                    .case_insensitive(true)
-                    .build()
-                    .unwrap()
-            });
-            if good_re.is_match(&message.content) {
-                return MessageType::GoodNano;
+        let good_re = GOOD_RE.get_or_init(|| {
+            RegexBuilder::new(GOOD_RE_PATTERN)
+                .case_insensitive(true)
+                .build()
+                .unwrap()
+        });
+        let reply = if bad_re.is_match(&message.content) {
+            Some(MessageType::BadNano)
+        } else if good_re.is_match(&message.content) {
+            Some(MessageType::GoodNano)
+        } else {
+            None
+        };
+        if let Some(reply) = reply {
+            if enabled_for(message, ctx, storage, Feature::NanoReplies).await? {
+                return Ok(reply);
             }
         }
     }
 
     if let Some(s) = catch_typst_message(&message.content) {
-        return MessageType::Typst(s);
+        if enabled_for(message, ctx, storage, Feature::Math).await? {
+            return Ok(MessageType::Typst(s));
+        }
     }
     match detect_language(&message.content) {
-        Some(Language::English) | None => MessageType::Normal,
-        Some(other) => MessageType::Translate(other),
+        Some(Language::English) | None => Ok(MessageType::Normal),
+        Some(other) if enabled_for(message, ctx, storage, Feature::Translation).await? => {
+            Ok(MessageType::Translate(other))
+        }
+        Some(_) => Ok(MessageType::Normal),
     }
 }
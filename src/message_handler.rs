@@ -16,25 +16,32 @@ use serenity::utils::MessageBuilder;
 
 use std::time::Duration;
 
-use crate::math_markup::{catch_typst_message, render_math};
-use crate::utils::Error;
+use crate::config::config;
+use crate::math_markup::{catch_typst_message, display_mentions, render_math};
+use crate::utils::{Data, Error};
 
 use serenity::{self, model::channel::Message, prelude::*};
 
 /// Appropriately deals wih the different potential message types.
-pub(crate) async fn handle_message(ctx: &Context, new_message: &Message) -> Result<(), Error> {
-    match get_message_type(new_message, ctx).await {
+pub(crate) async fn handle_message(
+    ctx: &Context,
+    new_message: &Message,
+    data: &Data,
+) -> Result<(), Error> {
+    let message_type = get_message_type(new_message, ctx, data.storage.as_ref()).await?;
+
+    match message_type {
         MessageType::Normal | MessageType::BotMessage => {}
         MessageType::Thank => {
             dbg!(&new_message.content);
-            crate::rep::thank(ctx, new_message).await?;
+            crate::rep::thank(ctx, new_message, data).await?;
         }
         MessageType::GoodNano => {
             new_message
                 .reply(
                     &ctx,
                     MessageBuilder::new()
-                        .push("https://i.imgur.com/bgiANhm.gif")
+                        .push(&config().reactions.good_nano_gif)
                         .build(),
                 )
                 .await?;
@@ -44,7 +51,7 @@ pub(crate) async fn handle_message(ctx: &Context, new_message: &Message) -> Resu
                 .reply(
                     &ctx,
                     MessageBuilder::new()
-                        .push("https://c.tenor.com/8QjR5hC91b0AAAAC/nichijou-nano.gif")
+                        .push(&config().reactions.bad_nano_gif)
                         .build(),
                 )
                 .await?;
@@ -69,14 +76,24 @@ pub(crate) async fn handle_message(ctx: &Context, new_message: &Message) -> Resu
             }
         }
         MessageType::Typst(typst_src) => {
-            let res = render_math(typst_src.as_str(), &new_message.author).await;
+            let displayed =
+                display_mentions(ctx, &typst_src, new_message.guild_id, &new_message.mentions);
+            let res = render_math(
+                displayed.as_str(),
+                &new_message.author,
+                new_message.guild_id,
+                data.storage.as_ref(),
+            )
+            .await;
             let mut typst_reply = new_message
                 .channel_id
                 .send_message(&ctx.http, |m| match res {
-                    Ok(im) => m.add_file(AttachmentType::Bytes {
-                        data: im.into(),
-                        filename: "Rendered.png".into(),
-                    }),
+                    Ok((im, notes)) => {
+                        m.content(notes.join("\n")).add_file(AttachmentType::Bytes {
+                            data: im.into(),
+                            filename: "Rendered.png".into(),
+                        })
+                    }
                     Err(e) => {
                         println!("`n{}n`\n{}", typst_src, e);
                         m.content(format!("`n{}n`\n{}", typst_src, e))
@@ -103,12 +120,24 @@ pub(crate) async fn handle_message(ctx: &Context, new_message: &Message) -> Resu
                 if let Some(new_typst_content) =
                     catch_typst_message(e.content.clone().unwrap().as_str())
                 {
-                    let res = render_math(new_typst_content.as_str(), &new_message.author).await;
+                    let displayed = display_mentions(
+                        ctx,
+                        &new_typst_content,
+                        new_message.guild_id,
+                        e.mentions.as_deref().unwrap_or_default(),
+                    );
+                    let res = render_math(
+                        displayed.as_str(),
+                        &new_message.author,
+                        new_message.guild_id,
+                        data.storage.as_ref(),
+                    )
+                    .await;
                     typst_reply
                         .edit(&ctx, |m| match res {
-                            Ok(im) => m
+                            Ok((im, notes)) => m
                                 .remove_existing_attachment(prev_img_id)
-                                .content("")
+                                .content(notes.join("\n"))
                                 .attachment(AttachmentType::Bytes {
                                     data: im.into(),
                                     filename: "Rendered.png".into(),
@@ -0,0 +1,399 @@
+//! The thank ledger: every thank is recorded as an event, so moderators can see who thanked whom and
+//! undo thanks that shouldn't have counted. Scores can always be rebuilt from the ledger.
+
+use std::collections::HashMap;
+
+use crate::storage::{Boards, Storage};
+use crate::utils::{Context, Error};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::model::user::User;
+
+use super::{
+    base_reputation_key, milestones, periods, reputation_key, score, subjects, thank_boards,
+};
+
+/// A single thank from one user to another.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ThankEvent {
+    /// The user giving thanks.
+    pub thanker: UserId,
+    /// The user being thanked.
+    pub thankee: UserId,
+    /// The guild the thank happened in.
+    pub guild: GuildId,
+    /// The channel of the thanking message.
+    pub channel: ChannelId,
+    /// The thanking message, or for reaction thanks, the message reacted to.
+    pub message: MessageId,
+    /// When the thank happened, as a Unix timestamp.
+    pub timestamp: i64,
+    /// The message the thankee is being thanked for: the one replied to, or the one reacted to. Reply
+    /// and reaction thanks for the same message only count once.
+    #[serde(default)]
+    pub answer: Option<MessageId>,
+    /// The subject the thank counts towards, if any.
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// Whether a moderator has revoked this thank. Revoked thanks don't count towards reputation.
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+impl ThankEvent {
+    /// A link to the thanking message.
+    pub(crate) fn link(&self) -> String {
+        self.message.link(self.channel, Some(self.guild))
+    }
+
+    /// The keys this thank claims, so it isn't counted again.
+    fn claims(&self) -> Vec<String> {
+        claims(self.thanker, self.thankee, self.message, self.answer)
+    }
+
+    /// A one-line description of the event, suitable for a list.
+    fn describe(&self) -> String {
+        format!(
+            "<t:{}:R> <@{}> → <@{}> in {}{}",
+            self.timestamp,
+            self.thanker.0,
+            self.thankee.0,
+            self.link(),
+            if self.revoked { " *(revoked)*" } else { "" }
+        )
+    }
+}
+
+/// The keys a thank claims in its guild's set of thanks given. Two thanks between the same users
+/// count once if they were given in the same message or for the same answer, like a reply thanking an
+/// answer that was already thanked by reaction, and then they share a key.
+pub(crate) fn claims(
+    thanker: UserId,
+    thankee: UserId,
+    message: MessageId,
+    answer: Option<MessageId>,
+) -> Vec<String> {
+    let mut claims = vec![format!("message:{}:{}:{}", thanker.0, thankee.0, message.0)];
+    if let Some(answer) = answer {
+        claims.push(format!("answer:{}:{}:{}", thanker.0, thankee.0, answer.0));
+    }
+    claims
+}
+
+/// Appends a thank event to its guild's ledger.
+pub(crate) async fn record(event: &ThankEvent, storage: &dyn Storage) -> Result<(), Error> {
+    let serialized = serde_json::to_string(event).expect("Thank events are always serializable");
+    storage
+        .push_ledger(event.guild, event.timestamp, &serialized)
+        .await
+}
+
+/// Parses ledger entries, skipping any that can't be parsed.
+fn parse_events(raw: &[String]) -> Vec<ThankEvent> {
+    raw.iter()
+        .filter_map(|s| serde_json::from_str(s).ok())
+        .collect()
+}
+
+/// Gets every thank event in a guild, oldest first. Entries that can't be parsed are skipped.
+pub(crate) async fn events(
+    guild: GuildId,
+    storage: &dyn Storage,
+) -> Result<Vec<ThankEvent>, Error> {
+    Ok(parse_events(&storage.ledger(guild).await?))
+}
+
+/// Gets the thank events in a guild given at or after the given Unix timestamp, oldest first. Entries
+/// that can't be parsed are skipped.
+pub(crate) async fn recent_events(
+    guild: GuildId,
+    since: i64,
+    storage: &dyn Storage,
+) -> Result<Vec<ThankEvent>, Error> {
+    Ok(parse_events(&storage.recent_ledger(guild, since).await?))
+}
+
+/// Marks every thank given in the given message, or given for it as an answer, as revoked, and
+/// releases their claims so the thanks can be given again, returning the revoked events.
+async fn revoke_message(
+    guild: GuildId,
+    message: MessageId,
+    storage: &dyn Storage,
+) -> Result<Vec<ThankEvent>, Error> {
+    let raw = storage.ledger(guild).await?;
+    let mut revoked = vec![];
+    for (i, s) in raw.iter().enumerate() {
+        match serde_json::from_str::<ThankEvent>(s) {
+            Ok(mut event)
+                if (event.message == message || event.answer == Some(message))
+                    && !event.revoked =>
+            {
+                event.revoked = true;
+                let serialized =
+                    serde_json::to_string(&event).expect("Thank events are always serializable");
+                storage.set_ledger_entry(guild, i, &serialized).await?;
+                storage.release_thanks(guild, &event.claims()).await?;
+                revoked.push(event);
+            }
+            _ => {}
+        }
+    }
+    Ok(revoked)
+}
+
+/// Reads a message ID, or the ID at the end of a message link. The message doesn't have to exist any
+/// more, so thanks from deleted messages can still be revoked.
+fn parse_message_id(text: &str) -> Option<MessageId> {
+    let id = text.trim().trim_end_matches('/').rsplit('/').next()?;
+    id.parse::<u64>().ok().filter(|&id| id != 0).map(MessageId)
+}
+
+/// Takes away the points revoked thanks gave, from every leaderboard they counted towards.
+async fn unthank(
+    guild: GuildId,
+    revoked: &[ThankEvent],
+    storage: &dyn Storage,
+) -> Result<(), Error> {
+    let seasons = periods::get_seasons(guild, storage).await?;
+    for event in revoked {
+        let boards = thank_boards(guild, event.timestamp, &seasons, event.subject.as_deref());
+        storage.decrement(&boards, event.thankee, 1).await?;
+    }
+    Ok(())
+}
+
+/// Recomputes a guild's reputation from scratch: points carried over from before the ledger existed,
+/// plus one point for every thank that hasn't been revoked. The time-windowed and subject leaderboards
+/// are rebuilt as well. Thanks given while this runs can be lost, so it's only for repairs.
+async fn rebuild_scores(guild: GuildId, storage: &dyn Storage) -> Result<(), Error> {
+    let events = events(guild, storage).await?;
+
+    let mut boards = Boards::new();
+    let all_time = boards.entry(reputation_key(guild)).or_default();
+    for (user, rep) in storage.top(&base_reputation_key(guild), None).await? {
+        *all_time.entry(user).or_default() += rep;
+    }
+    for event in events.iter().filter(|e| !e.revoked) {
+        *all_time.entry(event.thankee).or_default() += 1;
+    }
+    periods::rebuild_buckets(guild, &events, &mut boards, storage).await?;
+    subjects::rebuild_subjects(guild, &events, &mut boards, storage).await?;
+
+    storage.replace_boards(boards).await
+}
+
+/// Shows the most recent thanks in this server, optionally only those involving a given user.
+#[poise::command(slash_command, guild_only)]
+pub(crate) async fn history(
+    ctx: Context<'_>,
+    #[description = "Only show thanks given or received by this user"] user: Option<User>,
+    #[description = "Number of thanks to show (default 10)"]
+    #[min = 1_usize]
+    #[max = 25_usize]
+    count: Option<usize>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let lines: Vec<String> = events(guild_id, ctx.data().storage.as_ref())
+        .await?
+        .iter()
+        .rev()
+        .filter(|e| match &user {
+            Some(u) => e.thanker == u.id || e.thankee == u.id,
+            None => true,
+        })
+        .take(count.unwrap_or(10))
+        .map(ThankEvent::describe)
+        .collect();
+
+    let content = if lines.is_empty() {
+        "No thanks recorded yet.".to_string()
+    } else {
+        format!("# Recent thanks\n{}", lines.join("\n"))
+    };
+
+    ctx.send(|m| m.content(content).allowed_mentions(|a| a.empty_parse()))
+        .await?;
+    Ok(())
+}
+
+/// Formats the users most often seen in one role of a set of events, along with their counts.
+fn tally(events: &[&ThankEvent], key: impl Fn(&ThankEvent) -> UserId) -> String {
+    let mut counts: HashMap<UserId, usize> = HashMap::new();
+    for event in events {
+        *counts.entry(key(event)).or_default() += 1;
+    }
+    let mut counts: Vec<(UserId, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1));
+
+    counts
+        .into_iter()
+        .take(5)
+        .map(|(id, n)| format!("- <@{}>: **{}**", id.0, n))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Shows who has thanked a user and whom they have thanked, to help spot alts and thank trading.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_MESSAGES")]
+pub(crate) async fn audit(
+    ctx: Context<'_>,
+    #[description = "User to audit"] user: User,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let events = events(guild_id, ctx.data().storage.as_ref()).await?;
+
+    let received: Vec<&ThankEvent> = events
+        .iter()
+        .filter(|e| e.thankee == user.id && !e.revoked)
+        .collect();
+    let given: Vec<&ThankEvent> = events
+        .iter()
+        .filter(|e| e.thanker == user.id && !e.revoked)
+        .collect();
+    let num_revoked = events
+        .iter()
+        .filter(|e| e.revoked && (e.thankee == user.id || e.thanker == user.id))
+        .count();
+    let recent: Vec<String> = events
+        .iter()
+        .rev()
+        .filter(|e| e.thanker == user.id || e.thankee == user.id)
+        .take(10)
+        .map(ThankEvent::describe)
+        .collect();
+
+    let content = format!(
+        "# Audit of <@{}>\nReceived **{}** thanks, gave **{}** thanks, **{}** revoked.\n## Most thanked by\n{}\n## Most thanked\n{}\n## Recent\n{}",
+        user.id.0,
+        received.len(),
+        given.len(),
+        num_revoked,
+        tally(&received, |e| e.thanker),
+        tally(&given, |e| e.thankee),
+        recent.join("\n")
+    );
+
+    ctx.send(|m| {
+        m.content(content)
+            .allowed_mentions(|a| a.empty_parse())
+            .ephemeral(true)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Revokes all of the thanks recorded for a message, the thanking message or the answer a reaction
+/// thanked, and takes away the points they gave.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_MESSAGES")]
+pub(crate) async fn revoke(
+    ctx: Context<'_>,
+    #[description = "Message the thanks were recorded for (link or ID)"] message: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+
+    let message_id =
+        parse_message_id(&message).ok_or_else(|| anyhow!("That isn't a message link or ID."))?;
+    let revoked = revoke_message(guild_id, message_id, storage).await?;
+    if revoked.is_empty() {
+        return Err(anyhow!("No thanks to revoke in that message.").into());
+    }
+
+    let mut thankees: Vec<UserId> = revoked.iter().map(|e| e.thankee).collect();
+    thankees.sort();
+    thankees.dedup();
+    let mut thankers: Vec<UserId> = revoked.iter().map(|e| e.thanker).collect();
+    thankers.sort();
+    thankers.dedup();
+    let mut old_scores = vec![];
+    for &thankee in &thankees {
+        old_scores.push(score(guild_id, thankee, storage).await?);
+    }
+    unthank(guild_id, &revoked, storage).await?;
+
+    let milestones = milestones::get_milestones(guild_id, storage).await?;
+    for (&thankee, old) in thankees.iter().zip(old_scores) {
+        let new = score(guild_id, thankee, storage).await?;
+        milestones::apply(ctx.http(), &milestones, guild_id, thankee, old, new, None).await;
+    }
+
+    let mentions = |users: &[UserId]| {
+        users
+            .iter()
+            .map(|u| format!("<@{}>", u.0))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    ctx.send(|m| {
+        m.content(format!(
+            "Revoked **{}** thanks from {} to {}.",
+            revoked.len(),
+            mentions(&thankers),
+            mentions(&thankees)
+        ))
+        .allowed_mentions(|a| a.empty_parse())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Rebuilds this server's leaderboards from the thank ledger, to repair them if they've gone wrong.
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub(crate) async fn rebuild(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    ctx.defer().await?;
+    rebuild_scores(guild_id, ctx.data().storage.as_ref()).await?;
+    ctx.say("Rebuilt this server's leaderboards from the thank ledger.")
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claims() {
+        let overlap = |a: Vec<String>, b: Vec<String>| a.iter().any(|claim| b.contains(claim));
+        // a reaction thank, on the answer
+        let reaction = || claims(UserId(1), UserId(2), MessageId(10), Some(MessageId(10)));
+        // a reply thanking the same answer
+        assert!(overlap(
+            reaction(),
+            claims(UserId(1), UserId(2), MessageId(11), Some(MessageId(10)))
+        ));
+        assert!(overlap(
+            reaction(),
+            claims(UserId(1), UserId(2), MessageId(10), None)
+        ));
+        assert!(!overlap(
+            reaction(),
+            claims(UserId(1), UserId(2), MessageId(11), Some(MessageId(12)))
+        ));
+        assert!(!overlap(
+            reaction(),
+            claims(UserId(1), UserId(2), MessageId(11), None)
+        ));
+        assert!(!overlap(
+            reaction(),
+            claims(UserId(3), UserId(2), MessageId(11), Some(MessageId(10)))
+        ));
+    }
+
+    #[test]
+    fn test_parse_message_id() {
+        assert_eq!(
+            parse_message_id("1180000000000000001"),
+            Some(MessageId(1180000000000000001))
+        );
+        assert_eq!(
+            parse_message_id(
+                "https://discord.com/channels/1100000000000000000/1110000000000000000/1180000000000000001"
+            ),
+            Some(MessageId(1180000000000000001))
+        );
+        assert_eq!(parse_message_id("not a message"), None);
+        assert_eq!(parse_message_id("0"), None);
+    }
+}
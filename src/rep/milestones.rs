@@ -0,0 +1,290 @@
+//! Reputation milestones: each guild can set point thresholds that grant a role and announce the
+//! achievement. Roles are taken away again if someone drops back below a threshold (e.g., because
+//! their thanks were revoked). Guilds start out with the old 1000-point book reward, which can be
+//! replaced or removed like any other milestone.
+
+use crate::storage::Storage;
+use crate::utils::{log_err, Context, Error};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serenity::http::Http;
+use serenity::model::channel::Channel;
+use serenity::model::guild::Role;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+
+use super::reputation_key;
+
+/// A reward for reaching a certain number of points.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Milestone {
+    /// The number of points needed to reach the milestone.
+    pub threshold: usize,
+    /// The role granted while at or above the threshold.
+    pub role: Option<RoleId>,
+    /// Where to announce the milestone. Defaults to the channel the thank happened in.
+    pub channel: Option<ChannelId>,
+    /// The announcement. `{user}` is replaced by a mention of the user and `{points}` by the threshold.
+    pub message: Option<String>,
+}
+
+impl Milestone {
+    /// The announcement for a user reaching this milestone, if there is one.
+    fn announcement(&self, user: UserId) -> Option<String> {
+        self.message.as_ref().map(|m| {
+            m.replace("{user}", &format!("<@{}>", user.0))
+                .replace("{points}", &self.threshold.to_string())
+        })
+    }
+
+    /// A one-line description of the milestone, suitable for a list.
+    fn describe(&self) -> String {
+        let mut desc = format!("**{}** points", self.threshold);
+        if let Some(role) = self.role {
+            desc.push_str(&format!(": <@&{}>", role.0));
+        }
+        if let Some(channel) = self.channel {
+            desc.push_str(&format!(" (announced in <#{}>)", channel.0));
+        }
+        if let Some(message) = &self.message {
+            desc.push_str(&format!("\n  > {}", message));
+        }
+        desc
+    }
+}
+
+/// Splits milestones into the ones reached and the ones lost when reputation goes from `old` to `new`.
+pub(crate) fn crossed(
+    milestones: &[Milestone],
+    old: usize,
+    new: usize,
+) -> (Vec<&Milestone>, Vec<&Milestone>) {
+    let reached = milestones
+        .iter()
+        .filter(|m| old < m.threshold && m.threshold <= new)
+        .collect();
+    let lost = milestones
+        .iter()
+        .filter(|m| new < m.threshold && m.threshold <= old)
+        .collect();
+    (reached, lost)
+}
+
+/// The name of the guild setting holding a guild's milestones.
+const MILESTONES: &str = "rep-milestones";
+
+/// The milestones of a guild that hasn't set any: the book reward at 1000 points, from before
+/// milestones could be changed.
+fn default_milestones() -> Vec<Milestone> {
+    vec![Milestone {
+        threshold: 1000,
+        role: None,
+        channel: None,
+        message: Some(
+            "**{user}** has helped **{points}** people!!! In recognition of this achievement, {user} can redeem these points for a book of your choosing: contact PollardsRho for more information. \nhttps://tenor.com/view/happy-new-year2021version-gif-19777838"
+                .to_string(),
+        ),
+    }]
+}
+
+/// Gets a guild's milestones, in increasing order of threshold. Guilds that have never changed their
+/// milestones get the [default ones](default_milestones).
+pub(crate) async fn get_milestones(
+    guild: GuildId,
+    storage: &dyn Storage,
+) -> Result<Vec<Milestone>, Error> {
+    Ok(match storage.guild_setting(guild, MILESTONES).await? {
+        Some(raw) => serde_json::from_str(&raw).unwrap_or_default(),
+        None => default_milestones(),
+    })
+}
+
+/// Sets a guild's milestones.
+async fn set_milestones(
+    guild: GuildId,
+    milestones: &mut Vec<Milestone>,
+    storage: &dyn Storage,
+) -> Result<(), Error> {
+    milestones.sort_by_key(|m| m.threshold);
+    let serialized = serde_json::to_string(milestones).expect("Milestones are always serializable");
+    storage
+        .set_guild_setting(guild, MILESTONES, &serialized)
+        .await
+}
+
+/// Grants or removes roles and announces milestones for a user whose reputation changed from `old`
+/// to `new`. Announcements without a channel of their own go to `fallback_channel`.
+pub(crate) async fn apply(
+    http: impl AsRef<Http>,
+    milestones: &[Milestone],
+    guild: GuildId,
+    user: UserId,
+    old: usize,
+    new: usize,
+    fallback_channel: Option<ChannelId>,
+) {
+    let http = http.as_ref();
+    let (reached, lost) = crossed(milestones, old, new);
+
+    for milestone in reached {
+        if let Some(role) = milestone.role {
+            log_err(
+                http.add_member_role(guild.0, user.0, role.0, Some("Reputation milestone"))
+                    .await,
+            );
+        }
+        if let (Some(announcement), Some(channel)) = (
+            milestone.announcement(user),
+            milestone.channel.or(fallback_channel),
+        ) {
+            log_err(channel.say(http, announcement).await);
+        }
+    }
+
+    for milestone in lost {
+        if let Some(role) = milestone.role {
+            log_err(
+                http.remove_member_role(guild.0, user.0, role.0, Some("Reputation milestone"))
+                    .await,
+            );
+        }
+    }
+}
+
+/// Lists this server's reputation milestones.
+#[poise::command(slash_command, guild_only)]
+pub(crate) async fn milestones(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let milestones = get_milestones(guild_id, ctx.data().storage.as_ref()).await?;
+    let content = if milestones.is_empty() {
+        "This server has no reputation milestones.".to_string()
+    } else {
+        format!(
+            "# Milestones\n{}",
+            milestones
+                .iter()
+                .map(|m| format!("- {}", m.describe()))
+                .collect::<Vec<String>>()
+                .join("\n")
+        )
+    };
+
+    ctx.send(|m| m.content(content).allowed_mentions(|a| a.empty_parse()))
+        .await?;
+    Ok(())
+}
+
+/// Adds a reputation milestone, replacing any existing milestone with the same threshold.
+///
+/// Members already at or above the threshold are given the role straight away.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn add_milestone(
+    ctx: Context<'_>,
+    #[description = "Points needed to reach the milestone"]
+    #[min = 1_usize]
+    threshold: usize,
+    #[description = "Role to give members who reach the milestone"] role: Option<Role>,
+    #[description = "Channel to announce the milestone in (default: where the thank happened)"]
+    channel: Option<Channel>,
+    #[description = "Announcement: {user} and {points} are filled in"] message: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    if role.is_none() && message.is_none() {
+        return Err(anyhow!("A milestone needs a role, a message, or both.").into());
+    }
+
+    let storage = ctx.data().storage.as_ref();
+    let milestone = Milestone {
+        threshold,
+        role: role.map(|r| r.id),
+        channel: channel.map(|c| c.id()),
+        message,
+    };
+
+    let mut milestones = get_milestones(guild_id, storage).await?;
+    milestones.retain(|m| m.threshold != threshold);
+    milestones.push(milestone.clone());
+    set_milestones(guild_id, &mut milestones, storage).await?;
+
+    if let Some(role) = milestone.role {
+        let members = storage
+            .at_least(&reputation_key(guild_id), threshold)
+            .await?;
+        for member in members {
+            log_err(
+                ctx.http()
+                    .add_member_role(guild_id.0, member.0, role.0, Some("Reputation milestone"))
+                    .await,
+            );
+        }
+    }
+
+    ctx.send(|m| {
+        m.content(format!("Added milestone: {}", milestone.describe()))
+            .allowed_mentions(|a| a.empty_parse())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Removes the reputation milestone with the given threshold. Roles already given are kept.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn remove_milestone(
+    ctx: Context<'_>,
+    #[description = "Points needed to reach the milestone"] threshold: usize,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+
+    let mut milestones = get_milestones(guild_id, storage).await?;
+    let num_milestones = milestones.len();
+    milestones.retain(|m| m.threshold != threshold);
+    if milestones.len() == num_milestones {
+        return Err(anyhow!("There is no milestone at {} points.", threshold).into());
+    }
+    set_milestones(guild_id, &mut milestones, storage).await?;
+
+    ctx.say(format!(
+        "Removed the milestone at **{}** points.",
+        threshold
+    ))
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn milestone(threshold: usize) -> Milestone {
+        Milestone {
+            threshold,
+            role: None,
+            channel: None,
+            message: Some("{user} reached {points}!".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_crossed() {
+        let milestones = vec![milestone(10), milestone(100), milestone(1000)];
+
+        let (reached, lost) = crossed(&milestones, 9, 10);
+        assert_eq!(reached, vec![&milestones[0]]);
+        assert!(lost.is_empty());
+
+        let (reached, lost) = crossed(&milestones, 10, 11);
+        assert!(reached.is_empty() && lost.is_empty());
+
+        let (reached, lost) = crossed(&milestones, 150, 5);
+        assert!(reached.is_empty());
+        assert_eq!(lost, vec![&milestones[0], &milestones[1]]);
+    }
+
+    #[test]
+    fn test_announcement() {
+        assert_eq!(
+            milestone(100).announcement(UserId(42)),
+            Some("<@42> reached 100!".to_string())
+        );
+    }
+}
@@ -0,0 +1,494 @@
+//! Module that deals with the reputation system, processing "thanks" commands and setting reputation accordingly.
+//!
+//! Reputation is tracked per guild, in a leaderboard keyed by user ID, so a username change doesn't lose
+//! anyone their points and each server gets its own leaderboard. Every thank is also recorded in the
+//! [ledger], from which the scores can be rebuilt. Alongside the all-time leaderboard, thanks count
+//! towards [time-windowed leaderboards](periods) and [per-subject leaderboards](subjects).
+
+mod ledger;
+mod milestones;
+mod periods;
+mod policy;
+mod reactions;
+mod subjects;
+
+use std::collections::HashMap;
+
+use crate::config::config;
+use crate::guild_settings::{self, Feature};
+use crate::storage::Storage;
+use crate::utils::{log_err, Context, Data, Error};
+use anyhow::anyhow;
+use ledger::ThankEvent;
+use periods::{Period, Season};
+use policy::Rejection;
+
+use serenity::futures::StreamExt;
+use serenity::model::channel::{Message, Reaction};
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::model::user::User;
+
+/// The name of the leaderboard holding the reputation of a guild's members, keyed by user ID.
+fn reputation_key(guild: GuildId) -> String {
+    format!("reputation:{}", guild.0)
+}
+
+/// The name of the leaderboard holding reputation a guild's members earned before the ledger existed,
+/// which isn't backed by any thank events.
+fn base_reputation_key(guild: GuildId) -> String {
+    format!("reputation-base:{}", guild.0)
+}
+
+/// The names of the leaderboards a thank given at the given time, in the given subject, counts
+/// towards, starting with the all-time leaderboard.
+fn thank_boards(
+    guild: GuildId,
+    timestamp: i64,
+    seasons: &[Season],
+    subject: Option<&str>,
+) -> Vec<String> {
+    let mut boards = vec![reputation_key(guild)];
+    boards.extend(periods::bucket_keys(guild, timestamp, seasons));
+    if let Some(subject) = subject {
+        boards.push(subjects::subject_key(guild, subject));
+    }
+    boards
+}
+
+/// Thanks the given user at the given time, in the given subject, returning the new all-time
+/// reputation of that user. Does no checking on validity.
+async fn thank_user(
+    guild: GuildId,
+    user: &User,
+    timestamp: i64,
+    seasons: &[Season],
+    subject: Option<&str>,
+    storage: &dyn Storage,
+) -> Result<usize, Error> {
+    let boards = thank_boards(guild, timestamp, seasons, subject);
+    let new_reps = storage.increment(&boards, user.id, 1).await?;
+    Ok(new_reps[0])
+}
+
+/// Checks if a given thanker-thankee relationship is allowed at this moment. The original server
+/// prevented thanking bots, but I see no reason to do that. Thus, the two restrictions are that you
+/// can't thank yourself and bots can't thank anyone.
+fn can_thank(thanker: &User, thankee: &User) -> bool {
+    !thanker.bot && thanker != thankee
+}
+
+/// Gets the reputation of a user in a guild, which is 0 if they've never been thanked.
+async fn score(guild: GuildId, user: UserId, storage: &dyn Storage) -> Result<usize, Error> {
+    let score = storage.score(&reputation_key(guild), user).await?;
+    Ok(score.unwrap_or(0))
+}
+
+/// Gets the reputation and rank of a user in a guild, in that order, or `None` if they've never been
+/// thanked.
+pub(crate) async fn get_user_rep(
+    guild: GuildId,
+    user: &User,
+    storage: &dyn Storage,
+) -> Result<Option<(usize, usize)>, Error> {
+    let board = reputation_key(guild);
+    let score = storage.score(&board, user.id).await?;
+    let rank = storage.rank(&board, user.id).await?;
+    Ok(score.zip(rank))
+}
+
+/// Gets the name to show for a user in a guild: their nickname if they have one, their username if
+/// not, and their ID if they can't be found at all (e.g., they deleted their account).
+async fn display_name(ctx: Context<'_>, guild: GuildId, user_id: UserId) -> String {
+    match user_id.to_user(ctx).await {
+        Ok(user) => user.nick_in(ctx, guild).await.unwrap_or(user.name),
+        Err(_) => format!("Unknown user {}", user_id.0),
+    }
+}
+
+/// Get the top users by reputation.
+#[poise::command(slash_command, guild_only)]
+pub(crate) async fn leaderboard(
+    ctx: Context<'_>,
+    #[description = "Number of users to show (default 10)"]
+    #[min = 0_usize]
+    #[max = 50_usize]
+    num_users: Option<usize>,
+    #[description = "Time period to rank by (default all time)"] period: Option<Period>,
+    #[description = "Name of a past or current season to show instead"] season: Option<String>,
+    #[description = "Subject to show the all-time leaderboard of instead"] subject: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+
+    let period = period.unwrap_or_default();
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let board_key = match &subject {
+        Some(subject) if season.is_none() && period == Period::AllTime => {
+            subjects::subject_key(guild_id, subject)
+        }
+        Some(_) => {
+            return Err(
+                anyhow!("Subject leaderboards can't be combined with a period or season.").into(),
+            )
+        }
+        None => periods::board_key(guild_id, period, season.as_deref(), now, storage).await?,
+    };
+    let leaders = storage
+        .top(&board_key, Some(num_users.unwrap_or(10)))
+        .await?;
+    let title = match (&subject, &season, period) {
+        (Some(name), _, _) | (None, Some(name), _) => format!("Leaderboard: {}", name),
+        (None, None, Period::AllTime) => "Leaderboard".to_string(),
+        (None, None, period) => format!("Leaderboard: {}", period),
+    };
+
+    let mut board = vec![];
+    for (user_id, rep) in leaders {
+        board.push(format!(
+            "1. **{}** — **{:>5}** points",
+            display_name(ctx, guild_id, user_id).await,
+            rep
+        ));
+    }
+
+    ctx.say(format!("# {}\n\n{}", title, board.join("\n")))
+        .await?;
+    Ok(())
+}
+
+/// Get the reputation of a user.
+#[poise::command(slash_command, guild_only)]
+pub(crate) async fn reputation(
+    ctx: Context<'_>,
+    #[description = "User to get reputation of"] user: User,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+    let (rep, rank) = get_user_rep(guild_id, &user, storage)
+        .await?
+        .ok_or(anyhow!("{} hasn't been thanked yet.", user.name))?;
+
+    let breakdown: String = subjects::subject_breakdown(guild_id, user.id, storage)
+        .await?
+        .into_iter()
+        .map(|(subject, rep)| format!("\n- {}: **{}**", subject, rep))
+        .collect();
+
+    ctx.say(format!(
+        "User {} has **{}** points (ranked *{}*){}",
+        user.nick_in(ctx, guild_id).await.unwrap_or(user.name),
+        rep,
+        rank,
+        breakdown
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Parent command for managing reputation. Does nothing on its own.
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands(
+        "migrate",
+        "ledger::history",
+        "ledger::audit",
+        "ledger::revoke",
+        "ledger::rebuild",
+        "policy::policy",
+        "milestones::milestones",
+        "milestones::add_milestone",
+        "milestones::remove_milestone",
+        "periods::start_season",
+        "periods::end_season",
+        "subjects::subject",
+        "subjects::subjects",
+        "reactions::reactions"
+    )
+)]
+pub(crate) async fn rep(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Moves points from the old global, username-keyed leaderboard onto this server's leaderboard.
+///
+/// Only the server the old leaderboard belonged to, set in the config, can do this. Every username
+/// that matches a current member of this server is moved over and removed from the old leaderboard,
+/// so running this again won't count anyone twice. Usernames that can't be matched are left alone.
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub(crate) async fn migrate(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    if config().reputation.legacy_guild != Some(guild_id.0) {
+        return Err(anyhow!("The old leaderboard doesn't belong to this server.").into());
+    }
+    ctx.defer().await?;
+
+    let storage = ctx.data().storage.as_ref();
+    let legacy = storage.legacy_reputation().await?;
+
+    // go through the member list once, rather than searching for every username
+    let mut members = HashMap::new();
+    let mut member_list = guild_id.members_iter(ctx.http()).boxed();
+    while let Some(member) = member_list.next().await {
+        let user = member?.user;
+        members.insert(user.name, user.id);
+    }
+
+    let mut migrated = 0;
+    let mut unresolved = 0;
+    for (name, rep) in legacy {
+        match members.get(&name) {
+            Some(&user) => {
+                let boards = [reputation_key(guild_id), base_reputation_key(guild_id)];
+                storage.increment(&boards, user, rep).await?;
+                storage.remove_legacy_reputation(&name).await?;
+                migrated += 1;
+            }
+            None => unresolved += 1,
+        }
+    }
+
+    ctx.say(format!(
+        "Migrated **{}** users onto this server's leaderboard. **{}** users from the old leaderboard couldn't be found here.",
+        migrated, unresolved
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Where and when a thank happened.
+struct ThankSite {
+    /// The guild the thank happened in.
+    guild: GuildId,
+    /// The channel the thank happened in.
+    channel: ChannelId,
+    /// The message the thank was given in or on.
+    message: MessageId,
+    /// The message being thanked for, if there is one, and its author.
+    answer: Option<(MessageId, UserId)>,
+    /// When the thank happened, as a Unix timestamp.
+    timestamp: i64,
+    /// When the thanker joined the guild, as a Unix timestamp, if known.
+    joined_at: Option<i64>,
+}
+
+/// Thanks each of the given users on behalf of `thanker`, following the guild's thank policy and
+/// handing out milestones. Thanks that were already given in the same message, or for the same answer
+/// by reply and by reaction, are skipped, even if they arrive at the same time. Returns the users thanked, with their new reputation, and
+/// the users who couldn't be thanked, with why.
+async fn thank_users<'a>(
+    ctx: &serenity::prelude::Context,
+    thanker: &User,
+    thankees: &[&'a User],
+    site: &ThankSite,
+    storage: &dyn Storage,
+) -> Result<(Vec<(&'a User, usize)>, Vec<(&'a User, Rejection)>), Error> {
+    let guild_id = site.guild;
+    let policy = policy::get_policy(guild_id, storage).await?;
+    let milestones = milestones::get_milestones(guild_id, storage).await?;
+    let seasons = periods::get_seasons(guild_id, storage).await?;
+    let subject = subjects::resolve_subject(ctx, guild_id, site.channel, storage).await?;
+    let mut events =
+        ledger::recent_events(guild_id, site.timestamp - policy::WINDOW, storage).await?;
+
+    let mut reps = vec![];
+    let mut rejections = vec![];
+    for &user in thankees {
+        if !can_thank(thanker, user) {
+            continue;
+        }
+        // the answer only belongs to its author's thank, not to other users mentioned alongside
+        let answer = site
+            .answer
+            .filter(|(_, author)| *author == user.id)
+            .map(|(answer, _)| answer);
+        let claims = ledger::claims(thanker.id, user.id, site.message, answer);
+        if !storage.claim_thanks(guild_id, &claims).await? {
+            continue;
+        }
+        if let Err(rejection) =
+            policy.check(&events, thanker.id, user.id, site.timestamp, site.joined_at)
+        {
+            storage.release_thanks(guild_id, &claims).await?;
+            rejections.push((user, rejection));
+            continue;
+        }
+
+        let new_rep: usize = thank_user(
+            guild_id,
+            user,
+            site.timestamp,
+            &seasons,
+            subject.as_deref(),
+            storage,
+        )
+        .await?;
+        let event = ThankEvent {
+            thanker: thanker.id,
+            thankee: user.id,
+            guild: guild_id,
+            channel: site.channel,
+            message: site.message,
+            timestamp: site.timestamp,
+            answer,
+            subject: subject.clone(),
+            revoked: false,
+        };
+        ledger::record(&event, storage).await?;
+        events.push(event);
+
+        milestones::apply(
+            &ctx.http,
+            &milestones,
+            guild_id,
+            user.id,
+            new_rep - 1,
+            new_rep,
+            Some(site.channel),
+        )
+        .await;
+        reps.push((user, new_rep));
+    }
+
+    Ok((reps, rejections))
+}
+
+/// Given a message, thanks all of the eligible mentions and the author of the message it replies to
+/// if the message author is not on cooldown, starting a cooldown in the case of success. Replies to the
+/// message.
+pub(crate) async fn thank(
+    ctx: &serenity::prelude::Context,
+    msg: &Message,
+    data: &Data,
+) -> Result<(), Error> {
+    // reputation only exists within a guild
+    let Some(guild_id) = msg.guild_id else {
+        return Ok(());
+    };
+
+    let storage = data.storage.as_ref();
+    if storage.on_cooldown(msg.author.id).await? {
+        if let Err(e) = msg
+            .reply(
+                ctx,
+                format!(
+                    "You're still on cooldown. Wait {} seconds and try again!",
+                    config().reputation.thank_cooldown
+                )
+                .as_str(),
+            )
+            .await
+        {
+            println!("Error sending message: {}", e);
+        }
+    } else {
+        if msg.mentions_user(&msg.author) {
+            if let Err(e) = msg.reply(ctx, "You can't thank *yourself*, silly!").await {
+                println!("Error sending message: {}", e);
+            }
+        }
+
+        // replying to a message thanks its author, who may also be pinged by the reply
+        let mut thankees: Vec<&User> = msg.mentions.iter().collect();
+        if let Some(replied_to) = &msg.referenced_message {
+            if !thankees.iter().any(|u| u.id == replied_to.author.id) {
+                thankees.push(&replied_to.author);
+            }
+        }
+
+        let site = ThankSite {
+            guild: guild_id,
+            channel: msg.channel_id,
+            message: msg.id,
+            answer: msg.referenced_message.as_ref().map(|m| (m.id, m.author.id)),
+            timestamp: msg.timestamp.unix_timestamp(),
+            joined_at: msg
+                .member
+                .as_ref()
+                .and_then(|m| m.joined_at)
+                .map(|t| t.unix_timestamp()),
+        };
+        let (reps, rejections) = thank_users(ctx, &msg.author, &thankees, &site, storage).await?;
+
+        let mut content = String::from("");
+        for (user, new_rep) in reps {
+            content.push_str(&format!(
+                "Thanked **{}** (new rep: **{}**)\n",
+                user.name, new_rep
+            ));
+        }
+        let thanked_anyone = !content.is_empty();
+        for (user, rejection) in rejections {
+            content.push_str(&format!(
+                "Couldn't thank **{}**: {}\n",
+                user.name, rejection
+            ));
+        }
+
+        if thanked_anyone {
+            storage
+                .start_cooldown(msg.author.id, config().reputation.thank_cooldown)
+                .await?;
+        }
+        if !content.is_empty() {
+            if let Err(e) = msg.reply(ctx, content).await {
+                println!("Error sending message: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Given a reaction, thanks the author of the message reacted to if the reaction is one of the guild's
+/// thank reactions. Reaction thanks don't start a cooldown, and are only answered if they're rejected.
+pub(crate) async fn thank_reaction(
+    ctx: &serenity::prelude::Context,
+    reaction: &Reaction,
+    data: &Data,
+) -> Result<(), Error> {
+    let Some(guild_id) = reaction.guild_id else {
+        return Ok(());
+    };
+
+    let storage = data.storage.as_ref();
+    if !reactions::is_thank_reaction(guild_id, &reaction.emoji, storage).await? {
+        return Ok(());
+    }
+    let channel = reaction.channel_id;
+    if !guild_settings::feature_enabled(ctx, storage, guild_id, channel, Feature::Thanks).await? {
+        return Ok(());
+    }
+
+    let thanker = reaction.user(ctx).await?;
+    let msg = reaction.message(ctx).await?;
+    let site = ThankSite {
+        guild: guild_id,
+        channel: reaction.channel_id,
+        message: reaction.message_id,
+        answer: Some((reaction.message_id, msg.author.id)),
+        timestamp: time::OffsetDateTime::now_utc().unix_timestamp(),
+        joined_at: reaction
+            .member
+            .as_ref()
+            .and_then(|m| m.joined_at)
+            .map(|t| t.unix_timestamp()),
+    };
+    let (_reps, rejections) = thank_users(ctx, &thanker, &[&msg.author], &site, storage).await?;
+
+    for (user, rejection) in rejections {
+        log_err(
+            reaction
+                .channel_id
+                .say(
+                    ctx,
+                    format!(
+                        "<@{}>, couldn't thank **{}**: {}",
+                        thanker.id.0, user.name, rejection
+                    ),
+                )
+                .await,
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,285 @@
+//! Time-windowed leaderboards. Besides the all-time leaderboard, every thank counts towards a weekly
+//! and a monthly leaderboard, and towards the guild's current season if one is running. Seasons are
+//! started and ended by admins, and the final standings of an ended season are archived.
+
+use crate::storage::{Boards, Storage};
+use crate::utils::{Context, Error};
+use anyhow::anyhow;
+use poise::ChoiceParameter;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::GuildId;
+use time::OffsetDateTime;
+
+use super::ledger::ThankEvent;
+use super::reputation_key;
+
+/// The time period a leaderboard covers.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, ChoiceParameter)]
+pub(crate) enum Period {
+    #[name = "All time"]
+    AllTime,
+    #[name = "This week"]
+    Week,
+    #[name = "This month"]
+    Month,
+    #[name = "This season"]
+    Season,
+}
+
+impl Default for Period {
+    fn default() -> Self {
+        Self::AllTime
+    }
+}
+
+/// A season: a period of time with its own leaderboard, started and ended by admins.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Season {
+    /// The name of the season.
+    pub name: String,
+    /// When the season started, as a Unix timestamp.
+    pub start: i64,
+    /// When the season ended, as a Unix timestamp, or `None` if it is still running.
+    pub end: Option<i64>,
+}
+
+impl Season {
+    /// Whether a thank given at the given time counts towards this season.
+    fn contains(&self, timestamp: i64) -> bool {
+        self.start <= timestamp && self.end.map_or(true, |end| timestamp < end)
+    }
+}
+
+/// Converts a Unix timestamp to a date, falling back to the epoch for out-of-range timestamps.
+fn datetime(timestamp: i64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(timestamp).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+/// The name of the leaderboard holding a guild's reputation for the ISO week containing the timestamp.
+fn week_key(guild: GuildId, timestamp: i64) -> String {
+    let (year, week, _) = datetime(timestamp).to_iso_week_date();
+    format!("{}:week:{}-W{:02}", reputation_key(guild), year, week)
+}
+
+/// The name of the leaderboard holding a guild's reputation for the month containing the timestamp.
+fn month_key(guild: GuildId, timestamp: i64) -> String {
+    let date = datetime(timestamp);
+    format!(
+        "{}:month:{}-{:02}",
+        reputation_key(guild),
+        date.year(),
+        date.month() as u8
+    )
+}
+
+/// The name of the leaderboard holding a guild's reputation for the given season while it runs.
+fn season_key(guild: GuildId, season: &str) -> String {
+    format!("{}:season:{}", reputation_key(guild), season)
+}
+
+/// The name of the leaderboard holding the final standings of an ended season.
+fn season_archive_key(guild: GuildId, season: &str) -> String {
+    format!("rep-season-archive:{}:{}", guild.0, season)
+}
+
+/// The name of the guild setting holding a guild's list of seasons.
+const SEASONS: &str = "rep-seasons";
+
+/// Gets all of a guild's seasons, oldest first.
+pub(crate) async fn get_seasons(
+    guild: GuildId,
+    storage: &dyn Storage,
+) -> Result<Vec<Season>, Error> {
+    let raw = storage.guild_setting(guild, SEASONS).await?;
+    Ok(raw
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default())
+}
+
+/// Sets a guild's list of seasons.
+async fn set_seasons(
+    guild: GuildId,
+    seasons: &[Season],
+    storage: &dyn Storage,
+) -> Result<(), Error> {
+    let serialized = serde_json::to_string(seasons).expect("Seasons are always serializable");
+    storage.set_guild_setting(guild, SEASONS, &serialized).await
+}
+
+/// The names of the time-bucketed leaderboards a thank given at the given time counts towards.
+pub(crate) fn bucket_keys(guild: GuildId, timestamp: i64, seasons: &[Season]) -> Vec<String> {
+    let mut keys = vec![week_key(guild, timestamp), month_key(guild, timestamp)];
+    keys.extend(
+        seasons
+            .iter()
+            .filter(|s| s.contains(timestamp))
+            .map(|s| season_key(guild, &s.name)),
+    );
+    keys
+}
+
+/// The name of the leaderboard holding the leaderboard for the given period at time `now`. A named
+/// season can be given to see any season, past or present, instead of just the current one.
+pub(crate) async fn board_key(
+    guild: GuildId,
+    period: Period,
+    season: Option<&str>,
+    now: i64,
+    storage: &dyn Storage,
+) -> Result<String, Error> {
+    let seasons = get_seasons(guild, storage).await?;
+    if let Some(name) = season {
+        return match seasons.iter().find(|s| s.name == name) {
+            Some(s) if s.end.is_some() => Ok(season_archive_key(guild, &s.name)),
+            Some(s) => Ok(season_key(guild, &s.name)),
+            None => Err(anyhow!("There is no season named {}.", name).into()),
+        };
+    }
+
+    match period {
+        Period::AllTime => Ok(reputation_key(guild)),
+        Period::Week => Ok(week_key(guild, now)),
+        Period::Month => Ok(month_key(guild, now)),
+        Period::Season => seasons
+            .iter()
+            .find(|s| s.end.is_none())
+            .map(|s| season_key(guild, &s.name))
+            .ok_or_else(|| anyhow!("There is no season running right now.").into()),
+    }
+}
+
+/// Adds the contents of every time-bucketed leaderboard that any of the given thanks count towards to
+/// `boards`, so they can be rebuilt. Archived season standings are left as they were when the season
+/// ended.
+pub(crate) async fn rebuild_buckets(
+    guild: GuildId,
+    events: &[ThankEvent],
+    boards: &mut Boards,
+    storage: &dyn Storage,
+) -> Result<(), Error> {
+    let seasons = get_seasons(guild, storage).await?;
+    for event in events {
+        for key in bucket_keys(guild, event.timestamp, &seasons) {
+            // buckets with only revoked thanks still have to be cleared out
+            let board = boards.entry(key).or_default();
+            if !event.revoked {
+                *board.entry(event.thankee).or_default() += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Starts a new season, ending the current one if there is one.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn start_season(
+    ctx: Context<'_>,
+    #[description = "Name of the new season"] name: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+
+    let mut seasons = get_seasons(guild_id, storage).await?;
+    if seasons.iter().any(|s| s.name == name) {
+        return Err(anyhow!("There is already a season named {}.", name).into());
+    }
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let ended = end_current_season(guild_id, &mut seasons, now, storage).await?;
+    seasons.push(Season {
+        name: name.clone(),
+        start: now,
+        end: None,
+    });
+    set_seasons(guild_id, &seasons, storage).await?;
+
+    ctx.say(match ended {
+        Some(old) => format!("Ended season **{}** and started season **{}**!", old, name),
+        None => format!("Started season **{}**!", name),
+    })
+    .await?;
+    Ok(())
+}
+
+/// Ends the current season, archiving its final standings.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn end_season(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+
+    let mut seasons = get_seasons(guild_id, storage).await?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let ended = end_current_season(guild_id, &mut seasons, now, storage)
+        .await?
+        .ok_or(anyhow!("There is no season running right now."))?;
+    set_seasons(guild_id, &seasons, storage).await?;
+
+    ctx.say(format!(
+        "Ended season **{}**. Its final standings can be seen with `/leaderboard season:{}`.",
+        ended, ended
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Marks the running season, if any, as ended at `now` and archives its standings, returning its name.
+/// The caller is responsible for saving `seasons`.
+async fn end_current_season(
+    guild: GuildId,
+    seasons: &mut [Season],
+    now: i64,
+    storage: &dyn Storage,
+) -> Result<Option<String>, Error> {
+    match seasons.iter_mut().find(|s| s.end.is_none()) {
+        Some(season) => {
+            season.end = Some(now);
+            storage
+                .copy_board(
+                    &season_key(guild, &season.name),
+                    &season_archive_key(guild, &season.name),
+                )
+                .await?;
+            Ok(Some(season.name.clone()))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_keys() {
+        // 2024-01-01 is a Monday, in ISO week 1 of 2024
+        let new_year = 1704067200;
+        let seasons = vec![
+            Season {
+                name: "Fall".to_string(),
+                start: 0,
+                end: Some(new_year),
+            },
+            Season {
+                name: "Spring".to_string(),
+                start: new_year,
+                end: None,
+            },
+        ];
+        assert_eq!(
+            bucket_keys(GuildId(1), new_year, &seasons),
+            vec![
+                "reputation:1:week:2024-W01",
+                "reputation:1:month:2024-01",
+                "reputation:1:season:Spring"
+            ]
+        );
+        // 2023-12-31 is a Sunday, in the last ISO week of 2023
+        assert_eq!(
+            bucket_keys(GuildId(1), new_year - 1, &seasons),
+            vec![
+                "reputation:1:week:2023-W52",
+                "reputation:1:month:2023-12",
+                "reputation:1:season:Fall"
+            ]
+        );
+    }
+}
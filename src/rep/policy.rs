@@ -0,0 +1,348 @@
+//! Anti-farming rules for thanks. Each guild can limit how often thanks are given, so that two friends
+//! can't just trade points back and forth.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::storage::Storage;
+use crate::utils::{Context, Error};
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{GuildId, UserId};
+
+use super::ledger::ThankEvent;
+
+/// The number of seconds in a day.
+const DAY: i64 = 24 * 60 * 60;
+
+/// How far back to look for thank rings.
+const RING_WINDOW: i64 = 7 * DAY;
+
+/// How far back thanks can matter to a policy check, so older ones don't need to be looked at.
+pub(crate) const WINDOW: i64 = RING_WINDOW;
+
+/// A guild's limits on thanking. A limit of 0 means there is no limit.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct ThankPolicy {
+    /// How many times a day one user can thank the same other user.
+    pub pair_daily_limit: usize,
+    /// How many thanks a user can give in a day in total.
+    pub daily_limit: usize,
+    /// How many days someone has to have been in the guild before their thanks count.
+    pub min_member_age_days: i64,
+    /// How many times each user in a group has to have thanked the next within a week before the
+    /// group is treated as a thank ring.
+    pub ring_threshold: usize,
+}
+
+impl Default for ThankPolicy {
+    fn default() -> Self {
+        Self {
+            pair_daily_limit: 3,
+            daily_limit: 20,
+            min_member_age_days: 0,
+            ring_threshold: 5,
+        }
+    }
+}
+
+impl Display for ThankPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn limit(n: i64) -> String {
+            match n {
+                0 => "no limit".to_string(),
+                n => n.to_string(),
+            }
+        }
+        write!(
+            f,
+            "- Thanks per day to the same user: **{}**\n- Thanks per day in total: **{}**\n- Days in the server before thanking: **{}**\n- Mutual thanks per week before a ring is detected: **{}**",
+            limit(self.pair_daily_limit as i64),
+            limit(self.daily_limit as i64),
+            limit(self.min_member_age_days),
+            limit(self.ring_threshold as i64)
+        )
+    }
+}
+
+/// Why a thank was not allowed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Rejection {
+    /// The thanker has already thanked this user too many times today.
+    PairLimit(usize),
+    /// The thanker has already given too many thanks today.
+    DailyLimit(usize),
+    /// The thanker joined the guild too recently.
+    TooNew(i64),
+    /// The thanker and thankee are part of a group that keeps thanking each other.
+    Ring(Vec<UserId>),
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::PairLimit(n) => {
+                write!(f, "you can only thank the same person {} times a day", n)
+            }
+            Rejection::DailyLimit(n) => write!(f, "you can only give {} thanks a day", n),
+            Rejection::TooNew(days) => write!(
+                f,
+                "you need to have been in the server for {} days before you can thank people",
+                days
+            ),
+            Rejection::Ring(users) if users.len() == 2 => {
+                write!(
+                    f,
+                    "you two have been thanking each other too much this week"
+                )
+            }
+            Rejection::Ring(users) => write!(
+                f,
+                "you're one of {} people who have been thanking each other too much this week",
+                users.len()
+            ),
+        }
+    }
+}
+
+impl ThankPolicy {
+    /// Checks whether `thanker` may thank `thankee` at time `now`, given the thanks already given in
+    /// the guild in the last [WINDOW] and the time the thanker joined the guild, if known.
+    pub(crate) fn check(
+        &self,
+        events: &[ThankEvent],
+        thanker: UserId,
+        thankee: UserId,
+        now: i64,
+        joined_at: Option<i64>,
+    ) -> Result<(), Rejection> {
+        if self.min_member_age_days > 0 {
+            if let Some(joined) = joined_at {
+                if now - joined < self.min_member_age_days.saturating_mul(DAY) {
+                    return Err(Rejection::TooNew(self.min_member_age_days));
+                }
+            }
+        }
+
+        let today: Vec<&ThankEvent> = events
+            .iter()
+            .filter(|e| !e.revoked && e.thanker == thanker && now - e.timestamp < DAY)
+            .collect();
+
+        if self.daily_limit > 0 && today.len() >= self.daily_limit {
+            return Err(Rejection::DailyLimit(self.daily_limit));
+        }
+
+        if self.pair_daily_limit > 0
+            && today.iter().filter(|e| e.thankee == thankee).count() >= self.pair_daily_limit
+        {
+            return Err(Rejection::PairLimit(self.pair_daily_limit));
+        }
+
+        if self.ring_threshold > 0 {
+            if let Some(ring) = find_ring(events, thanker, thankee, now, self.ring_threshold) {
+                return Err(Rejection::Ring(ring));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Looks for a group of two or three users, including this thank, where each user has thanked the
+/// next at least `threshold` times in the past week. Returns the users in the ring, if there is one.
+fn find_ring(
+    events: &[ThankEvent],
+    thanker: UserId,
+    thankee: UserId,
+    now: i64,
+    threshold: usize,
+) -> Option<Vec<UserId>> {
+    let mut counts: HashMap<(UserId, UserId), usize> = HashMap::new();
+    for e in events
+        .iter()
+        .filter(|e| !e.revoked && now - e.timestamp < RING_WINDOW)
+    {
+        *counts.entry((e.thanker, e.thankee)).or_default() += 1;
+    }
+    // count the thank being checked
+    *counts.entry((thanker, thankee)).or_default() += 1;
+
+    let heavy =
+        |from: UserId, to: UserId| counts.get(&(from, to)).copied().unwrap_or(0) >= threshold;
+
+    if !heavy(thanker, thankee) {
+        return None;
+    }
+
+    if heavy(thankee, thanker) {
+        return Some(vec![thanker, thankee]);
+    }
+
+    counts
+        .keys()
+        .filter(|(from, to)| *from == thankee && *to != thanker && heavy(*from, *to))
+        .find(|(_, middle)| heavy(*middle, thanker))
+        .map(|(_, middle)| vec![thanker, thankee, *middle])
+}
+
+/// The name of the guild setting holding a guild's thank policy.
+const POLICY: &str = "rep-policy";
+
+/// Gets a guild's thank policy, or the default policy if none has been set.
+pub(crate) async fn get_policy(
+    guild: GuildId,
+    storage: &dyn Storage,
+) -> Result<ThankPolicy, Error> {
+    let raw = storage.guild_setting(guild, POLICY).await?;
+    Ok(raw
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default())
+}
+
+/// Sets a guild's thank policy.
+async fn set_policy(
+    guild: GuildId,
+    policy: &ThankPolicy,
+    storage: &dyn Storage,
+) -> Result<(), Error> {
+    let serialized = serde_json::to_string(policy).expect("Policies are always serializable");
+    storage.set_guild_setting(guild, POLICY, &serialized).await
+}
+
+/// Shows or changes this server's limits on thanking. Set a limit to 0 to disable it.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn policy(
+    ctx: Context<'_>,
+    #[description = "How many times a day someone can thank the same person"]
+    pair_daily_limit: Option<usize>,
+    #[description = "How many thanks someone can give in a day"] daily_limit: Option<usize>,
+    #[description = "How many days someone must be in the server before thanking"]
+    #[min = 0_i64]
+    #[max = 3650_i64]
+    min_member_age_days: Option<i64>,
+    #[description = "How many mutual thanks per week count as a thank ring"] ring_threshold: Option<
+        usize,
+    >,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+
+    let mut policy = get_policy(guild_id, storage).await?;
+    let old_policy = policy.clone();
+    policy.pair_daily_limit = pair_daily_limit.unwrap_or(policy.pair_daily_limit);
+    policy.daily_limit = daily_limit.unwrap_or(policy.daily_limit);
+    policy.min_member_age_days = min_member_age_days.unwrap_or(policy.min_member_age_days);
+    policy.ring_threshold = ring_threshold.unwrap_or(policy.ring_threshold);
+
+    if policy != old_policy {
+        set_policy(guild_id, &policy, storage).await?;
+        ctx.say(format!("Updated the thank policy:\n{}", policy))
+            .await?;
+    } else {
+        ctx.say(format!("Current thank policy:\n{}", policy))
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::model::id::{ChannelId, MessageId};
+
+    fn event(thanker: u64, thankee: u64, timestamp: i64) -> ThankEvent {
+        ThankEvent {
+            thanker: UserId(thanker),
+            thankee: UserId(thankee),
+            guild: GuildId(1),
+            channel: ChannelId(1),
+            message: MessageId(1),
+            timestamp,
+            answer: None,
+            subject: None,
+            revoked: false,
+        }
+    }
+
+    #[test]
+    fn test_pair_limit() {
+        let policy = ThankPolicy::default();
+        let events: Vec<ThankEvent> = (0..3).map(|i| event(1, 2, i)).collect();
+        assert_eq!(
+            policy.check(&events, UserId(1), UserId(2), 100, None),
+            Err(Rejection::PairLimit(3))
+        );
+        assert_eq!(
+            policy.check(&events, UserId(1), UserId(3), 100, None),
+            Ok(())
+        );
+        // a day later, the limit resets
+        assert_eq!(
+            policy.check(&events, UserId(1), UserId(2), DAY + 10, None),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_daily_limit() {
+        let policy = ThankPolicy::default();
+        let events: Vec<ThankEvent> = (0..20).map(|i| event(1, 10 + i, i as i64)).collect();
+        assert_eq!(
+            policy.check(&events, UserId(1), UserId(2), 100, None),
+            Err(Rejection::DailyLimit(20))
+        );
+    }
+
+    #[test]
+    fn test_member_age() {
+        let policy = ThankPolicy {
+            min_member_age_days: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.check(&[], UserId(1), UserId(2), DAY, Some(0)),
+            Err(Rejection::TooNew(2))
+        );
+        assert_eq!(
+            policy.check(&[], UserId(1), UserId(2), 3 * DAY, Some(0)),
+            Ok(())
+        );
+        let policy = ThankPolicy {
+            min_member_age_days: i64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.check(&[], UserId(1), UserId(2), DAY, Some(0)),
+            Err(Rejection::TooNew(i64::MAX))
+        );
+    }
+
+    #[test]
+    fn test_rings() {
+        let policy = ThankPolicy {
+            pair_daily_limit: 0,
+            daily_limit: 0,
+            ..Default::default()
+        };
+        let mut events: Vec<ThankEvent> = (0..5).map(|i| event(2, 1, i)).collect();
+        events.extend((0..4).map(|i| event(1, 2, i)));
+        assert_eq!(
+            policy.check(&events, UserId(1), UserId(2), 100, None),
+            Err(Rejection::Ring(vec![UserId(1), UserId(2)]))
+        );
+
+        let mut events: Vec<ThankEvent> = (0..5).map(|i| event(2, 3, i)).collect();
+        events.extend((0..5).map(|i| event(3, 1, i)));
+        events.extend((0..4).map(|i| event(1, 2, i)));
+        assert_eq!(
+            policy.check(&events, UserId(1), UserId(2), 100, None),
+            Err(Rejection::Ring(vec![UserId(1), UserId(2), UserId(3)]))
+        );
+        // old thanks don't count
+        assert_eq!(
+            policy.check(&events, UserId(1), UserId(2), RING_WINDOW + 100, None),
+            Ok(())
+        );
+    }
+}
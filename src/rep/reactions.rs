@@ -0,0 +1,82 @@
+//! Thank reactions: reacting to a message with one of a guild's thank reactions thanks its author.
+
+use crate::storage::Storage;
+use crate::utils::{Context, Error};
+use serenity::model::channel::ReactionType;
+use serenity::model::id::GuildId;
+
+/// The reactions that count as thanks in guilds that haven't chosen their own.
+const DEFAULT_THANK_REACTIONS: [&str; 2] = ["✅", "🙏"];
+
+/// The name of the guild setting holding a guild's thank reactions.
+const THANK_REACTIONS: &str = "rep-thank-reactions";
+
+/// Gets the reactions that count as thanks in a guild, as they appear in messages: Unicode emoji as
+/// themselves and custom emoji as `<:name:id>`.
+async fn get_thank_reactions(guild: GuildId, storage: &dyn Storage) -> Result<Vec<String>, Error> {
+    let raw = storage.guild_setting(guild, THANK_REACTIONS).await?;
+    Ok(raw
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_else(|| {
+            DEFAULT_THANK_REACTIONS
+                .iter()
+                .map(|r| r.to_string())
+                .collect()
+        }))
+}
+
+/// Checks whether a reaction counts as a thank in a guild.
+pub(crate) async fn is_thank_reaction(
+    guild: GuildId,
+    emoji: &ReactionType,
+    storage: &dyn Storage,
+) -> Result<bool, Error> {
+    let emoji = match emoji {
+        // the name of a custom emoji is irrelevant, and isn't always sent
+        ReactionType::Custom { id, .. } => id.0.to_string(),
+        other => other.to_string(),
+    };
+    Ok(get_thank_reactions(guild, storage)
+        .await?
+        .iter()
+        .any(|r| match r.rsplit_once(':') {
+            Some((_, id)) => id.trim_end_matches('>') == emoji,
+            None => *r == emoji,
+        }))
+}
+
+/// Shows or sets which reactions thank the author of the message reacted to.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn reactions(
+    ctx: Context<'_>,
+    #[description = "Thank reactions, separated by spaces (\"none\" to disable)"] emojis: Option<
+        String,
+    >,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+
+    if let Some(emojis) = emojis {
+        let reactions: Vec<&str> = match emojis.trim() {
+            "none" => vec![],
+            emojis => emojis.split_whitespace().collect(),
+        };
+        let serialized =
+            serde_json::to_string(&reactions).expect("Reactions are always serializable");
+        storage
+            .set_guild_setting(guild_id, THANK_REACTIONS, &serialized)
+            .await?;
+    }
+
+    let reactions = get_thank_reactions(guild_id, storage).await?;
+    ctx.say(if reactions.is_empty() {
+        "Reactions don't count as thanks in this server.".to_string()
+    } else {
+        format!(
+            "Reacting with {} thanks the author of a message.",
+            reactions.join(" or ")
+        )
+    })
+    .await?;
+    Ok(())
+}
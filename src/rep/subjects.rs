@@ -0,0 +1,168 @@
+//! Subject-tagged reputation. Each guild can map channels to subjects (math, chemistry, languages...),
+//! and thanks given in a channel also count towards that subject's leaderboard. Threads and forum
+//! posts count towards the subject of the channel they were made in, unless mapped themselves.
+
+use std::collections::HashSet;
+
+use crate::storage::{Boards, Storage};
+use crate::utils::{Context, Error};
+use anyhow::anyhow;
+use serenity::model::channel::Channel;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+
+use super::ledger::ThankEvent;
+use super::reputation_key;
+
+/// The name of the leaderboard holding a guild's reputation in a subject.
+pub(crate) fn subject_key(guild: GuildId, subject: &str) -> String {
+    format!(
+        "{}:subject:{}",
+        reputation_key(guild),
+        subject.to_lowercase()
+    )
+}
+
+/// Gets all of the subjects in a guild, in alphabetical order.
+pub(crate) async fn get_subjects(
+    guild: GuildId,
+    storage: &dyn Storage,
+) -> Result<Vec<String>, Error> {
+    let mut subjects: Vec<String> = storage
+        .channel_subjects(guild)
+        .await?
+        .into_values()
+        .collect();
+    subjects.sort();
+    subjects.dedup();
+    Ok(subjects)
+}
+
+/// Works out which subject thanks in a channel count towards, if any: that of the channel or, failing
+/// that, that of the channel's parent.
+pub(crate) async fn resolve_subject(
+    ctx: &serenity::prelude::Context,
+    guild: GuildId,
+    channel: ChannelId,
+    storage: &dyn Storage,
+) -> Result<Option<String>, Error> {
+    let mut mapping = storage.channel_subjects(guild).await?;
+    if let Some(subject) = mapping.remove(&channel.0) {
+        return Ok(Some(subject));
+    }
+
+    let parent = match channel.to_channel(ctx).await {
+        Ok(Channel::Guild(channel)) => channel.parent_id,
+        _ => None,
+    };
+    Ok(parent.and_then(|p| mapping.remove(&p.0)))
+}
+
+/// Gets a user's reputation in each subject of a guild in which they have any, highest first.
+pub(crate) async fn subject_breakdown(
+    guild: GuildId,
+    user: UserId,
+    storage: &dyn Storage,
+) -> Result<Vec<(String, usize)>, Error> {
+    let mut breakdown = vec![];
+    for subject in get_subjects(guild, storage).await? {
+        let score = storage.score(&subject_key(guild, &subject), user).await?;
+        if let Some(score) = score.filter(|&s| s > 0) {
+            breakdown.push((subject, score));
+        }
+    }
+    breakdown.sort_by(|a, b| b.1.cmp(&a.1));
+    Ok(breakdown)
+}
+
+/// Adds the contents of every subject leaderboard in a guild, as given by the given thanks, to
+/// `boards`, so they can be rebuilt.
+pub(crate) async fn rebuild_subjects(
+    guild: GuildId,
+    events: &[ThankEvent],
+    boards: &mut Boards,
+    storage: &dyn Storage,
+) -> Result<(), Error> {
+    // subjects that have been unmapped since still have to be cleared out
+    let mut subjects: HashSet<String> = get_subjects(guild, storage).await?.into_iter().collect();
+    subjects.extend(events.iter().filter_map(|e| e.subject.clone()));
+    for subject in subjects {
+        boards.entry(subject_key(guild, &subject)).or_default();
+    }
+
+    for event in events.iter().filter(|e| !e.revoked) {
+        if let Some(subject) = &event.subject {
+            *boards
+                .entry(subject_key(guild, subject))
+                .or_default()
+                .entry(event.thankee)
+                .or_default() += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the subject thanks in a channel count towards, or clears it if no subject is given.
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub(crate) async fn subject(
+    ctx: Context<'_>,
+    #[description = "Channel, thread or forum to set the subject of"] channel: Channel,
+    #[description = "The subject (leave out to clear)"] subject: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let storage = ctx.data().storage.as_ref();
+
+    let channel_id: ChannelId = channel.id();
+    match subject {
+        Some(subject) => {
+            let subject = subject.trim().to_lowercase();
+            if subject.is_empty() {
+                return Err(anyhow!("Subjects can't be blank.").into());
+            }
+            storage
+                .set_channel_subject(guild_id, channel_id, Some(&subject))
+                .await?;
+            ctx.say(format!(
+                "Thanks in <#{}> now count towards **{}**.",
+                channel_id.0, subject
+            ))
+            .await?;
+        }
+        None => {
+            storage
+                .set_channel_subject(guild_id, channel_id, None)
+                .await?;
+            ctx.say(format!("Cleared the subject of <#{}>.", channel_id.0))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists which channels count towards which subjects.
+#[poise::command(slash_command, guild_only)]
+pub(crate) async fn subjects(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap_or_default();
+    let mut mapping: Vec<(u64, String)> = ctx
+        .data()
+        .storage
+        .channel_subjects(guild_id)
+        .await?
+        .into_iter()
+        .collect();
+    mapping.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let content = if mapping.is_empty() {
+        "No channels have a subject yet.".to_string()
+    } else {
+        format!(
+            "# Subjects\n{}",
+            mapping
+                .iter()
+                .map(|(channel, subject)| format!("- <#{}>: **{}**", channel, subject))
+                .collect::<Vec<String>>()
+                .join("\n")
+        )
+    };
+    ctx.say(content).await?;
+    Ok(())
+}
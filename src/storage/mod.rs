@@ -0,0 +1,152 @@
+//! Persistent storage for everything Nano remembers: reputation, thank cooldowns and user preferences.
+//!
+//! Storage is behind the [Storage] trait, with a [Redis backend](RedisStorage) for big deployments and
+//! an embedded [SQLite backend](SqliteStorage) for small ones (and tests) that don't want to run a
+//! separate server. Which one is used is decided at startup by the storage URL: `sqlite:` followed by
+//! a path (or `:memory:`) opens a SQLite database, and anything else is treated as a Redis URL.
+
+mod redis_storage;
+mod sqlite_storage;
+
+use std::collections::HashMap;
+
+use crate::utils::Error;
+use async_trait::async_trait;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+
+pub(crate) use redis_storage::RedisStorage;
+pub(crate) use sqlite_storage::SqliteStorage;
+
+/// The full contents of some leaderboards, used to overwrite them all at once.
+pub(crate) type Boards = HashMap<String, HashMap<UserId, usize>>;
+
+/// Somewhere to keep the bot's state.
+///
+/// Leaderboards are named sets of users with points. Guild settings and ledger entries are opaque
+/// strings (JSON, in practice) that are only interpreted by the modules that write them.
+#[async_trait]
+pub(crate) trait Storage: Send + Sync {
+    /// Gives a user `points` more points on each of the given leaderboards, all at once, returning
+    /// their new points on each leaderboard in the same order.
+    async fn increment(
+        &self,
+        boards: &[String],
+        user: UserId,
+        points: usize,
+    ) -> Result<Vec<usize>, Error>;
+
+    /// Takes `points` points away from a user on each of the given leaderboards, all at once. Users
+    /// left with no points are taken off the leaderboard.
+    async fn decrement(&self, boards: &[String], user: UserId, points: usize) -> Result<(), Error>;
+
+    /// Gets a user's points on a leaderboard, if they're on it at all.
+    async fn score(&self, board: &str, user: UserId) -> Result<Option<usize>, Error>;
+
+    /// Gets a user's rank on a leaderboard, starting from 1 for the user with the most points, if
+    /// they're on it at all.
+    async fn rank(&self, board: &str, user: UserId) -> Result<Option<usize>, Error>;
+
+    /// Gets the users on a leaderboard and their points, most points first, keeping only the first
+    /// `limit` if a limit is given.
+    async fn top(&self, board: &str, limit: Option<usize>) -> Result<Vec<(UserId, usize)>, Error>;
+
+    /// Gets the users with at least `threshold` points on a leaderboard.
+    async fn at_least(&self, board: &str, threshold: usize) -> Result<Vec<UserId>, Error>;
+
+    /// Overwrites each of the given leaderboards with the given points, all at once. Leaderboards
+    /// given without any users are cleared.
+    async fn replace_boards(&self, boards: Boards) -> Result<(), Error>;
+
+    /// Overwrites a leaderboard with a copy of another.
+    async fn copy_board(&self, from: &str, to: &str) -> Result<(), Error>;
+
+    /// Gets the old global, username-keyed leaderboard, from before reputation was per guild. Only
+    /// the Redis backend can have one.
+    async fn legacy_reputation(&self) -> Result<Vec<(String, usize)>, Error> {
+        Ok(vec![])
+    }
+
+    /// Removes a username from the old global leaderboard.
+    async fn remove_legacy_reputation(&self, _name: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Gets one of a user's preferences as it was stored before preferences were keyed by user ID,
+    /// under their username. Only the Redis backend can have these.
+    async fn legacy_preference(
+        &self,
+        _name: &str,
+        _username: &str,
+    ) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    /// Removes a preference stored under a username.
+    async fn remove_legacy_preference(&self, _name: &str, _username: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Appends an entry to a guild's thank ledger, for a thank given at the given Unix timestamp.
+    async fn push_ledger(&self, guild: GuildId, timestamp: i64, entry: &str) -> Result<(), Error>;
+
+    /// Gets every entry of a guild's thank ledger, oldest first.
+    async fn ledger(&self, guild: GuildId) -> Result<Vec<String>, Error>;
+
+    /// Gets the entries of a guild's thank ledger for thanks given at or after the given Unix
+    /// timestamp, in the order they were added.
+    async fn recent_ledger(&self, guild: GuildId, since: i64) -> Result<Vec<String>, Error>;
+
+    /// Overwrites the entry at the given position of a guild's thank ledger.
+    async fn set_ledger_entry(
+        &self,
+        guild: GuildId,
+        index: usize,
+        entry: &str,
+    ) -> Result<(), Error>;
+
+    /// Claims all of the given keys in a guild's set of thanks given, unless any of them has been
+    /// claimed already, returning whether they were claimed. Claiming is atomic, so of two thanks
+    /// claiming the same key at once, only one gets it.
+    async fn claim_thanks(&self, guild: GuildId, claims: &[String]) -> Result<bool, Error>;
+
+    /// Releases keys claimed in a guild's set of thanks given, so they can be claimed again.
+    async fn release_thanks(&self, guild: GuildId, claims: &[String]) -> Result<(), Error>;
+
+    /// Gets one of a guild's settings, if it has been set.
+    async fn guild_setting(&self, guild: GuildId, name: &str) -> Result<Option<String>, Error>;
+
+    /// Sets one of a guild's settings.
+    async fn set_guild_setting(&self, guild: GuildId, name: &str, value: &str)
+        -> Result<(), Error>;
+
+    /// Gets the subjects of a guild's channels, by channel ID.
+    async fn channel_subjects(&self, guild: GuildId) -> Result<HashMap<u64, String>, Error>;
+
+    /// Sets the subject of a channel, or clears it if no subject is given.
+    async fn set_channel_subject(
+        &self,
+        guild: GuildId,
+        channel: ChannelId,
+        subject: Option<&str>,
+    ) -> Result<(), Error>;
+
+    /// Checks whether a user is on cooldown for thanking.
+    async fn on_cooldown(&self, user: UserId) -> Result<bool, Error>;
+
+    /// Puts a user on cooldown for thanking for the given number of seconds.
+    async fn start_cooldown(&self, user: UserId, seconds: usize) -> Result<(), Error>;
+
+    /// Gets one of a user's preferences, if they have set it.
+    async fn preference(&self, name: &str, user: UserId) -> Result<Option<String>, Error>;
+
+    /// Sets one of a user's preferences.
+    async fn set_preference(&self, name: &str, user: UserId, value: &str) -> Result<(), Error>;
+}
+
+/// Opens the storage at the given URL: a SQLite database for `sqlite:<path>`, Redis otherwise.
+pub(crate) async fn connect(url: &str) -> Result<Box<dyn Storage>, Error> {
+    match url.strip_prefix("sqlite:") {
+        Some(path) => Ok(Box::new(SqliteStorage::open(path)?)),
+        None => Ok(Box::new(RedisStorage::connect(url).await?)),
+    }
+}
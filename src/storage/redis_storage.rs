@@ -0,0 +1,292 @@
+//! Storage in Redis. Leaderboards are sorted sets, ledgers are lists with a sorted set of positions
+//! by time, guild settings are plain keys named `{setting}:{guild}`, channel subjects, thank claims
+//! and preferences are hashes and cooldowns are keys that expire.
+
+use std::collections::HashMap;
+
+use crate::utils::Error;
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+
+use super::{Boards, Storage};
+
+/// The name of the old global reputation set, keyed by username.
+const LEGACY_REPUTATION: &str = "reputation";
+
+/// Storage in a Redis server, through a connection that is shared between tasks and reconnects by
+/// itself if the server goes away.
+pub(crate) struct RedisStorage {
+    con: ConnectionManager,
+}
+
+impl RedisStorage {
+    /// Connects to the Redis server at the given URL.
+    pub(crate) async fn connect(url: &str) -> Result<Self, Error> {
+        let client = redis::Client::open(url)?;
+        Ok(Self {
+            con: ConnectionManager::new(client).await?,
+        })
+    }
+}
+
+/// The name of the list holding a guild's thank ledger.
+fn ledger_key(guild: GuildId) -> String {
+    format!("rep-ledger:{}", guild.0)
+}
+
+/// The name of the sorted set holding the positions in a guild's thank ledger, by the time of the
+/// thank.
+fn ledger_times_key(guild: GuildId) -> String {
+    format!("rep-ledger-times:{}", guild.0)
+}
+
+/// The name of the hash holding the keys claimed by a guild's thanks.
+fn claims_key(guild: GuildId) -> String {
+    format!("rep-claims:{}", guild.0)
+}
+
+/// Claims every field given as an argument in the hash given as a key, unless any of them exists.
+const CLAIM_SCRIPT: &str = "
+for _, claim in ipairs(ARGV) do
+    if redis.call('HEXISTS', KEYS[1], claim) == 1 then
+        return 0
+    end
+end
+for _, claim in ipairs(ARGV) do
+    redis.call('HSET', KEYS[1], claim, 1)
+end
+return 1
+";
+
+/// The name of the hash mapping a guild's channels to subjects.
+fn subjects_key(guild: GuildId) -> String {
+    format!("rep-subjects:{}", guild.0)
+}
+
+/// The name of the key that exists while a user is on cooldown.
+fn cooldown_key(user: UserId) -> String {
+    format!("on-cooldown:{}", user.0)
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn increment(
+        &self,
+        boards: &[String],
+        user: UserId,
+        points: usize,
+    ) -> Result<Vec<usize>, Error> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for board in boards {
+            pipe.zincr(board, user.0, points);
+        }
+        Ok(pipe.query_async(&mut self.con.clone()).await?)
+    }
+
+    async fn decrement(&self, boards: &[String], user: UserId, points: usize) -> Result<(), Error> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for board in boards {
+            pipe.zincr(board, user.0, -(points as i64)).ignore();
+            pipe.zrembyscore(board, "-inf", 0).ignore();
+        }
+        Ok(pipe.query_async(&mut self.con.clone()).await?)
+    }
+
+    async fn score(&self, board: &str, user: UserId) -> Result<Option<usize>, Error> {
+        Ok(self.con.clone().zscore(board, user.0).await?)
+    }
+
+    async fn rank(&self, board: &str, user: UserId) -> Result<Option<usize>, Error> {
+        let rank: Option<usize> = self.con.clone().zrevrank(board, user.0).await?;
+        Ok(rank.map(|r| r + 1))
+    }
+
+    async fn top(&self, board: &str, limit: Option<usize>) -> Result<Vec<(UserId, usize)>, Error> {
+        let stop = match limit {
+            Some(0) => return Ok(vec![]),
+            Some(n) => n as isize - 1,
+            None => -1,
+        };
+        let top: Vec<(u64, usize)> = self
+            .con
+            .clone()
+            .zrevrange_withscores(board, 0, stop)
+            .await?;
+        Ok(top.into_iter().map(|(id, rep)| (UserId(id), rep)).collect())
+    }
+
+    async fn at_least(&self, board: &str, threshold: usize) -> Result<Vec<UserId>, Error> {
+        let users: Vec<u64> = self
+            .con
+            .clone()
+            .zrangebyscore(board, threshold, "+inf")
+            .await?;
+        Ok(users.into_iter().map(UserId).collect())
+    }
+
+    async fn replace_boards(&self, boards: Boards) -> Result<(), Error> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (board, scores) in boards {
+            pipe.del(&board).ignore();
+            let items: Vec<(usize, u64)> = scores
+                .into_iter()
+                .map(|(user, points)| (points, user.0))
+                .collect();
+            if !items.is_empty() {
+                pipe.zadd_multiple(&board, &items).ignore();
+            }
+        }
+        Ok(pipe.query_async(&mut self.con.clone()).await?)
+    }
+
+    async fn copy_board(&self, from: &str, to: &str) -> Result<(), Error> {
+        let _: usize = self.con.clone().zunionstore(to, &[from]).await?;
+        Ok(())
+    }
+
+    async fn legacy_reputation(&self) -> Result<Vec<(String, usize)>, Error> {
+        Ok(self
+            .con
+            .clone()
+            .zrange_withscores(LEGACY_REPUTATION, 0, -1)
+            .await?)
+    }
+
+    async fn remove_legacy_reputation(&self, name: &str) -> Result<(), Error> {
+        let _: usize = self.con.clone().zrem(LEGACY_REPUTATION, name).await?;
+        Ok(())
+    }
+
+    async fn legacy_preference(&self, name: &str, username: &str) -> Result<Option<String>, Error> {
+        // preferences used to be keyed by username, in the same hash as the ones keyed by ID now
+        Ok(self.con.clone().hget(name, username).await?)
+    }
+
+    async fn remove_legacy_preference(&self, name: &str, username: &str) -> Result<(), Error> {
+        let _: usize = self.con.clone().hdel(name, username).await?;
+        Ok(())
+    }
+
+    async fn push_ledger(&self, guild: GuildId, timestamp: i64, entry: &str) -> Result<(), Error> {
+        let mut con = self.con.clone();
+        let length: usize = con.rpush(ledger_key(guild), entry).await?;
+        let _: usize = con
+            .zadd(ledger_times_key(guild), length - 1, timestamp)
+            .await?;
+        Ok(())
+    }
+
+    async fn ledger(&self, guild: GuildId) -> Result<Vec<String>, Error> {
+        Ok(self.con.clone().lrange(ledger_key(guild), 0, -1).await?)
+    }
+
+    async fn recent_ledger(&self, guild: GuildId, since: i64) -> Result<Vec<String>, Error> {
+        let mut con = self.con.clone();
+        let mut positions: Vec<isize> = con
+            .zrangebyscore(ledger_times_key(guild), since, "+inf")
+            .await?;
+        if positions.is_empty() {
+            return Ok(vec![]);
+        }
+        positions.sort_unstable();
+        let mut pipe = redis::pipe();
+        for position in positions {
+            pipe.lindex(ledger_key(guild), position);
+        }
+        Ok(pipe.query_async(&mut con).await?)
+    }
+
+    async fn set_ledger_entry(
+        &self,
+        guild: GuildId,
+        index: usize,
+        entry: &str,
+    ) -> Result<(), Error> {
+        let _: () = self
+            .con
+            .clone()
+            .lset(ledger_key(guild), index as isize, entry)
+            .await?;
+        Ok(())
+    }
+
+    async fn claim_thanks(&self, guild: GuildId, claims: &[String]) -> Result<bool, Error> {
+        Ok(redis::Script::new(CLAIM_SCRIPT)
+            .key(claims_key(guild))
+            .arg(claims)
+            .invoke_async(&mut self.con.clone())
+            .await?)
+    }
+
+    async fn release_thanks(&self, guild: GuildId, claims: &[String]) -> Result<(), Error> {
+        let _: usize = self.con.clone().hdel(claims_key(guild), claims).await?;
+        Ok(())
+    }
+
+    async fn guild_setting(&self, guild: GuildId, name: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .con
+            .clone()
+            .get(format!("{}:{}", name, guild.0))
+            .await?)
+    }
+
+    async fn set_guild_setting(
+        &self,
+        guild: GuildId,
+        name: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let _: () = self
+            .con
+            .clone()
+            .set(format!("{}:{}", name, guild.0), value)
+            .await?;
+        Ok(())
+    }
+
+    async fn channel_subjects(&self, guild: GuildId) -> Result<HashMap<u64, String>, Error> {
+        Ok(self.con.clone().hgetall(subjects_key(guild)).await?)
+    }
+
+    async fn set_channel_subject(
+        &self,
+        guild: GuildId,
+        channel: ChannelId,
+        subject: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut con = self.con.clone();
+        let _: usize = match subject {
+            Some(subject) => con.hset(subjects_key(guild), channel.0, subject).await?,
+            None => con.hdel(subjects_key(guild), channel.0).await?,
+        };
+        Ok(())
+    }
+
+    async fn on_cooldown(&self, user: UserId) -> Result<bool, Error> {
+        Ok(self.con.clone().exists(cooldown_key(user)).await?)
+    }
+
+    async fn start_cooldown(&self, user: UserId, seconds: usize) -> Result<(), Error> {
+        let _: () = self
+            .con
+            .clone()
+            .set_ex(cooldown_key(user), "", seconds)
+            .await?;
+        Ok(())
+    }
+
+    async fn preference(&self, name: &str, user: UserId) -> Result<Option<String>, Error> {
+        Ok(self.con.clone().hget(name, user.0).await?)
+    }
+
+    async fn set_preference(&self, name: &str, user: UserId, value: &str) -> Result<(), Error> {
+        let _: usize = self.con.clone().hset(name, user.0, value).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,602 @@
+//! Storage in an embedded SQLite database, for deployments that don't want to run a Redis server.
+//!
+//! SQLite calls block, so they are run on Tokio's blocking thread pool, one at a time.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::utils::Error;
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+
+use super::{Boards, Storage};
+
+/// The tables, created when a database is first opened.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scores (
+    board TEXT NOT NULL,
+    user INTEGER NOT NULL,
+    points INTEGER NOT NULL,
+    PRIMARY KEY (board, user)
+);
+CREATE TABLE IF NOT EXISTS ledger (
+    guild INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    entry TEXT NOT NULL,
+    PRIMARY KEY (guild, position)
+);
+CREATE INDEX IF NOT EXISTS ledger_by_time ON ledger (guild, timestamp);
+CREATE TABLE IF NOT EXISTS thank_claims (
+    guild INTEGER NOT NULL,
+    claim TEXT NOT NULL,
+    PRIMARY KEY (guild, claim)
+);
+CREATE TABLE IF NOT EXISTS guild_settings (
+    guild INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (guild, name)
+);
+CREATE TABLE IF NOT EXISTS channel_subjects (
+    guild INTEGER NOT NULL,
+    channel INTEGER NOT NULL,
+    subject TEXT NOT NULL,
+    PRIMARY KEY (guild, channel)
+);
+CREATE TABLE IF NOT EXISTS cooldowns (
+    user INTEGER PRIMARY KEY,
+    until INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS preferences (
+    name TEXT NOT NULL,
+    user INTEGER NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (name, user)
+);
+";
+
+/// Storage in a SQLite database file.
+pub(crate) struct SqliteStorage {
+    con: Arc<Mutex<Connection>>,
+}
+
+/// The current time, as a Unix timestamp.
+fn now() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+impl SqliteStorage {
+    /// Opens the database at the given path, creating it if it doesn't exist. The path `:memory:`
+    /// gives a fresh database that only lasts as long as the bot runs.
+    pub(crate) fn open(path: &str) -> Result<Self, Error> {
+        let con = Connection::open(path)?;
+        con.execute_batch(SCHEMA)?;
+        Ok(Self {
+            con: Arc::new(Mutex::new(con)),
+        })
+    }
+
+    /// Runs a function on the database connection without blocking the async runtime.
+    async fn run<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let con = self.con.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            // a panic while holding the lock can't leave a transaction half-applied, so carry on
+            let mut con = con.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut con)
+        })
+        .await?;
+        Ok(res?)
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn increment(
+        &self,
+        boards: &[String],
+        user: UserId,
+        points: usize,
+    ) -> Result<Vec<usize>, Error> {
+        let boards = boards.to_vec();
+        self.run(move |con| {
+            let tx = con.transaction()?;
+            let mut new_points = vec![];
+            for board in boards {
+                new_points.push(tx.query_row(
+                    "INSERT INTO scores (board, user, points) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (board, user) DO UPDATE SET points = points + excluded.points
+                     RETURNING points",
+                    params![board, user.0, points],
+                    |row| row.get(0),
+                )?);
+            }
+            tx.commit()?;
+            Ok(new_points)
+        })
+        .await
+    }
+
+    async fn decrement(&self, boards: &[String], user: UserId, points: usize) -> Result<(), Error> {
+        let boards = boards.to_vec();
+        self.run(move |con| {
+            let tx = con.transaction()?;
+            for board in boards {
+                tx.execute(
+                    "UPDATE scores SET points = points - ?3 WHERE board = ?1 AND user = ?2",
+                    params![board, user.0, points],
+                )?;
+                tx.execute(
+                    "DELETE FROM scores WHERE board = ?1 AND user = ?2 AND points <= 0",
+                    params![board, user.0],
+                )?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn score(&self, board: &str, user: UserId) -> Result<Option<usize>, Error> {
+        let board = board.to_string();
+        self.run(move |con| {
+            con.query_row(
+                "SELECT points FROM scores WHERE board = ?1 AND user = ?2",
+                params![board, user.0],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn rank(&self, board: &str, user: UserId) -> Result<Option<usize>, Error> {
+        let board = board.to_string();
+        self.run(move |con| {
+            con.query_row(
+                "SELECT 1 + (SELECT COUNT(*) FROM scores AS other
+                             WHERE other.board = scores.board AND other.points > scores.points)
+                 FROM scores WHERE board = ?1 AND user = ?2",
+                params![board, user.0],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn top(&self, board: &str, limit: Option<usize>) -> Result<Vec<(UserId, usize)>, Error> {
+        let board = board.to_string();
+        // a negative limit means no limit
+        let limit = limit.map_or(-1, |n| n as i64);
+        self.run(move |con| {
+            let mut stmt = con.prepare(
+                "SELECT user, points FROM scores WHERE board = ?1
+                 ORDER BY points DESC, user DESC LIMIT ?2",
+            )?;
+            let rows = stmt.query_map(params![board, limit], |row| {
+                Ok((UserId(row.get(0)?), row.get(1)?))
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn at_least(&self, board: &str, threshold: usize) -> Result<Vec<UserId>, Error> {
+        let board = board.to_string();
+        self.run(move |con| {
+            let mut stmt =
+                con.prepare("SELECT user FROM scores WHERE board = ?1 AND points >= ?2")?;
+            let rows = stmt.query_map(params![board, threshold], |row| Ok(UserId(row.get(0)?)))?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn replace_boards(&self, boards: Boards) -> Result<(), Error> {
+        self.run(move |con| {
+            let tx = con.transaction()?;
+            for (board, scores) in boards {
+                tx.execute("DELETE FROM scores WHERE board = ?1", params![board])?;
+                for (user, points) in scores {
+                    tx.execute(
+                        "INSERT INTO scores (board, user, points) VALUES (?1, ?2, ?3)",
+                        params![board, user.0, points],
+                    )?;
+                }
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn copy_board(&self, from: &str, to: &str) -> Result<(), Error> {
+        let (from, to) = (from.to_string(), to.to_string());
+        self.run(move |con| {
+            let tx = con.transaction()?;
+            tx.execute("DELETE FROM scores WHERE board = ?1", params![to])?;
+            tx.execute(
+                "INSERT INTO scores (board, user, points)
+                 SELECT ?2, user, points FROM scores WHERE board = ?1",
+                params![from, to],
+            )?;
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn push_ledger(&self, guild: GuildId, timestamp: i64, entry: &str) -> Result<(), Error> {
+        let entry = entry.to_string();
+        self.run(move |con| {
+            con.execute(
+                "INSERT INTO ledger (guild, position, timestamp, entry)
+                 SELECT ?1, COALESCE(MAX(position) + 1, 0), ?2, ?3 FROM ledger WHERE guild = ?1",
+                params![guild.0, timestamp, entry],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn ledger(&self, guild: GuildId) -> Result<Vec<String>, Error> {
+        self.run(move |con| {
+            let mut stmt =
+                con.prepare("SELECT entry FROM ledger WHERE guild = ?1 ORDER BY position")?;
+            let rows = stmt.query_map(params![guild.0], |row| row.get(0))?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn recent_ledger(&self, guild: GuildId, since: i64) -> Result<Vec<String>, Error> {
+        self.run(move |con| {
+            let mut stmt = con.prepare(
+                "SELECT entry FROM ledger WHERE guild = ?1 AND timestamp >= ?2 ORDER BY position",
+            )?;
+            let rows = stmt.query_map(params![guild.0, since], |row| row.get(0))?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn set_ledger_entry(
+        &self,
+        guild: GuildId,
+        index: usize,
+        entry: &str,
+    ) -> Result<(), Error> {
+        let entry = entry.to_string();
+        self.run(move |con| {
+            // positions are handed out consecutively from 0, so they match indices
+            con.execute(
+                "UPDATE ledger SET entry = ?3 WHERE guild = ?1 AND position = ?2",
+                params![guild.0, index, entry],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn claim_thanks(&self, guild: GuildId, claims: &[String]) -> Result<bool, Error> {
+        let claims = claims.to_vec();
+        self.run(move |con| {
+            let tx = con.transaction()?;
+            for claim in &claims {
+                let taken: bool = tx.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM thank_claims WHERE guild = ?1 AND claim = ?2)",
+                    params![guild.0, claim],
+                    |row| row.get(0),
+                )?;
+                if taken {
+                    return Ok(false);
+                }
+            }
+            for claim in &claims {
+                tx.execute(
+                    "INSERT INTO thank_claims (guild, claim) VALUES (?1, ?2)",
+                    params![guild.0, claim],
+                )?;
+            }
+            tx.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn release_thanks(&self, guild: GuildId, claims: &[String]) -> Result<(), Error> {
+        let claims = claims.to_vec();
+        self.run(move |con| {
+            let tx = con.transaction()?;
+            for claim in &claims {
+                tx.execute(
+                    "DELETE FROM thank_claims WHERE guild = ?1 AND claim = ?2",
+                    params![guild.0, claim],
+                )?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn guild_setting(&self, guild: GuildId, name: &str) -> Result<Option<String>, Error> {
+        let name = name.to_string();
+        self.run(move |con| {
+            con.query_row(
+                "SELECT value FROM guild_settings WHERE guild = ?1 AND name = ?2",
+                params![guild.0, name],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn set_guild_setting(
+        &self,
+        guild: GuildId,
+        name: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let (name, value) = (name.to_string(), value.to_string());
+        self.run(move |con| {
+            con.execute(
+                "INSERT OR REPLACE INTO guild_settings (guild, name, value) VALUES (?1, ?2, ?3)",
+                params![guild.0, name, value],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn channel_subjects(&self, guild: GuildId) -> Result<HashMap<u64, String>, Error> {
+        self.run(move |con| {
+            let mut stmt =
+                con.prepare("SELECT channel, subject FROM channel_subjects WHERE guild = ?1")?;
+            let rows = stmt.query_map(params![guild.0], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn set_channel_subject(
+        &self,
+        guild: GuildId,
+        channel: ChannelId,
+        subject: Option<&str>,
+    ) -> Result<(), Error> {
+        let subject = subject.map(str::to_string);
+        self.run(move |con| {
+            match subject {
+                Some(subject) => con.execute(
+                    "INSERT OR REPLACE INTO channel_subjects (guild, channel, subject)
+                     VALUES (?1, ?2, ?3)",
+                    params![guild.0, channel.0, subject],
+                ),
+                None => con.execute(
+                    "DELETE FROM channel_subjects WHERE guild = ?1 AND channel = ?2",
+                    params![guild.0, channel.0],
+                ),
+            }
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn on_cooldown(&self, user: UserId) -> Result<bool, Error> {
+        self.run(move |con| {
+            con.query_row(
+                "SELECT EXISTS (SELECT 1 FROM cooldowns WHERE user = ?1 AND until > ?2)",
+                params![user.0, now()],
+                |row| row.get(0),
+            )
+        })
+        .await
+    }
+
+    async fn start_cooldown(&self, user: UserId, seconds: usize) -> Result<(), Error> {
+        self.run(move |con| {
+            con.execute(
+                "INSERT OR REPLACE INTO cooldowns (user, until) VALUES (?1, ?2)",
+                params![user.0, now() + seconds as i64],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn preference(&self, name: &str, user: UserId) -> Result<Option<String>, Error> {
+        let name = name.to_string();
+        self.run(move |con| {
+            con.query_row(
+                "SELECT value FROM preferences WHERE name = ?1 AND user = ?2",
+                params![name, user.0],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn set_preference(&self, name: &str, user: UserId, value: &str) -> Result<(), Error> {
+        let (name, value) = (name.to_string(), value.to_string());
+        self.run(move |con| {
+            con.execute(
+                "INSERT OR REPLACE INTO preferences (name, user, value) VALUES (?1, ?2, ?3)",
+                params![name, user.0, value],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage() -> SqliteStorage {
+        SqliteStorage::open(":memory:").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_leaderboards() {
+        let storage = storage();
+        let boards = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(
+            storage.increment(&boards, UserId(1), 1).await.unwrap(),
+            vec![1, 1]
+        );
+        assert_eq!(
+            storage.increment(&boards[..1], UserId(1), 2).await.unwrap(),
+            vec![3]
+        );
+        storage.increment(&boards, UserId(2), 2).await.unwrap();
+
+        assert_eq!(storage.score("a", UserId(1)).await.unwrap(), Some(3));
+        assert_eq!(storage.score("a", UserId(3)).await.unwrap(), None);
+        assert_eq!(storage.rank("a", UserId(2)).await.unwrap(), Some(2));
+        assert_eq!(storage.rank("b", UserId(2)).await.unwrap(), Some(1));
+        assert_eq!(
+            storage.top("a", None).await.unwrap(),
+            vec![(UserId(1), 3), (UserId(2), 2)]
+        );
+        assert_eq!(
+            storage.top("a", Some(1)).await.unwrap(),
+            vec![(UserId(1), 3)]
+        );
+        assert_eq!(storage.at_least("a", 3).await.unwrap(), vec![UserId(1)]);
+
+        storage.decrement(&boards, UserId(2), 1).await.unwrap();
+        assert_eq!(storage.score("a", UserId(2)).await.unwrap(), Some(1));
+        storage.decrement(&boards, UserId(2), 1).await.unwrap();
+        assert_eq!(storage.score("b", UserId(2)).await.unwrap(), None);
+        storage.increment(&boards, UserId(2), 2).await.unwrap();
+
+        storage.copy_board("a", "c").await.unwrap();
+        let mut boards = Boards::new();
+        boards.insert("a".to_string(), HashMap::from([(UserId(4), 7)]));
+        storage.replace_boards(boards).await.unwrap();
+        assert_eq!(storage.top("a", None).await.unwrap(), vec![(UserId(4), 7)]);
+        assert_eq!(storage.score("c", UserId(1)).await.unwrap(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_ledger() {
+        let storage = storage();
+        storage.push_ledger(GuildId(1), 10, "first").await.unwrap();
+        storage.push_ledger(GuildId(1), 20, "second").await.unwrap();
+        storage.push_ledger(GuildId(2), 30, "other").await.unwrap();
+        storage
+            .set_ledger_entry(GuildId(1), 1, "changed")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.ledger(GuildId(1)).await.unwrap(),
+            vec!["first", "changed"]
+        );
+        assert_eq!(storage.ledger(GuildId(2)).await.unwrap(), vec!["other"]);
+        assert_eq!(
+            storage.recent_ledger(GuildId(1), 15).await.unwrap(),
+            vec!["changed"]
+        );
+        assert!(storage
+            .recent_ledger(GuildId(2), 31)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_claims() {
+        let storage = storage();
+        let claims = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<String>>();
+        assert!(storage
+            .claim_thanks(GuildId(1), &claims(&["a", "b"]))
+            .await
+            .unwrap());
+        // nothing is claimed if any key is taken
+        assert!(!storage
+            .claim_thanks(GuildId(1), &claims(&["c", "b"]))
+            .await
+            .unwrap());
+        assert!(storage
+            .claim_thanks(GuildId(1), &claims(&["c"]))
+            .await
+            .unwrap());
+        assert!(storage
+            .claim_thanks(GuildId(2), &claims(&["a"]))
+            .await
+            .unwrap());
+        storage
+            .release_thanks(GuildId(1), &claims(&["a", "b"]))
+            .await
+            .unwrap();
+        assert!(storage
+            .claim_thanks(GuildId(1), &claims(&["b"]))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_settings_and_preferences() {
+        let storage = storage();
+        assert_eq!(storage.guild_setting(GuildId(1), "x").await.unwrap(), None);
+        storage
+            .set_guild_setting(GuildId(1), "x", "1")
+            .await
+            .unwrap();
+        storage
+            .set_guild_setting(GuildId(1), "x", "2")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.guild_setting(GuildId(1), "x").await.unwrap(),
+            Some("2".to_string())
+        );
+
+        storage
+            .set_channel_subject(GuildId(1), ChannelId(5), Some("math"))
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.channel_subjects(GuildId(1)).await.unwrap(),
+            HashMap::from([(5, "math".to_string())])
+        );
+        storage
+            .set_channel_subject(GuildId(1), ChannelId(5), None)
+            .await
+            .unwrap();
+        assert!(storage
+            .channel_subjects(GuildId(1))
+            .await
+            .unwrap()
+            .is_empty());
+
+        storage
+            .set_preference("math_markup", UserId(1), "latex")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.preference("math_markup", UserId(1)).await.unwrap(),
+            Some("latex".to_string())
+        );
+        assert_eq!(
+            storage.preference("math_markup", UserId(2)).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cooldowns() {
+        let storage = storage();
+        assert!(!storage.on_cooldown(UserId(1)).await.unwrap());
+        storage.start_cooldown(UserId(1), 60).await.unwrap();
+        assert!(storage.on_cooldown(UserId(1)).await.unwrap());
+        storage.start_cooldown(UserId(2), 0).await.unwrap();
+        assert!(!storage.on_cooldown(UserId(2)).await.unwrap());
+    }
+}
@@ -2,6 +2,7 @@
 
 pub(crate) mod available_langs;
 pub(crate) mod detection;
+pub(crate) mod thank_phrases;
 pub(crate) mod translation;
 pub(crate) use translation::translate;
 pub(crate) use translation::translate_content;
@@ -0,0 +1,395 @@
+//! Ways of saying thanks in each of the languages Nano can translate, used to recognize thanks that
+//! aren't in English.
+//!
+//! A thank phrase has to be near the start or end of a message to count, so that a passing "merci" in
+//! the middle of a paragraph doesn't give anyone points, and can't mean "because of", as in "thanks to
+//! the rain, the match was cancelled".
+
+use super::available_langs::AVAILABLE_LANGS;
+use super::detection::detect_language;
+use lingua::Language;
+
+/// How many words from the start or end of a message a thank phrase may be.
+const EDGE_WORDS: usize = 3;
+
+/// How many characters from the start or end of a run of Chinese or Japanese text a thank phrase may
+/// be, as those languages don't separate words with spaces.
+const EDGE_CHARS: usize = 3;
+
+/// Words that turn a following thank phrase into a refusal ("no thanks").
+const NEGATIONS: [&str; 14] = [
+    "no", "nein", "non", "nee", "nej", "ei", "não", "nao", "нет", "не", "ne", "nie", "nu", "όχι",
+];
+
+/// Words that, following a thank phrase, make it mean "because of" rather than thanks, as in "thanks
+/// to the rain" or "grazie alla pioggia". "thanks to you" still counts.
+const CAUSAL_CONTINUATIONS: [&[&str]; 20] = [
+    &["to", "the"],
+    &["to", "a"],
+    &["to", "an"],
+    &["to", "this"],
+    &["to", "that"],
+    &["to", "these"],
+    &["to", "those"],
+    &["to", "its"],
+    &["to", "his"],
+    &["to", "their"],
+    // Spanish
+    &["a", "la"],
+    &["a", "los"],
+    &["a", "las"],
+    &["al"],
+    // Italian
+    &["alla"],
+    &["allo"],
+    &["ai"],
+    &["agli"],
+    &["alle"],
+    &["all"],
+];
+
+/// Thank phrases for each language, in lowercase, with words separated by single spaces.
+pub(crate) const THANK_PHRASES: [(Language, &[&str]); 25] = [
+    (Language::Bulgarian, &["благодаря", "мерси", "благодарско"]),
+    (
+        Language::Czech,
+        &[
+            "děkuji", "děkuju", "dekuji", "dekuju", "díky", "diky", "dík",
+        ],
+    ),
+    (Language::Danish, &["mange tak", "tusind tak", "tak for"]),
+    (
+        Language::German,
+        &["danke", "dankeschön", "vielen dank", "dank dir"],
+    ),
+    (
+        Language::Greek,
+        &["ευχαριστώ", "ευχαριστω", "ευχαριστούμε", "ευχαριστουμε"],
+    ),
+    (
+        Language::English,
+        &["thanks", "thank", "thanx", "thx", "ty", "tysm", "tyvm"],
+    ),
+    (Language::Spanish, &["gracias", "muchas gracias"]),
+    (Language::Estonian, &["aitäh", "aitah", "tänan", "tanan"]),
+    (Language::Finnish, &["kiitos", "kiitti", "kiitoksia"]),
+    (Language::French, &["merci", "merci beaucoup"]),
+    (
+        Language::Hungarian,
+        &["köszönöm", "koszonom", "köszi", "koszi", "kösz"],
+    ),
+    (Language::Indonesian, &["terima kasih", "makasih", "makasi"]),
+    (Language::Italian, &["grazie", "grazie mille"]),
+    (
+        Language::Japanese,
+        &["ありがとう", "ありがと", "サンキュー", "感謝します"],
+    ),
+    (Language::Lithuanian, &["ačiū", "aciu", "dėkoju", "dekoju"]),
+    (Language::Latvian, &["paldies"]),
+    (
+        Language::Dutch,
+        &["dank je", "dank u", "dankjewel", "dankuwel", "bedankt"],
+    ),
+    (
+        Language::Polish,
+        &["dziękuję", "dziekuje", "dzięki", "dzieki"],
+    ),
+    (Language::Portuguese, &["obrigado", "obrigada", "valeu"]),
+    (
+        Language::Romanian,
+        &["mulțumesc", "mulţumesc", "multumesc", "mersi"],
+    ),
+    (Language::Russian, &["спасибо", "спс", "благодарю"]),
+    (Language::Slovak, &["ďakujem", "dakujem", "vďaka", "vdaka"]),
+    (Language::Slovene, &["hvala"]),
+    (Language::Swedish, &["tack", "tack så mycket"]),
+    (
+        Language::Chinese,
+        &["谢谢", "謝謝", "多谢", "多謝", "感谢", "感謝"],
+    ),
+];
+
+/// Whether a character belongs to a script written without spaces between words.
+fn is_unspaced(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' // hiragana and katakana
+        | '\u{3400}'..='\u{4dbf}' // CJK extension A
+        | '\u{4e00}'..='\u{9fff}' // CJK unified ideographs
+    )
+}
+
+/// Splits a message into lowercase words, dropping numbers (which is what's left of mentions).
+fn words(msg: &str) -> Vec<String> {
+    msg.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty() && !w.chars().all(|c| c.is_ascii_digit()))
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// Checks whether a thank phrase appears close enough to the start or end of a message, not as part
+/// of a refusal or meaning "because of".
+fn has_phrase(words: &[String], phrase: &str) -> bool {
+    if phrase.chars().any(is_unspaced) {
+        return has_unspaced_phrase(words, phrase);
+    }
+
+    let phrase: Vec<&str> = phrase.split(' ').collect();
+    if phrase.len() > words.len() {
+        return false;
+    }
+    (0..=words.len() - phrase.len()).any(|start| {
+        let end = start + phrase.len();
+        let near_edge = start < EDGE_WORDS || end + EDGE_WORDS > words.len();
+        let negated = start > 0 && NEGATIONS.contains(&words[start - 1].as_str());
+        let causal = CAUSAL_CONTINUATIONS.iter().any(|continuation| {
+            let rest = &words[end..];
+            rest.len() >= continuation.len()
+                && rest.iter().zip(continuation.iter()).all(|(w, c)| w == c)
+        });
+        near_edge
+            && !negated
+            && !causal
+            && words[start..end].iter().zip(&phrase).all(|(w, p)| w == p)
+    })
+}
+
+/// Checks whether a phrase in a language without spaces appears close enough to the start or end of
+/// a run of text in an edge word of the message.
+fn has_unspaced_phrase(words: &[String], phrase: &str) -> bool {
+    let phrase_len = phrase.chars().count();
+    words.iter().enumerate().any(|(i, word)| {
+        if i >= EDGE_WORDS && i + EDGE_WORDS < words.len() {
+            return false;
+        }
+        word.match_indices(phrase).any(|(byte_start, _)| {
+            let start = word[..byte_start].chars().count();
+            let end = start + phrase_len;
+            start < EDGE_CHARS || end + EDGE_CHARS > word.chars().count()
+        })
+    })
+}
+
+/// The thank phrases of a language.
+fn phrases_of(lang: Language) -> &'static [&'static str] {
+    THANK_PHRASES
+        .iter()
+        .find(|(l, _)| *l == lang)
+        .map(|(_, phrases)| *phrases)
+        .unwrap_or(&[])
+}
+
+/// Checks whether a message is thanking someone in the given language or in English, which is
+/// understood everywhere. If no language is given, every language is checked.
+pub(crate) fn is_thanks_in(msg: &str, lang: Option<Language>) -> bool {
+    let words = words(msg);
+    let langs: Vec<Language> = match lang {
+        Some(lang) => vec![Language::English, lang],
+        None => AVAILABLE_LANGS.to_vec(),
+    };
+    langs
+        .into_iter()
+        .flat_map(phrases_of)
+        .any(|phrase| has_phrase(&words, phrase))
+}
+
+/// Checks whether a message is thanking someone, in any of the languages Nano knows. Short messages
+/// (as most thanks are) are checked against every language, as their language can't be detected
+/// reliably.
+pub(crate) fn is_thanks(msg: &str) -> bool {
+    is_thanks_in(msg, detect_language(msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that every message is recognized as thanks in the given language, both when the
+    /// language is known and when it isn't.
+    fn assert_thanks(lang: Language, msgs: &[&str]) {
+        for msg in msgs {
+            assert!(is_thanks_in(msg, Some(lang)), "{:?}: {}", lang, msg);
+            assert!(is_thanks_in(msg, None), "{:?} (undetected): {}", lang, msg);
+        }
+    }
+
+    #[test]
+    fn test_every_language_has_phrases() {
+        for lang in AVAILABLE_LANGS {
+            assert!(!phrases_of(lang).is_empty(), "{:?}", lang);
+        }
+    }
+
+    #[test]
+    fn test_bulgarian() {
+        assert_thanks(
+            Language::Bulgarian,
+            &["<@123> благодаря!", "Много благодаря"],
+        );
+    }
+
+    #[test]
+    fn test_czech() {
+        assert_thanks(Language::Czech, &["Děkuji <@123>", "díky moc!"]);
+    }
+
+    #[test]
+    fn test_danish() {
+        assert_thanks(Language::Danish, &["Mange tak <@123>", "tak for hjælpen!"]);
+    }
+
+    #[test]
+    fn test_german() {
+        assert_thanks(Language::German, &["Danke <@123>!", "<@123> vielen Dank!!"]);
+    }
+
+    #[test]
+    fn test_greek() {
+        assert_thanks(Language::Greek, &["Ευχαριστώ <@123>", "σε ευχαριστώ πολύ"]);
+    }
+
+    #[test]
+    fn test_english() {
+        assert_thanks(
+            Language::English,
+            &[
+                "thanks <@123>",
+                "ty!",
+                "<@123> thank you so much",
+                "thx",
+                "thanks to you <@123> it works now",
+                "<@123> thanks for the help!",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_spanish() {
+        assert_thanks(
+            Language::Spanish,
+            &["¡Gracias <@123>!", "muchas gracias amigo"],
+        );
+    }
+
+    #[test]
+    fn test_estonian() {
+        assert_thanks(Language::Estonian, &["Aitäh <@123>", "tänan väga"]);
+    }
+
+    #[test]
+    fn test_finnish() {
+        assert_thanks(Language::Finnish, &["Kiitos <@123>!", "kiitti paljon"]);
+    }
+
+    #[test]
+    fn test_french() {
+        assert_thanks(Language::French, &["Merci <@123> !", "merci beaucoup"]);
+    }
+
+    #[test]
+    fn test_hungarian() {
+        assert_thanks(Language::Hungarian, &["Köszönöm <@123>", "köszi!"]);
+    }
+
+    #[test]
+    fn test_indonesian() {
+        assert_thanks(Language::Indonesian, &["Terima kasih <@123>", "makasih ya"]);
+    }
+
+    #[test]
+    fn test_italian() {
+        assert_thanks(Language::Italian, &["Grazie <@123>!", "grazie mille"]);
+    }
+
+    #[test]
+    fn test_japanese() {
+        assert_thanks(
+            Language::Japanese,
+            &["<@123> ありがとうございます！", "本当にありがとう"],
+        );
+    }
+
+    #[test]
+    fn test_lithuanian() {
+        assert_thanks(Language::Lithuanian, &["Ačiū <@123>", "labai ačiū"]);
+    }
+
+    #[test]
+    fn test_latvian() {
+        assert_thanks(Language::Latvian, &["Paldies <@123>!", "liels paldies"]);
+    }
+
+    #[test]
+    fn test_dutch() {
+        assert_thanks(Language::Dutch, &["Dank je <@123>", "bedankt!"]);
+    }
+
+    #[test]
+    fn test_polish() {
+        assert_thanks(Language::Polish, &["Dziękuję <@123>", "dzięki!"]);
+    }
+
+    #[test]
+    fn test_portuguese() {
+        assert_thanks(
+            Language::Portuguese,
+            &["Obrigado <@123>!", "muito obrigada"],
+        );
+    }
+
+    #[test]
+    fn test_romanian() {
+        assert_thanks(Language::Romanian, &["Mulțumesc <@123>", "mersi mult"]);
+    }
+
+    #[test]
+    fn test_russian() {
+        assert_thanks(Language::Russian, &["Спасибо <@123>!", "большое спасибо"]);
+    }
+
+    #[test]
+    fn test_slovak() {
+        assert_thanks(Language::Slovak, &["Ďakujem <@123>", "vďaka!"]);
+    }
+
+    #[test]
+    fn test_slovene() {
+        assert_thanks(Language::Slovene, &["Hvala <@123>!", "hvala lepa"]);
+    }
+
+    #[test]
+    fn test_swedish() {
+        assert_thanks(Language::Swedish, &["Tack <@123>!", "tack så mycket"]);
+    }
+
+    #[test]
+    fn test_chinese() {
+        assert_thanks(Language::Chinese, &["谢谢 <@123>", "非常感谢！"]);
+    }
+
+    #[test]
+    fn test_false_positives() {
+        let not_thanks = [
+            // thank phrases buried in the middle of a longer message
+            "<@123> I think the answer depends on whether thanks to the chain rule we can differentiate it at all",
+            "<@123> Ich habe gestern gelesen dass danke in vielen Sprachen anders klingt und so weiter",
+            "<@123> 我觉得这个问题谢谢你的解释但是还有一个地方不太明白的问题",
+            // words that merely contain a thank phrase
+            "<@123> that was merciless",
+            "<@123> tackle the second part first",
+            "<@123> look at the kiitospäivä article",
+            // "because of", even at the start of a message
+            "<@123> thanks to the rain, the match was cancelled",
+            "Thanks to a typo <@123> the build broke",
+            "<@123> gracias a la lluvia no jugamos",
+            "<@123> grazie alla pioggia la partita è stata annullata",
+            // refusals
+            "<@123> no thanks",
+            "<@123> nein danke",
+            // "tak" is "yes" in Polish
+            "<@123> tak, to jest poprawne",
+        ];
+        for msg in not_thanks {
+            assert!(!is_thanks_in(msg, None), "{}", msg);
+        }
+    }
+}
@@ -10,9 +10,8 @@ use deepl_openapi::{
 };
 use lingua::Language;
 
-use std::env;
-
 use crate::{
+    config::config,
     translate::available_langs::{lingua_to_deepl_source, lingua_to_deepl_target},
     utils::{Context, Error},
 };
@@ -25,13 +24,17 @@ pub(crate) async fn translate_content(
     target: Language,
 ) -> Result<(String, String)> {
     let client = reqwest::Client::new();
+    let settings = &config().translation;
 
     // setting this to an invalid key will trigger a request error which saves me having to make a
     // custom error type here
-    let api_key = env::var("DEEPL_KEY").unwrap_or_else(|_| "bad".to_string());
+    let api_key = match settings.deepl_key.as_str() {
+        "" => "bad".to_string(),
+        key => key.to_string(),
+    };
 
     let config = Configuration {
-        base_path: "https://api-free.deepl.com/v2".to_owned(),
+        base_path: settings.deepl_url.clone(),
         user_agent: Some("OpenAPI-Generator/2.7.0/rust".to_owned()),
         client,
         basic_auth: None,
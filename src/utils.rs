@@ -1,7 +1,14 @@
 //! Utilities.
 use std::fmt::Debug;
 
-pub(crate) type Data = ();
+use crate::storage::Storage;
+
+/// State shared by every command and event handler.
+pub(crate) struct Data {
+    /// Where reputation, cooldowns and preferences are kept.
+    pub storage: Box<dyn Storage>,
+}
+
 pub(crate) type Error = Box<dyn std::error::Error + Send + Sync>;
 pub(crate) type Context<'a> = poise::Context<'a, Data, Error>;
 